@@ -3,7 +3,7 @@
 set -eux
 
 cargo check --quiet --workspace --all-targets
-cargo check --quiet --workspace --all-features --lib --target wasm32-unknown-unknown
+cargo check --quiet --workspace --lib --target wasm32-unknown-unknown # the opus feature needs the native libopus
 cargo fmt --all -- --check
 cargo clippy --quiet --workspace --all-targets --all-features --  -D warnings -W clippy::all
 cargo test --quiet --workspace --all-targets --all-features
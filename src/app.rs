@@ -1,34 +1,40 @@
 use std::cmp::PartialEq;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashSet, VecDeque};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicI8, AtomicUsize, Ordering};
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 
 use eframe::epaint::Color32;
+use egui::load::Bytes;
 use egui::scroll_area::ScrollBarVisibility;
 use egui::TextStyle::Body;
 use egui::{
-    Align, CentralPanel, Context, FontFamily, FontId, Layout, ProgressBar, RichText, ScrollArea,
-    TextStyle, Ui, Vec2,
+    Align, CentralPanel, Context, FontFamily, FontId, Layout, ProgressBar, Rect, RichText,
+    ScrollArea, TextStyle, Ui, UiBuilder, Vec2,
 };
 use walkdir::WalkDir;
 
 use crate::audio_player::{
-    gather_songs_from_path, get_atomic_float, locate_playlists, start_worker_thread, AudioPlayer,
-    Song,
+    cover_art, is_audio_file, locate_playlists, start_worker_thread, AudioPlayer, Song,
 };
 use crate::bluetooth::{BluetoothDevice, BluetoothDevices};
 use crate::constants;
-use crate::constants::{AudioThreadActions, PLAYLIST_DIRECTORY};
-#[cfg(not(target_arch = "x86_64"))]
-use crate::lights::get_gpio_map;
-#[cfg(not(target_arch = "x86_64"))]
-use crate::lights::{interface_gpio, LightType};
-#[cfg(not(target_arch = "x86_64"))]
-use rppal::gpio::OutputPin;
+use crate::constants::{AudioCommand, AudioThreadActions, PLAYLIST_DIRECTORY};
+use crate::fixtures::{ComponentKind, FixtureConfig};
+use crate::library::{skipped_songs_notification, MediaLibrary, SharedLibrary};
+use crate::lights::{light_track_name, LightType};
+use crate::outputs::{
+    create_shared_output, LatencyConfig, LatencyGroup, OutputBackend, OutputConfig, RigState,
+    SharedLatency, SharedLightOutput, MAX_LATENCY_OFFSET,
+};
+use crate::playback::{PlaybackConfig, SharedPlaybackConfig, MAX_CROSSFADE};
+use crate::player_events::{PlayerEvent, PlayerState};
+use crate::playlist::{is_playlist_file, load_playlist, PlaylistFile};
+use crate::shuffle::ShuffleMode;
+use crate::timing::{SharedTimingRecorder, HISTOGRAM_BUCKETS};
 
 /// The screens available in OpenLightsCore
 ///
@@ -37,6 +43,7 @@ use rppal::gpio::OutputPin;
 /// FileManager: Allows for deleting audio and playlists
 /// Audio: Bluetooth management screen
 /// Debug: Displays a light matrix for debugging relays
+/// Calibration: Tunes the latency offsets with a click track and a flashing channel
 #[derive(PartialEq, Default)]
 enum Screen {
     #[default]
@@ -45,72 +52,174 @@ enum Screen {
     FileManager,
     Audio,
     Debug,
+    Calibration,
 }
 
 pub struct OpenLightsCore {
     playlist_vec: Vec<String>,
-    song_vec_cache: Option<Vec<Song>>,
     playlist: String,
     current_screen: Screen,
     file_explorer: FileExplorer,
-    pub audio_player: Arc<Mutex<AudioPlayer>>,
-    messenger: Sender<AudioThreadActions>,
-    song_vec_receiver: Receiver<Vec<Song>>,
-    volume: Arc<AtomicI8>,
-    clicked_index: Arc<AtomicUsize>,
+    timing_recorder: SharedTimingRecorder,
+    messenger: Sender<AudioCommand>,
+    player_events: Receiver<PlayerEvent>,
+    player_state: PlayerState,
+    volume: i8,
     selected_bt_device: i8,
     cached_selected_bt_device: Option<BluetoothDevice>,
     clicked_squares: HashSet<usize>,
+    debug_intensity: u8,
     notifications: VecDeque<Notification>,
     bluetooth: BluetoothDevices,
-    bt_receiver: Receiver<Notification>,
-    #[cfg(not(target_arch = "x86_64"))]
-    gpio_pins: Arc<Mutex<HashMap<i32, OutputPin>>>,
+    notification_receiver: Receiver<Notification>,
+    output_config: OutputConfig,
+    light_output: SharedLightOutput,
+    fixtures: FixtureConfig,
+    latency: SharedLatency,
+    playback: SharedPlaybackConfig,
+    calibration_channel: usize,
+    calibrating: bool,
+    new_latency_group: LatencyGroup,
+    new_latency_group_channels: String,
+    seek_preview: Option<f32>,
+    cover_art: Option<(PathBuf, Option<Bytes>)>,
+    cover_art_loader: Option<Receiver<Option<Bytes>>>,
 }
 
 impl Default for OpenLightsCore {
     fn default() -> Self {
-        let volume = Arc::new(AtomicI8::new(100));
-        let clicked_index = Arc::new(AtomicUsize::new(0));
-        let (tx_song_vec, rx_song_vec) = mpsc::channel();
-        #[cfg(not(target_arch = "x86_64"))]
-        let gpio_pins = Arc::new(Mutex::new(get_gpio_map()));
+        let (tx_notification, rx_notification) = mpsc::channel();
+        let mut notifications = VecDeque::new();
+
+        let output_config = OutputConfig::load().unwrap_or_else(|err| {
+            notifications.push_front(Notification {
+                title: "Invalid Output Config".to_string(),
+                message: format!(
+                    "The light output config could not be read, so the defaults are used instead. {}",
+                    err
+                ),
+                timer: Timer::new(Duration::from_secs(30)),
+                id: fastrand::i32(0..i32::MAX),
+            });
+            OutputConfig::default()
+        });
+        let fixtures = FixtureConfig::load().unwrap_or_else(|err| {
+            notifications.push_front(Notification {
+                title: "Invalid Fixtures".to_string(),
+                message: format!(
+                    "The fixture definitions could not be read, so fixtures will stay dark. {}",
+                    err
+                ),
+                timer: Timer::new(Duration::from_secs(30)),
+                id: fastrand::i32(0..i32::MAX),
+            });
+            FixtureConfig::default()
+        });
+        let latency = LatencyConfig::load().unwrap_or_else(|err| {
+            notifications.push_front(Notification {
+                title: "Invalid Latency Config".to_string(),
+                message: format!(
+                    "The latency offsets could not be read, so no offsets are applied. {}",
+                    err
+                ),
+                timer: Timer::new(Duration::from_secs(30)),
+                id: fastrand::i32(0..i32::MAX),
+            });
+            LatencyConfig::default()
+        });
+        let latency = Arc::new(Mutex::new(latency));
+        let library = MediaLibrary::load().unwrap_or_else(|err| {
+            notifications.push_front(Notification {
+                title: "Invalid Library Index".to_string(),
+                message: format!(
+                    "The library index could not be read, so every song will be scanned again. {}",
+                    err
+                ),
+                timer: Timer::new(Duration::from_secs(30)),
+                id: fastrand::i32(0..i32::MAX),
+            });
+            MediaLibrary::new()
+        });
+        let library = Arc::new(Mutex::new(library));
+        let playback = PlaybackConfig::load().unwrap_or_else(|err| {
+            notifications.push_front(Notification {
+                title: "Invalid Playback Config".to_string(),
+                message: format!(
+                    "The playback settings could not be read, so songs play without a crossfade. {}",
+                    err
+                ),
+                timer: Timer::new(Duration::from_secs(30)),
+                id: fastrand::i32(0..i32::MAX),
+            });
+            PlaybackConfig::default()
+        });
+        let playback = Arc::new(Mutex::new(playback));
+        let (light_output, output_error) = create_shared_output(&output_config);
+        if let Some(err) = output_error {
+            notifications.push_front(output_failure_notification(err));
+        }
+
+        let bluetooth = BluetoothDevices::new(tx_notification.clone());
+        let file_explorer = FileExplorer::new(Arc::clone(&library), tx_notification.clone());
         let audio_player = Arc::new(Mutex::new(AudioPlayer::new(
-            Arc::clone(&volume),
-            Arc::clone(&clicked_index),
-            #[cfg(not(target_arch = "x86_64"))]
-            Arc::clone(&gpio_pins),
+            Arc::clone(&light_output),
+            Arc::clone(&latency),
+            Arc::clone(&library),
+            Arc::clone(&playback),
+            tx_notification,
         )));
 
-        let (tx_bt, rx_bt) = mpsc::channel();
-        let bluetooth = BluetoothDevices::new(tx_bt);
-
         let (tx, rx) = mpsc::channel();
-        start_worker_thread(Arc::clone(&audio_player), rx, tx_song_vec);
+        start_worker_thread(Arc::clone(&audio_player), rx);
+        let audio_player = audio_player.lock().unwrap();
+        let player_events = audio_player.events().lock().unwrap().subscribe();
+        let timing_recorder = audio_player.timing_recorder();
+        drop(audio_player);
 
         Self {
             playlist_vec: locate_playlists(),
-            song_vec_cache: None,
             playlist: String::from(""),
             current_screen: Screen::default(),
-            file_explorer: FileExplorer::new(),
-            audio_player,
+            file_explorer,
+            timing_recorder,
             messenger: tx,
-            song_vec_receiver: rx_song_vec,
-            volume,
-            clicked_index,
+            player_events,
+            player_state: PlayerState::default(),
+            volume: 100,
             selected_bt_device: -1,
             cached_selected_bt_device: None,
             clicked_squares: HashSet::new(),
-            notifications: VecDeque::new(),
+            debug_intensity: u8::MAX,
+            notifications,
             bluetooth,
-            bt_receiver: rx_bt,
-            #[cfg(not(target_arch = "x86_64"))]
-            gpio_pins,
+            notification_receiver: rx_notification,
+            output_config,
+            light_output,
+            fixtures,
+            latency,
+            playback,
+            calibration_channel: 0,
+            calibrating: false,
+            new_latency_group: LatencyGroup::default(),
+            new_latency_group_channels: String::new(),
+            seek_preview: None,
+            cover_art: None,
+            cover_art_loader: None,
         }
     }
 }
 
+/// The size of the cover art in the now playing area
+const COVER_ART_SIZE: f32 = 150.;
+
+/// How many songs of the play history are listed on the Jukebox screen
+const RECENTLY_PLAYED_COUNT: usize = 5;
+
+/// The URI the cover art of a song is cached under by the image loaders
+fn cover_art_uri(path: &Path) -> String {
+    format!("bytes://cover_art/{}", path.display())
+}
+
 #[inline]
 fn heading2() -> TextStyle {
     TextStyle::Name("Heading2".into())
@@ -179,13 +288,12 @@ impl OpenLightsCore {
                     .scroll_bar_visibility(ScrollBarVisibility::AlwaysHidden)
                     .show(ui, |ui| {
                         if !self.playlist_vec.is_empty() {
-                            for (index, option) in self.playlist_vec.iter().enumerate() {
+                            for option in &self.playlist_vec {
                                 if ui.add(egui::SelectableLabel::new(
-                                    self.clicked_index.load(Ordering::Relaxed) == index,
+                                    &self.playlist == option,
                                     option,
                                 )).clicked() {
                                     self.playlist.clone_from(option);
-                                    self.clicked_index.store(index, Ordering::Relaxed);
                                 };
                                 ui.add_space(10.);
                             }
@@ -193,15 +301,19 @@ impl OpenLightsCore {
                             ui.add_space(30.);
                             if ui.add_sized([210., 80.], egui::Button::new("Confirm")).clicked() && !self.playlist.is_empty() {
                                 if self.quick_playlist_valid() {
-                                    self.song_vec_cache = None;
-                                    self.messenger.send(AudioThreadActions::LoadFromPlaylist).unwrap();
+                                    self.send_action(AudioThreadActions::LoadPlaylist(self.playlist.clone()));
                                     self.current_screen = Screen::Jukebox;
                                 } else {
+                                    let message = if is_playlist_file(&Path::new(&**PLAYLIST_DIRECTORY).join(&self.playlist)) {
+                                        format!("The playlist file {} does not list any songs or could not be read.", self.playlist)
+                                    } else {
+                                        format!("The playlist {} does not contain any songs. \
+                                        Please add songs inside of a folder named the same as the song inside of the playlist folder. \
+                                        Ex: /open_lights/playlists/{}/SONG_NAME/SONG_NAME.mp3", self.playlist, self.playlist)
+                                    };
                                     let notification = Notification {
                                         title: "Invalid Playlist".to_string(),
-                                        message: format!("The playlist {} does not contain any songs. \
-                                        Please add songs inside of a folder named the same as the song inside of the playlist folder. \
-                                        Ex: /open_lights/playlists/{}/SONG_NAME/SONG_NAME.wav", self.playlist, self.playlist),
+                                        message,
                                         timer: Timer::new(Duration::from_secs(30)),
                                         id: fastrand::i32(0..i32::MAX),
                                     };
@@ -231,15 +343,21 @@ impl OpenLightsCore {
         });
     }
 
+    /// Sends an action to the audio thread
+    /// Errors are published as player events and shown as notifications.
+    fn send_action(&self, action: AudioThreadActions) {
+        self.messenger.send(AudioCommand::new(action)).unwrap();
+    }
+
     /// Checks to see if the playlist path is valid
     fn quick_playlist_valid(&mut self) -> bool {
-        let path = format!("{}{}/", &**PLAYLIST_DIRECTORY, &self.playlist);
+        let path = Path::new(&**PLAYLIST_DIRECTORY).join(&self.playlist);
+        if is_playlist_file(&path) {
+            return PlaylistFile::read(&path).is_ok_and(|playlist| !playlist.entries.is_empty());
+        }
         for file in WalkDir::new(path).min_depth(2).max_depth(3) {
             let song_file = file.unwrap();
-            let song_path = song_file.path().to_str().expect("Invalid UTF-8 sequence");
-
-            // Check if the file is a WAV file
-            if song_path.ends_with(".wav") {
+            if is_audio_file(song_file.path()) {
                 return true;
             }
         }
@@ -252,25 +370,31 @@ impl OpenLightsCore {
             egui::widgets::global_theme_preference_buttons(ui);
 
             if ui.button("Playlists").clicked() {
-                self.messenger.send(AudioThreadActions::Reset).unwrap();
+                self.send_action(AudioThreadActions::Reset);
                 self.current_screen = Screen::Playlist;
             }
 
             if ui.button("Song Manager").clicked() {
-                self.messenger.send(AudioThreadActions::Reset).unwrap();
+                self.send_action(AudioThreadActions::Reset);
                 self.current_screen = Screen::FileManager;
             }
 
             if ui.button("Bluetooth Manager").clicked() {
-                self.messenger.send(AudioThreadActions::Reset).unwrap();
+                self.send_action(AudioThreadActions::Reset);
                 self.current_screen = Screen::Audio;
             }
 
             if ui.button("Debug").clicked() {
-                self.messenger.send(AudioThreadActions::Reset).unwrap();
+                self.send_action(AudioThreadActions::Reset);
                 self.clicked_squares.clear();
                 self.current_screen = Screen::Debug;
             }
+
+            if ui.button("Calibration").clicked() {
+                self.send_action(AudioThreadActions::Reset);
+                self.calibrating = false;
+                self.current_screen = Screen::Calibration;
+            }
         });
     }
 
@@ -283,24 +407,13 @@ impl OpenLightsCore {
             self.top_menu(ui);
         });
 
-        if self.song_vec_cache.is_none() {
-            self.messenger
-                .send(AudioThreadActions::RequestSongVec)
-                .unwrap();
-            self.song_vec_cache =
-                Some(self.song_vec_receiver.recv().unwrap_or_else(|_| Vec::new()));
-        }
-
-        let current_song = {
-            let song_index = self.audio_player.lock().unwrap().song_index.clone();
-            let song_index_value = song_index.load(Ordering::Relaxed);
-            if let Some(ref song_vec) = self.song_vec_cache {
-                song_vec.get(song_index_value).unwrap().clone()
-            } else {
-                return;
-            }
+        // The song list arrives as a player event after the playlist is loaded
+        let Some(current_song) = self.player_state.current_song().cloned() else {
+            return;
         };
 
+        self.virtual_rig_panel(ctx, false);
+
         // Center
         CentralPanel::default().show(ctx, |ui| {
             ui.with_layout(Layout::top_down(Align::Center), |ui| {
@@ -319,35 +432,62 @@ impl OpenLightsCore {
                     .max_height(200.)
                     .scroll_bar_visibility(ScrollBarVisibility::AlwaysHidden)
                     .show(ui, |ui| {
-                        if let Some(ref song_vec) = self.song_vec_cache {
-                            for (index, song) in song_vec.iter().enumerate() {
-                                if ui
-                                    .add(egui::SelectableLabel::new(
-                                        &current_song == song,
-                                        format!("{} by {}", song.name, song.artist),
-                                    ))
-                                    .clicked()
-                                {
-                                    self.clicked_index.store(index, Ordering::Relaxed);
-                                    self.messenger
-                                        .send(AudioThreadActions::SongOverride)
-                                        .unwrap();
-                                };
-                                ui.add_space(10.);
-                            }
+                        for (index, song) in self.player_state.songs.iter().enumerate() {
+                            let label = if song.light_files.is_empty() {
+                                RichText::new(format!(
+                                    "{} by {}  (no light track)",
+                                    song.name, song.artist
+                                ))
+                                .color(ui.visuals().warn_fg_color)
+                            } else {
+                                RichText::new(format!("{} by {}", song.name, song.artist))
+                            };
+                            let response =
+                                ui.add(egui::SelectableLabel::new(&current_song == song, label));
+                            if response.clicked() {
+                                self.send_action(AudioThreadActions::PlayIndex(index));
+                            };
+                            // Opened with a right click or a long press on the touchscreen
+                            response.context_menu(|ui| {
+                                if ui.button("Play Next").clicked() {
+                                    self.send_action(AudioThreadActions::PlayNext(index));
+                                    ui.close_menu();
+                                }
+                                if ui.button("Add to Queue").clicked() {
+                                    self.send_action(AudioThreadActions::Enqueue(index));
+                                    ui.close_menu();
+                                }
+                            });
+                            ui.add_space(10.);
                         }
                     });
+
+                self.up_next_list(ui);
+                self.history_list(ui);
             });
         });
 
         egui::TopBottomPanel::bottom("bottom_taskbar").show(ctx, |ui| {
-            ui.set_height(150.0);
+            ui.set_height(170.0);
+
+            // Cover Art
+            self.cover_art(ui, &current_song);
 
             ui.with_layout(Layout::top_down(Align::Center), |ui| {
                 ui.separator();
 
                 // Song Title
-                ui.label(format!("{} by {}", current_song.name, current_song.artist));
+                ui.label(
+                    RichText::new(format!("{} by {}", current_song.name, current_song.artist))
+                        .strong(),
+                );
+                let details = current_song.details();
+                if !details.is_empty() {
+                    ui.label(RichText::new(details).weak());
+                }
+
+                // Light Track
+                self.light_track_selector(ui, &current_song);
 
                 // Loading Bar
                 Self::centered_song_progress_display(self, ui);
@@ -361,6 +501,169 @@ impl OpenLightsCore {
         });
     }
 
+    /// Shows the songs queued to play next, which can be reordered or taken off the queue
+    fn up_next_list(&mut self, ui: &mut Ui) {
+        if self.player_state.up_next.is_empty() {
+            return;
+        }
+        ui.add_space(10.);
+        ui.label(RichText::new("Up Next").strong());
+
+        let last = self.player_state.up_next.len() - 1;
+        let mut action = None;
+        ScrollArea::vertical()
+            .id_salt("up_next")
+            .auto_shrink([true, true])
+            .max_height(120.)
+            .scroll_bar_visibility(ScrollBarVisibility::AlwaysHidden)
+            .show(ui, |ui| {
+                for (position, index) in self.player_state.up_next.iter().enumerate() {
+                    let Some(song) = self.player_state.songs.get(*index) else {
+                        continue;
+                    };
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{}. {} by {}",
+                            position + 1,
+                            song.name,
+                            song.artist
+                        ));
+                        if ui
+                            .add_enabled(position > 0, egui::Button::new("⬆"))
+                            .clicked()
+                        {
+                            action = Some(AudioThreadActions::MoveInQueue(position, position - 1));
+                        }
+                        if ui
+                            .add_enabled(position < last, egui::Button::new("⬇"))
+                            .clicked()
+                        {
+                            action = Some(AudioThreadActions::MoveInQueue(position, position + 1));
+                        }
+                        if ui.button("✖").clicked() {
+                            action = Some(AudioThreadActions::Dequeue(position));
+                        }
+                    });
+                }
+            });
+        if let Some(action) = action {
+            self.send_action(action);
+        }
+    }
+
+    /// Shows the songs played most recently, the latest first, which play again when clicked
+    fn history_list(&mut self, ui: &mut Ui) {
+        if self.player_state.history.is_empty() {
+            return;
+        }
+        let mut clicked = None;
+        ui.add_space(10.);
+        egui::CollapsingHeader::new("Recently Played").show(ui, |ui| {
+            for index in self
+                .player_state
+                .history
+                .iter()
+                .rev()
+                .take(RECENTLY_PLAYED_COUNT)
+            {
+                let Some(song) = self.player_state.songs.get(*index) else {
+                    continue;
+                };
+                if ui
+                    .selectable_label(false, format!("{} by {}", song.name, song.artist))
+                    .clicked()
+                {
+                    clicked = Some(*index);
+                }
+            }
+        });
+        if let Some(index) = clicked {
+            self.send_action(AudioThreadActions::PlayIndex(index));
+        }
+    }
+
+    /// Shows the cover art of a song in the corner of the now playing area
+    /// The cover art is read again in the background whenever the song changes, and shown once
+    /// it has been read.
+    fn cover_art(&mut self, ui: &mut Ui, song: &Song) {
+        let cached = matches!(&self.cover_art, Some((path, _)) if path == &song.path);
+        if !cached {
+            if let Some((path, Some(_))) = &self.cover_art {
+                ui.ctx().forget_image(&cover_art_uri(path));
+            }
+            self.cover_art = Some((song.path.clone(), None));
+            // Replacing the receiver drops the cover art of a song that was skipped past
+            let (sender, receiver) = mpsc::channel();
+            let path = song.path.clone();
+            let ctx = ui.ctx().clone();
+            thread::spawn(move || {
+                if sender.send(cover_art(&path).map(Bytes::from)).is_ok() {
+                    ctx.request_repaint();
+                }
+            });
+            self.cover_art_loader = Some(receiver);
+        }
+        if let Some(loader) = &self.cover_art_loader {
+            match loader.try_recv() {
+                Ok(bytes) => {
+                    if let Some((_, cover)) = &mut self.cover_art {
+                        *cover = bytes;
+                    }
+                    self.cover_art_loader = None;
+                }
+                Err(TryRecvError::Disconnected) => self.cover_art_loader = None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+
+        let Some((path, Some(bytes))) = &self.cover_art else {
+            return;
+        };
+        let rect = Rect::from_min_size(
+            ui.max_rect().left_top() + Vec2::splat(10.),
+            Vec2::splat(COVER_ART_SIZE),
+        );
+        // A child ui keeps the now playing area centered beside the cover art
+        let mut cover_ui = ui.new_child(UiBuilder::new().max_rect(rect));
+        cover_ui.add(egui::Image::from_bytes(cover_art_uri(path), bytes.clone()).rounding(4.));
+    }
+
+    /// Shows which light track the current song uses and lets the user pick an alternative
+    fn light_track_selector(&mut self, ui: &mut Ui, song: &Song) {
+        if song.light_files.is_empty() {
+            ui.label(RichText::new("No light track").color(ui.visuals().warn_fg_color));
+            return;
+        }
+        if song.light_files.len() == 1 {
+            return;
+        }
+
+        let selected = song
+            .light_file()
+            .map(|light_file| light_track_name(&song.path, light_file))
+            .unwrap_or_default();
+        let mut clicked_track = None;
+        egui::ComboBox::from_id_salt("light_track_selector")
+            .selected_text(format!("Light Track: {}", selected))
+            .show_ui(ui, |ui| {
+                for (index, light_file) in song.light_files.iter().enumerate() {
+                    if ui
+                        .selectable_label(
+                            song.light_track == index,
+                            light_track_name(&song.path, light_file),
+                        )
+                        .clicked()
+                    {
+                        clicked_track = Some(index);
+                    }
+                }
+            });
+
+        if let Some(index) = clicked_track {
+            self.send_action(AudioThreadActions::SelectLightTrack(index));
+        }
+    }
+
     /// Shows the File Manager screen
     fn show_file_manager_screen(&mut self, ctx: &Context) {
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
@@ -373,27 +676,45 @@ impl OpenLightsCore {
 
     /// Displays a centered progress bar for the current audio track
     fn centered_song_progress_display(&mut self, ui: &mut Ui) {
-        let audio_player_safe = self.audio_player.lock().unwrap();
-        let progress = &audio_player_safe.progress.clone();
-        let ms_pos = &audio_player_safe.millisecond_position.clone();
-        let song_duration = &audio_player_safe.song_duration.clone();
-        drop(audio_player_safe);
-
-        let bar = ProgressBar::new(get_atomic_float(progress)).animate(false);
+        let song_duration = self.player_state.duration.as_secs_f32();
+
+        // While dragging, show where the song will jump to
+        let (progress, position) = match self.seek_preview {
+            Some(preview) => (preview, (preview * song_duration) as i32),
+            None => (
+                self.player_state.progress(),
+                Self::milliseconds_to_seconds(self.player_state.position.as_millis() as u64),
+            ),
+        };
+        let bar = ProgressBar::new(progress).animate(false);
 
         // Get the width of the text to center it
         let text = format!(
             "{} / {}",
-            Self::format_time(Self::milliseconds_to_seconds(
-                ms_pos.load(Ordering::Relaxed)
-            )),
-            Self::format_time(get_atomic_float(song_duration) as i32)
+            Self::format_time(position),
+            Self::format_time(song_duration as i32)
         );
 
         // Layout the progress bar
         ui.vertical_centered(|ui| {
-            // Add the progress bar
-            let response = ui.add(bar);
+            // Add the progress bar, which seeks when clicked or dragged
+            let response = ui
+                .add(bar)
+                .interact(egui::Sense::click_and_drag())
+                .on_hover_cursor(egui::CursorIcon::PointingHand);
+            let pointer_progress = response.interact_pointer_pos().map(|pointer| {
+                ((pointer.x - response.rect.left()) / response.rect.width()).clamp(0.0, 1.0)
+            });
+            if response.dragged() {
+                self.seek_preview = pointer_progress;
+            }
+            if response.drag_stopped() || response.clicked() {
+                if let Some(target) = pointer_progress.or(self.seek_preview) {
+                    let position = Duration::from_secs_f32(target * song_duration);
+                    self.send_action(AudioThreadActions::Seek(position));
+                }
+                self.seek_preview = None;
+            }
 
             // Calculate the position to center the text
             let rect = response.rect;
@@ -427,21 +748,28 @@ impl OpenLightsCore {
         let button_size = Vec2::new(40.0, 40.0); // Width and height of each button
 
         ui.horizontal(|ui| {
-            center_objects(button_size, 5, ui);
+            center_objects(button_size, 6, ui);
+
+            let has_history = !self.player_state.history.is_empty();
+            if ui
+                .add_enabled_ui(has_history, |ui| {
+                    ui.add_sized(button_size, egui::Button::new("⏮"))
+                })
+                .inner
+                .clicked()
+            {
+                self.send_action(AudioThreadActions::Previous);
+            }
 
             if ui.add_sized(button_size, egui::Button::new("⏭")).clicked() {
-                self.messenger.send(AudioThreadActions::Skip).unwrap();
+                self.send_action(AudioThreadActions::Skip);
             }
 
             if ui.add_sized(button_size, egui::Button::new("⏪")).clicked() {
-                self.messenger.send(AudioThreadActions::Rewind).unwrap();
+                self.send_action(AudioThreadActions::Rewind);
             }
 
-            let audio_player_safe = self.audio_player.lock().unwrap();
-            let playing_clone = audio_player_safe.playing.clone();
-            let looping = audio_player_safe.looping.clone();
-            drop(audio_player_safe);
-            let playing = playing_clone.load(Ordering::Relaxed);
+            let playing = self.player_state.playing;
             if ui
                 .add_sized(
                     button_size,
@@ -450,25 +778,37 @@ impl OpenLightsCore {
                 .clicked()
             {
                 if playing {
-                    self.messenger.send(AudioThreadActions::Pause).unwrap();
+                    self.send_action(AudioThreadActions::Pause);
                 } else {
-                    self.messenger.send(AudioThreadActions::Play).unwrap();
+                    self.send_action(AudioThreadActions::Play);
                 }
             }
 
-            if ui.add_sized(button_size, egui::Button::new("🔀")).clicked() {
-                self.song_vec_cache = None;
-                self.messenger.send(AudioThreadActions::Shuffle).unwrap();
-            }
-
             let visuals = ui.style().visuals.clone();
             let inactive = visuals.widgets.inactive.bg_fill;
             let active = visuals.selection.bg_fill;
 
+            // Each click moves on to the next shuffle mode
+            let shuffle = self.player_state.shuffle;
             if ui
                 .add_sized(
                     button_size,
-                    egui::Button::new("🔁").fill(if looping.load(Ordering::Relaxed) {
+                    egui::Button::new("🔀").fill(if shuffle == ShuffleMode::Off {
+                        inactive
+                    } else {
+                        active
+                    }),
+                )
+                .on_hover_text(shuffle.name())
+                .clicked()
+            {
+                self.send_action(AudioThreadActions::SetShuffle(shuffle.next()));
+            }
+
+            if ui
+                .add_sized(
+                    button_size,
+                    egui::Button::new("🔁").fill(if self.player_state.looping {
                         active
                     } else {
                         inactive
@@ -476,14 +816,14 @@ impl OpenLightsCore {
                 )
                 .clicked()
             {
-                self.messenger.send(AudioThreadActions::Loop).unwrap();
+                self.send_action(AudioThreadActions::Loop);
             }
         });
     }
 
     /// Creates a centered volume slider of the Jukebox screen
     fn centered_volume_slider(&mut self, ui: &mut Ui) {
-        let mut slider_percent = self.volume.load(Ordering::Relaxed);
+        let mut slider_percent = self.volume;
         let slider_size = Vec2::new(170., 50.);
 
         if ui
@@ -496,8 +836,8 @@ impl OpenLightsCore {
             )
             .drag_stopped
         {
-            self.volume.store(slider_percent, Ordering::Relaxed);
-            self.messenger.send(AudioThreadActions::Volume).unwrap();
+            self.volume = slider_percent;
+            self.send_action(AudioThreadActions::SetVolume(slider_percent as f32 / 100.0));
         }
     }
 
@@ -584,16 +924,59 @@ impl OpenLightsCore {
                             self.notifications.push_front(notification);
                         };
                     }
-                })
+                });
+
+                ui.add_space(20.0);
+                ui.label(RichText::new("  Playback  ").text_style(heading2()).strong().underline());
+                ui.separator();
+                self.playback_settings(ui);
             });
         });
     }
 
+    /// Shows the crossfade setting, saving it once it is changed
+    fn playback_settings(&mut self, ui: &mut Ui) {
+        let mut playback = self.playback.lock().unwrap().clone();
+        let response = ui.add(
+            egui::Slider::new(&mut playback.crossfade, 0.0..=MAX_CROSSFADE)
+                .text("Crossfade")
+                .suffix(" s")
+                .step_by(0.5),
+        );
+        ui.label(
+            RichText::new("At 0 s songs play back to back without a gap.")
+                .text_style(notification_font()),
+        );
+        let normalize = ui.checkbox(&mut playback.normalize, "Normalize loudness");
+        ui.label(
+            RichText::new("Plays every song at the same loudness, from the next song on.")
+                .text_style(notification_font()),
+        );
+
+        if response.changed() || normalize.changed() {
+            *self.playback.lock().unwrap() = playback.clone();
+        }
+        if response.drag_stopped()
+            || (response.changed() && !response.dragged())
+            || normalize.changed()
+        {
+            if let Err(err) = playback.save() {
+                self.notifications.push_front(Notification {
+                    title: "Failed to Save Playback Settings".to_string(),
+                    message: format!("The playback settings could not be saved. {}", err),
+                    timer: Timer::new(Duration::from_secs(10)),
+                    id: fastrand::i32(0..i32::MAX),
+                });
+            }
+        }
+    }
+
     /// Shows the Debug screen
     fn show_debug_screen(&mut self, ctx: &Context) {
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             self.top_menu(ui);
         });
+        self.virtual_rig_panel(ctx, true);
         CentralPanel::default().show(ctx, |ui| {
             ui.with_layout(Layout::top_down(Align::Center), |ui| {
                 ui.label(
@@ -602,7 +985,16 @@ impl OpenLightsCore {
                         .strong()
                         .underline(),
                 );
-                ui.add_space(50.0);
+                ui.add_space(20.0);
+
+                self.output_selector(ui);
+                ui.add(
+                    egui::Slider::new(&mut self.debug_intensity, 1..=u8::MAX)
+                        .text("Intensity")
+                        .trailing_fill(true),
+                );
+
+                ui.add_space(30.0);
 
                 let square_size = Vec2::new(100.0, 100.0); // Each square is 100x100 pixels
                 let total_size = Vec2::new(400.0, 400.0); // Total area is 400x400 pixels
@@ -624,33 +1016,399 @@ impl OpenLightsCore {
                                     )
                                     .clicked()
                                 {
-                                    if self.clicked_squares.contains(&index) {
-                                        self.clicked_squares.remove(&index);
-                                        #[cfg(not(target_arch = "x86_64"))]
-                                        let mut pin = self.gpio_pins.lock().unwrap();
-                                        #[cfg(not(target_arch = "x86_64"))]
-                                        interface_gpio(
-                                            pin.get_mut(&(index as i32)).unwrap(),
-                                            &LightType::Off,
-                                        );
+                                    let light_type = if self.clicked_squares.remove(&index) {
+                                        LightType::Off
                                     } else {
                                         self.clicked_squares.insert(index);
-                                        #[cfg(not(target_arch = "x86_64"))]
-                                        let mut pin = self.gpio_pins.lock().unwrap();
-                                        #[cfg(not(target_arch = "x86_64"))]
-                                        interface_gpio(
-                                            pin.get_mut(&(index as i32)).unwrap(),
-                                            &LightType::On,
-                                        );
-                                    }
+                                        LightType::from_intensity(self.debug_intensity)
+                                    };
+                                    let mut output = self.light_output.lock().unwrap();
+                                    output.set_channel(index as i32, light_type);
+                                    output.flush();
                                 }
                             }
                         });
                     }
                 });
+
+                ui.add_space(20.0);
+                ui.collapsing("Timing Accuracy", |ui| {
+                    self.timing_report(ui);
+                });
+            });
+        });
+    }
+
+    /// Displays the light timing report of the last song played, with export buttons
+    fn timing_report(&mut self, ui: &mut Ui) {
+        let recorder = self.timing_recorder.lock().unwrap();
+        let Some(report) = recorder.latest() else {
+            ui.label("Play a song with a light track to record its timing.");
+            return;
+        };
+
+        let summary = report.summary();
+        ui.label(RichText::new(&report.song).text_style(heading3()));
+        ui.label(format!(
+            "{} events, {} dropped\nMean lateness {:.2} ms, max lateness {:.2} ms",
+            summary.events, summary.dropped, summary.mean_lateness, summary.max_lateness
+        ));
+
+        let largest_bucket = summary.histogram.iter().copied().max().unwrap_or(0).max(1);
+        for ((_, label), count) in HISTOGRAM_BUCKETS.iter().zip(&summary.histogram) {
+            ui.add(
+                ProgressBar::new(*count as f32 / largest_bucket as f32)
+                    .desired_width(300.0)
+                    .text(format!("{}: {}", label, count)),
+            );
+        }
+
+        ui.horizontal(|ui| {
+            center_objects(Vec2::new(100.0, 20.0), 2, ui);
+            let mut export = None;
+            if ui.button("Export CSV").clicked() {
+                export = Some(report.export(false));
+            }
+            if ui.button("Export JSON").clicked() {
+                export = Some(report.export(true));
+            }
+            let notification = match export {
+                Some(Ok(path)) => Notification {
+                    title: "Timing Report Exported".to_string(),
+                    message: format!("The timing report was saved to {}", path.display()),
+                    timer: Timer::new(Duration::from_secs(10)),
+                    id: fastrand::i32(0..i32::MAX),
+                },
+                Some(Err(err)) => Notification {
+                    title: "Export Failed".to_string(),
+                    message: format!("The timing report could not be saved. {}", err),
+                    timer: Timer::new(Duration::from_secs(10)),
+                    id: fastrand::i32(0..i32::MAX),
+                },
+                None => return,
+            };
+            self.notifications.push_front(notification);
+        });
+    }
+
+    /// Shows the Calibration screen
+    fn show_calibration_screen(&mut self, ctx: &Context) {
+        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+            self.top_menu(ui);
+        });
+        self.virtual_rig_panel(ctx, false);
+        CentralPanel::default().show(ctx, |ui| {
+            ui.with_layout(Layout::top_down(Align::Center), |ui| {
+                ui.label(
+                    RichText::new("  Latency Calibration  ")
+                        .text_style(heading2())
+                        .strong()
+                        .underline(),
+                );
+                ui.add_space(10.0);
+                ui.label(
+                    "Start the click track and adjust the offsets until the flashing channel \
+                    lines up with the clicks. Positive offsets delay the lights.",
+                );
+                ui.add_space(20.0);
+
+                let channel_count = self.light_output.lock().unwrap().channel_count();
+                ui.horizontal(|ui| {
+                    center_objects(Vec2::new(150.0, 30.0), 2, ui);
+                    ui.add_enabled(
+                        !self.calibrating,
+                        egui::DragValue::new(&mut self.calibration_channel)
+                            .range(0..=channel_count.saturating_sub(1))
+                            .prefix("Flash Channel "),
+                    );
+                    if self.calibrating {
+                        if ui.button("Stop Click Track").clicked() {
+                            self.send_action(AudioThreadActions::StopCalibration);
+                            self.calibrating = false;
+                        }
+                    } else if ui.button("Start Click Track").clicked() {
+                        self.send_action(AudioThreadActions::StartCalibration(
+                            self.calibration_channel as i32,
+                        ));
+                        self.calibrating = true;
+                    }
+                });
+
+                ui.add_space(20.0);
+                ui.separator();
+                self.latency_settings(ui);
             });
         });
     }
+
+    /// Displays the latency offsets, saving them whenever they are changed
+    fn latency_settings(&mut self, ui: &mut Ui) {
+        let backend = self.light_output.lock().unwrap().backend();
+        let mut latency = self.latency.lock().unwrap().clone();
+        let mut changed = false;
+        let mut save = false;
+
+        let mut offset_slider = |ui: &mut Ui, offset: &mut i32, text: String| {
+            let response = ui.add(
+                egui::Slider::new(offset, -MAX_LATENCY_OFFSET..=MAX_LATENCY_OFFSET)
+                    .text(text)
+                    .suffix(" ms"),
+            );
+            changed |= response.changed();
+            save |= response.drag_stopped() || (response.changed() && !response.dragged());
+        };
+
+        offset_slider(ui, &mut latency.global, "Global".to_string());
+        offset_slider(
+            ui,
+            latency.backends.entry(backend).or_insert(0),
+            format!("{} Output", backend.name()),
+        );
+
+        ui.add_space(10.0);
+        ui.label(RichText::new("Channel Groups").text_style(heading3()));
+        let mut removed_group = None;
+        for (index, group) in latency.groups.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                offset_slider(
+                    ui,
+                    &mut group.offset,
+                    format!("{} {:?}", group.name, group.channels),
+                );
+                if ui.button("🗑").clicked() {
+                    removed_group = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed_group {
+            latency.groups.remove(index);
+            changed = true;
+            save = true;
+        }
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_latency_group.name)
+                    .hint_text("Group name")
+                    .desired_width(120.0),
+            );
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_latency_group_channels)
+                    .hint_text("Channels, e.g. 0, 1, 2")
+                    .desired_width(160.0),
+            );
+            let channels: Option<Vec<i32>> = self
+                .new_latency_group_channels
+                .split(',')
+                .map(|channel| channel.trim().parse().ok())
+                .collect();
+            let valid = !self.new_latency_group.name.trim().is_empty()
+                && channels
+                    .as_ref()
+                    .is_some_and(|channels| !channels.is_empty());
+            if ui
+                .add_enabled(valid, egui::Button::new("Add Group"))
+                .clicked()
+            {
+                let mut group = std::mem::take(&mut self.new_latency_group);
+                group.channels = channels.unwrap_or_default();
+                latency.groups.push(group);
+                self.new_latency_group_channels.clear();
+                changed = true;
+                save = true;
+            }
+        });
+
+        if changed {
+            *self.latency.lock().unwrap() = latency.clone();
+        }
+        if save {
+            if let Err(err) = latency.save() {
+                self.notifications.push_front(Notification {
+                    title: "Failed to Save Latency".to_string(),
+                    message: format!("The latency offsets could not be saved. {}", err),
+                    timer: Timer::new(Duration::from_secs(10)),
+                    id: fastrand::i32(0..i32::MAX),
+                });
+            }
+        }
+    }
+
+    /// Shows the live channel grid of the virtual rig, if it is the active output
+    ///
+    /// show_history: Whether to list recent transitions and the statistics controls
+    fn virtual_rig_panel(&mut self, ctx: &Context, show_history: bool) {
+        let Some(rig) = self.light_output.lock().unwrap().virtual_rig() else {
+            return;
+        };
+        ctx.request_repaint_after(Duration::from_millis(30));
+
+        egui::SidePanel::right("virtual_rig_panel")
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.with_layout(Layout::top_down(Align::Center), |ui| {
+                    ui.label(RichText::new("Virtual Rig").text_style(heading3()).strong());
+                    ui.add_space(10.0);
+
+                    let mut rig_state = rig.lock().unwrap();
+                    show_channel_grid(ui, &rig_state);
+                    show_fixture_swatches(ui, &self.fixtures, &rig_state);
+
+                    if show_history {
+                        ui.add_space(10.0);
+                        if ui.button("Reset Statistics").clicked() {
+                            rig_state.reset_statistics();
+                        }
+                        ui.separator();
+
+                        ScrollArea::vertical()
+                            .auto_shrink([false, true])
+                            .max_height(200.)
+                            .show(ui, |ui| {
+                                for transition in rig_state.history().iter().rev() {
+                                    ui.label(
+                                        RichText::new(format!(
+                                            "{:>8.3}s  Channel {:>2}  {:?}",
+                                            transition.time.as_secs_f32(),
+                                            transition.channel,
+                                            transition.light_type
+                                        ))
+                                        .text_style(notification_font()),
+                                    );
+                                }
+                            });
+                    }
+                });
+            });
+    }
+
+    /// Displays a selector for the light output backend
+    fn output_selector(&mut self, ui: &mut Ui) {
+        let mut selected = self.output_config.backend;
+        egui::ComboBox::from_label("Light Output")
+            .selected_text(selected.name())
+            .show_ui(ui, |ui| {
+                for backend in OutputBackend::ALL {
+                    ui.selectable_value(&mut selected, backend, backend.name());
+                }
+            });
+
+        if selected != self.output_config.backend {
+            let previous = self.output_config.backend;
+            self.output_config.backend = selected;
+            match self.output_config.create_output() {
+                Ok(new_output) => {
+                    let mut output = self.light_output.lock().unwrap();
+                    output.terminate();
+                    *output = new_output;
+                    drop(output);
+
+                    self.clicked_squares.clear();
+                    if let Err(err) = self.output_config.save() {
+                        self.notifications
+                            .push_front(output_failure_notification(err));
+                    }
+                }
+                Err(err) => {
+                    self.output_config.backend = previous;
+                    self.notifications
+                        .push_front(output_failure_notification(err));
+                }
+            }
+        }
+    }
+}
+
+/// Draws every channel of a virtual rig as a square with its duty cycle
+fn show_channel_grid(ui: &mut Ui, rig_state: &RigState) {
+    let square_size = Vec2::new(50.0, 50.0);
+    let visuals = ui.style().visuals.clone();
+
+    egui::Grid::new("virtual_rig_grid")
+        .spacing(Vec2::new(6.0, 6.0))
+        .show(ui, |ui| {
+            for (index, channel) in rig_state.channels().iter().enumerate() {
+                let (rect, response) = ui.allocate_exact_size(square_size, egui::Sense::hover());
+                let (fill, text_color) = if channel.light_type.is_on() {
+                    let lit = Color32::from_rgb(255, 200, 60);
+                    let intensity = channel.light_type.intensity() as f32 / u8::MAX as f32;
+                    (
+                        visuals
+                            .widgets
+                            .inactive
+                            .bg_fill
+                            .lerp_to_gamma(lit, intensity),
+                        Color32::BLACK,
+                    )
+                } else {
+                    (visuals.widgets.inactive.bg_fill, visuals.text_color())
+                };
+                ui.painter().rect_filled(rect, 6.0, fill);
+                ui.painter().text(
+                    rect.center(),
+                    egui::Align2::CENTER_CENTER,
+                    format!("{}\n{:.0}%", index, rig_state.duty_cycle(index) * 100.0),
+                    FontId::proportional(12.),
+                    text_color,
+                );
+                response.on_hover_text(format!(
+                    "Transitions: {}\nLast change: {:.3}s",
+                    channel.transitions,
+                    channel.last_change.as_secs_f32()
+                ));
+
+                if (index + 1) % 4 == 0 {
+                    ui.end_row();
+                }
+            }
+        });
+}
+
+/// Draws the colour of every RGB fixture of a virtual rig
+fn show_fixture_swatches(ui: &mut Ui, fixtures: &FixtureConfig, rig_state: &RigState) {
+    let level = |channel: Option<i32>| {
+        channel
+            .and_then(|channel| usize::try_from(channel).ok())
+            .and_then(|channel| rig_state.channels().get(channel))
+            .map(|state| state.light_type.intensity())
+    };
+
+    let colored = fixtures.fixtures.iter().filter(|fixture| {
+        fixture.has(ComponentKind::Red)
+            || fixture.has(ComponentKind::Green)
+            || fixture.has(ComponentKind::Blue)
+    });
+    for fixture in colored {
+        let white = level(fixture.channel_of(ComponentKind::White)).unwrap_or(0);
+        let dimmer = level(fixture.channel_of(ComponentKind::Intensity)).unwrap_or(u8::MAX);
+        let component = |kind: ComponentKind| {
+            let value = level(fixture.channel_of(kind))
+                .unwrap_or(0)
+                .saturating_add(white);
+            (value as u16 * dimmer as u16 / u8::MAX as u16) as u8
+        };
+        let color = Color32::from_rgb(
+            component(ComponentKind::Red),
+            component(ComponentKind::Green),
+            component(ComponentKind::Blue),
+        );
+
+        ui.horizontal(|ui| {
+            let (rect, _) = ui.allocate_exact_size(Vec2::new(20.0, 20.0), egui::Sense::hover());
+            ui.painter().circle_filled(rect.center(), 9.0, color);
+            ui.label(RichText::new(&fixture.name).text_style(notification_font()));
+        });
+    }
+}
+
+/// Creates a notification for a light output that failed
+fn output_failure_notification(message: String) -> Notification {
+    Notification {
+        title: "Light Output Failure".to_string(),
+        message,
+        timer: Timer::new(Duration::from_secs(15)),
+        id: fastrand::i32(0..i32::MAX),
+    }
 }
 
 /// Creates the proper amount of space for the given amount of objects
@@ -679,9 +1437,20 @@ impl eframe::App for OpenLightsCore {
     /// Called each time the UI needs repainting, which may be many times per second.
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
         show_notification(ctx, &mut self.notifications);
-        if let Ok(notification) = self.bt_receiver.try_recv() {
+        if let Ok(notification) = self.notification_receiver.try_recv() {
             self.notifications.push_front(notification);
         }
+        while let Ok(event) = self.player_events.try_recv() {
+            if let PlayerEvent::Error(err) = &event {
+                self.notifications.push_front(Notification {
+                    title: "Playback Error".to_string(),
+                    message: err.clone(),
+                    timer: Timer::new(Duration::from_secs(10)),
+                    id: fastrand::i32(0..i32::MAX),
+                });
+            }
+            self.player_state.apply(&event);
+        }
 
         #[cfg(not(target_arch = "x86_64"))]
         ctx.set_cursor_icon(egui::CursorIcon::None);
@@ -692,6 +1461,7 @@ impl eframe::App for OpenLightsCore {
             Screen::FileManager => self.show_file_manager_screen(ctx),
             Screen::Audio => self.show_bt_settings_screen(ctx),
             Screen::Debug => self.show_debug_screen(ctx),
+            Screen::Calibration => self.show_calibration_screen(ctx),
         }
     }
 }
@@ -705,20 +1475,26 @@ enum Selection {
 }
 
 struct FileExplorer {
+    library: SharedLibrary,
+    notification_sender: Sender<Notification>,
     selection: Selection,
     playlists: Vec<PathBuf>,
+    playlist: PathBuf,
     songs: Vec<Song>,
     selected_index: usize,
     show_edit_buttons: bool,
 }
 
 impl FileExplorer {
-    fn new() -> Self {
+    fn new(library: SharedLibrary, notification_sender: Sender<Notification>) -> Self {
         let playlists =
             Self::read_directory(PLAYLIST_DIRECTORY.as_ref()).unwrap_or_else(|_| vec![]);
         Self {
+            library,
+            notification_sender,
             selection: Selection::Playlist,
             playlists,
+            playlist: PathBuf::new(),
             songs: Vec::new(),
             selected_index: 0,
             show_edit_buttons: false,
@@ -729,7 +1505,9 @@ impl FileExplorer {
         let mut entries = vec![];
         for entry in fs::read_dir(path)? {
             let entry = entry?.path();
-            entries.push(entry);
+            if entry.is_dir() || is_playlist_file(&entry) {
+                entries.push(entry);
+            }
         }
         Ok(entries)
     }
@@ -782,14 +1560,19 @@ impl FileExplorer {
 
     fn render_playlists(&mut self, ui: &mut Ui) {
         for (index, path) in self.playlists.iter().enumerate() {
-            let label = ui.add(egui::SelectableLabel::new(
-                index == self.selected_index,
+            // Playlist files keep their extension, as a folder may share their name
+            let name = if path.is_dir() {
                 path.file_stem()
                     .unwrap()
                     .to_string_lossy()
                     .into_owned()
                     .replace('_', " ")
-                    .to_string(),
+            } else {
+                path.file_name().unwrap().to_string_lossy().into_owned()
+            };
+            let label = ui.add(egui::SelectableLabel::new(
+                index == self.selected_index,
+                name,
             ));
 
             if label.clicked() {
@@ -800,7 +1583,26 @@ impl FileExplorer {
             if label.double_clicked() {
                 self.selection = Selection::Song;
                 self.selected_index = 0;
-                self.songs = gather_songs_from_path(&path.to_string_lossy().into_owned());
+                self.playlist.clone_from(path);
+                let mut library = self.library.lock().unwrap();
+                let mut skipped = Vec::new();
+                self.songs =
+                    load_playlist(&mut library, path, &mut skipped).unwrap_or_else(|err| {
+                        self.notify(
+                            "Playlist Error",
+                            format!("The playlist {} could not be read. {}", path.display(), err),
+                        );
+                        Vec::new()
+                    });
+                if let Some(notification) = skipped_songs_notification(&skipped) {
+                    let _ = self.notification_sender.send(notification);
+                }
+                if let Err(err) = library.save() {
+                    self.notify(
+                        "Library Index Error",
+                        format!("The library index could not be saved. {}", err),
+                    );
+                }
             }
             ui.add_space(10.);
         }
@@ -822,18 +1624,85 @@ impl FileExplorer {
         }
     }
 
+    /// Removes the songs of a deleted directory from the library index
+    fn forget(&self, path: &Path) {
+        let mut library = self.library.lock().unwrap();
+        library.forget(path);
+        if let Err(err) = library.save() {
+            self.notify(
+                "Library Index Error",
+                format!("The library index could not be saved. {}", err),
+            );
+        }
+    }
+
+    /// Shows a notification for an error
+    fn notify(&self, title: &str, message: String) {
+        let _ = self.notification_sender.send(Notification {
+            title: title.to_string(),
+            message,
+            timer: Timer::new(Duration::from_secs(10)),
+            id: fastrand::i32(0..i32::MAX),
+        });
+    }
+
     fn remove_current_selected(&mut self) {
         match self.selection {
             Selection::Playlist => {
                 let path = self.playlists.get(self.selected_index).unwrap();
-                fs::remove_dir_all(path).expect("Failed to delete playlist");
+                if path.is_dir() {
+                    fs::remove_dir_all(path).expect("Failed to delete playlist");
+                    self.forget(path);
+                } else {
+                    // The songs of a playlist file live elsewhere and are kept
+                    fs::remove_file(path).expect("Failed to delete playlist");
+                }
                 self.playlists.remove(self.selected_index);
                 self.selected_index = 0;
             }
+            Selection::Song if is_playlist_file(&self.playlist) => {
+                // Songs are only taken out of playlist files, as other playlists may use them
+                let song = self.songs.get(self.selected_index).unwrap();
+                let occurrence = self.songs[..self.selected_index]
+                    .iter()
+                    .filter(|other| other.path == song.path)
+                    .count();
+                let directory = self.playlist.parent().unwrap_or(Path::new(""));
+                match PlaylistFile::read(&self.playlist) {
+                    Ok(mut playlist) => {
+                        playlist.remove(directory, &song.path, occurrence);
+                        if let Err(err) = playlist.write(&self.playlist) {
+                            self.notify(
+                                "Playlist Error",
+                                format!(
+                                    "The playlist {} could not be saved. {}",
+                                    self.playlist.display(),
+                                    err
+                                ),
+                            );
+                            return;
+                        }
+                    }
+                    Err(err) => {
+                        self.notify(
+                            "Playlist Error",
+                            format!(
+                                "The playlist {} could not be read. {}",
+                                self.playlist.display(),
+                                err
+                            ),
+                        );
+                        return;
+                    }
+                }
+                self.songs.remove(self.selected_index);
+                self.selected_index = 0;
+            }
             Selection::Song => {
                 let song = self.songs.get(self.selected_index).unwrap();
                 let path = song.path.parent().unwrap();
                 fs::remove_dir_all(path).expect("Failed to delete song");
+                self.forget(path);
                 self.songs.remove(self.selected_index);
                 self.selected_index = 0;
             }
@@ -0,0 +1,279 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rodio::source::SeekError;
+use rodio::{Sample, Source};
+
+/// The longest time the position is moved forward between two reads of the audio output
+/// The output reads samples in bursts, so without this the position would stutter.
+const MAX_EXTRAPOLATION: Duration = Duration::from_millis(50);
+
+/// The position of the audio being played, counted in samples
+/// The count is advanced by `CountingSource` as the output consumes samples, so it follows
+/// the audio itself instead of a timer.
+///
+/// samples: The amount of samples consumed since the start of the audio, across all channels
+/// samples_per_second: The sample rate multiplied by the channel count of the audio
+#[derive(Default)]
+pub struct AudioClock {
+    samples: AtomicU64,
+    samples_per_second: AtomicU32,
+}
+
+pub type SharedAudioClock = Arc<AudioClock>;
+
+impl AudioClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The amount of samples consumed since the start of the audio
+    pub fn samples(&self) -> u64 {
+        self.samples.load(Ordering::Relaxed)
+    }
+
+    /// The position in the audio of a sample count
+    pub fn position_of(&self, samples: u64) -> Duration {
+        let samples_per_second = self.samples_per_second.load(Ordering::Relaxed) as u64;
+        if samples_per_second == 0 {
+            return Duration::ZERO;
+        }
+        let seconds = samples / samples_per_second;
+        let remainder = samples % samples_per_second;
+        Duration::from_secs(seconds)
+            + Duration::from_nanos(remainder * 1_000_000_000 / samples_per_second)
+    }
+
+    /// The position of the audio consumed by the output
+    pub fn position(&self) -> Duration {
+        self.position_of(self.samples())
+    }
+
+    /// Moves the clock to a position, such as after seeking or loading new audio
+    fn set_position(&self, position: Duration) {
+        let samples_per_second = self.samples_per_second.load(Ordering::Relaxed) as u128;
+        let samples = position.as_nanos() * samples_per_second / 1_000_000_000;
+        self.samples.store(samples as u64, Ordering::Relaxed);
+    }
+
+    /// Starts counting a new audio source from its beginning
+    fn start(&self, sample_rate: u32, channels: u16) {
+        self.samples_per_second
+            .store(sample_rate * channels as u32, Ordering::Relaxed);
+        self.samples.store(0, Ordering::Relaxed);
+    }
+}
+
+/// A source that counts the samples taken from it on an `AudioClock`
+/// Every other property is passed through from the wrapped source.
+///
+/// input: The wrapped source
+/// clock: The clock the samples are counted on
+pub struct CountingSource<S> {
+    input: S,
+    clock: SharedAudioClock,
+}
+
+impl<S> CountingSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    /// Wraps a source, restarting the clock at the beginning of the source
+    pub fn new(input: S, clock: SharedAudioClock) -> Self {
+        clock.start(input.sample_rate(), input.channels());
+        Self { input, clock }
+    }
+}
+
+impl<S> Iterator for CountingSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.input.next()?;
+        self.clock.samples.fetch_add(1, Ordering::Relaxed);
+        Some(sample)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S> Source for CountingSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(position)?;
+        self.clock.set_position(position);
+        Ok(())
+    }
+}
+
+/// Reads an `AudioClock` smoothly
+/// Between two reads of the audio output the position is moved forward with the system clock,
+/// so the position advances continuously while staying locked to the samples played.
+///
+/// clock: The clock being read
+/// observed: The sample count at the last change
+/// observed_at: When the sample count last changed
+pub struct ClockReader {
+    clock: SharedAudioClock,
+    observed: u64,
+    observed_at: Instant,
+}
+
+impl ClockReader {
+    pub fn new(clock: SharedAudioClock) -> Self {
+        let observed = clock.samples();
+        Self {
+            clock,
+            observed,
+            observed_at: Instant::now(),
+        }
+    }
+
+    /// The current position in the audio
+    ///
+    /// playing: Whether the audio is playing, as paused audio must not move forward
+    pub fn position(&mut self, playing: bool) -> Duration {
+        let samples = self.clock.samples();
+        if samples != self.observed {
+            self.observed = samples;
+            self.observed_at = Instant::now();
+        }
+
+        let position = self.clock.position_of(samples);
+        if playing {
+            position + self.observed_at.elapsed().min(MAX_EXTRAPOLATION)
+        } else {
+            self.observed_at = Instant::now();
+            position
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+    use std::thread;
+
+    /// A clock counting stereo audio at 44.1 kHz
+    fn stereo_clock() -> SharedAudioClock {
+        let clock = Arc::new(AudioClock::new());
+        clock.start(44100, 2);
+        clock
+    }
+
+    #[test]
+    fn position_of_sample_counts() {
+        let clock = stereo_clock();
+        assert_eq!(clock.position_of(0), Duration::ZERO);
+        assert_eq!(clock.position_of(88200), Duration::from_secs(1));
+        assert_eq!(clock.position_of(44100), Duration::from_millis(500));
+        assert_eq!(
+            clock.position_of(88200 * 90 + 882),
+            Duration::from_millis(90_010)
+        );
+        // Positions are rounded down to the nanosecond
+        assert_eq!(clock.position_of(1), Duration::from_nanos(11337));
+    }
+
+    #[test]
+    fn clock_without_audio_stays_at_the_start() {
+        let clock = AudioClock::new();
+        assert_eq!(clock.position_of(1000), Duration::ZERO);
+        assert_eq!(clock.position(), Duration::ZERO);
+    }
+
+    #[test]
+    fn set_position_rounds_down_to_a_sample() {
+        let clock = stereo_clock();
+        clock.set_position(Duration::from_millis(250));
+        assert_eq!(clock.samples(), 22050);
+        assert_eq!(clock.position(), Duration::from_millis(250));
+        clock.set_position(Duration::from_nanos(11338));
+        assert_eq!(clock.samples(), 1);
+    }
+
+    #[test]
+    fn counting_source_counts_consumed_samples() {
+        let clock = stereo_clock();
+        clock.set_position(Duration::from_secs(3));
+        let buffer = SamplesBuffer::new(2, 48000, vec![0i16; 96000]);
+        let mut source = CountingSource::new(buffer, Arc::clone(&clock));
+        // A new source restarts the clock in its own format
+        assert_eq!(clock.samples(), 0);
+
+        source.by_ref().take(4800).for_each(drop);
+        assert_eq!(clock.samples(), 4800);
+        assert_eq!(clock.position(), Duration::from_millis(50));
+
+        source.try_seek(Duration::from_millis(500)).unwrap();
+        assert_eq!(clock.position(), Duration::from_millis(500));
+        source.by_ref().take(96).for_each(drop);
+        assert_eq!(clock.position(), Duration::from_millis(501));
+    }
+
+    #[test]
+    fn reader_does_not_move_while_paused() {
+        let clock = stereo_clock();
+        clock.samples.store(88200, Ordering::Relaxed);
+        let mut reader = ClockReader::new(Arc::clone(&clock));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(reader.position(false), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reader_extrapolates_at_most_50_ms() {
+        let clock = stereo_clock();
+        clock.samples.store(88200, Ordering::Relaxed);
+        let mut reader = ClockReader::new(Arc::clone(&clock));
+
+        let position = reader.position(true);
+        assert!(position >= Duration::from_secs(1));
+        assert!(position <= Duration::from_secs(1) + MAX_EXTRAPOLATION);
+
+        thread::sleep(MAX_EXTRAPOLATION * 2);
+        assert_eq!(
+            reader.position(true),
+            Duration::from_secs(1) + MAX_EXTRAPOLATION
+        );
+
+        // New samples restart the extrapolation from their position
+        clock.samples.store(88200 * 2, Ordering::Relaxed);
+        let position = reader.position(true);
+        assert!(position >= Duration::from_secs(2));
+        assert!(position <= Duration::from_secs(2) + MAX_EXTRAPOLATION);
+    }
+}
@@ -1,49 +1,154 @@
-use lofty::file::TaggedFileExt;
+use lofty::picture::PictureType;
 use lofty::prelude::*;
-use lofty::probe::Probe;
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
-#[cfg(not(target_arch = "x86_64"))]
-use rppal::gpio::OutputPin;
+use rodio::buffer::SamplesBuffer;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use serde::{Deserialize, Serialize};
 use std::cmp::PartialEq;
+use std::f32::consts::TAU;
 
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, AtomicI8, AtomicU32, AtomicU64, AtomicUsize, Ordering};
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fs, thread};
-use std::collections::HashMap;
-use walkdir::WalkDir;
 
-use crate::constants::{AudioThreadActions, PLAYLIST_DIRECTORY};
-use crate::lights::{all_off, start_light_thread};
+use crate::app::{Notification, Timer};
+use crate::audio_clock::{AudioClock, CountingSource, SharedAudioClock};
+use crate::constants::{
+    AudioCommand, AudioReply, AudioResult, AudioThreadActions, AUDIO_EXTENSIONS, PLAYLIST_DIRECTORY,
+};
+use crate::fixtures::FixtureConfig;
+use crate::library::{skipped_songs_notification, SharedLibrary};
+use crate::lights::{calibration_light_data, gather_light_data, start_light_thread, ChannelData};
+use crate::loudness::{cached_loudness, start_loudness_thread, tagged_loudness, Loudness};
+#[cfg(feature = "opus")]
+use crate::opus_decoder::OpusDecoder;
+use crate::outputs::{SharedLatency, SharedLightOutput};
+use crate::playback::SharedPlaybackConfig;
+use crate::player_events::{EventHub, PlayerEvent, SharedEventHub};
+use crate::playlist::{is_playlist_file, load_playlist};
+use crate::queue::PlayQueue;
+use crate::shuffle::{PlayOrder, ShuffleMode};
+use crate::timing::{SharedTimingRecorder, TimingRecorder};
+use crate::transition::{Track, TrackQueue, TransitionSource};
+use crate::validator::{validate_light_file, Diagnostic, Severity, ValidationContext};
+
+/// The time between two beats of the calibration click track
+pub const CALIBRATION_BEAT: Duration = Duration::from_millis(500);
+
+/// How long the calibration click track plays for
+const CALIBRATION_LENGTH: Duration = Duration::from_secs(600);
+
+/// How long before the crossfade into the next song the next song is opened
+const PRELOAD_TIME: Duration = Duration::from_secs(5);
+
+/// How often the playback position is published while playing
+const POSITION_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long each click of the calibration click track is
+const CLICK_LENGTH: Duration = Duration::from_millis(20);
+
+/// How long the calibration channel stays on for each beat
+const FLASH_LENGTH: Duration = Duration::from_millis(100);
+
+/// The tags and length of an audio file
+///
+/// title: The title of the song
+/// artist: The artist of the song, "Unknown" if it is not tagged
+/// album: The album the song is from
+/// year: The year the song was released
+/// track: The number of the song on its album
+/// genre: The genre of the song
+/// duration: The length of the song in seconds
+/// replay_gain: The loudness from the ReplayGain tags of the song
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct SongMetadata {
+    pub title: Option<String>,
+    pub artist: String,
+    pub album: Option<String>,
+    pub year: Option<u32>,
+    pub track: Option<u32>,
+    pub genre: Option<String>,
+    pub duration: f32,
+    pub replay_gain: Option<Loudness>,
+}
 
 #[derive(Clone, Default)]
 pub struct Song {
     pub name: String,
     pub artist: String,
+    pub album: Option<String>,
+    pub year: Option<u32>,
+    pub track: Option<u32>,
+    pub genre: Option<String>,
     pub path: PathBuf,
     pub duration: f32,
+    pub replay_gain: Option<Loudness>,
+    pub light_files: Vec<PathBuf>,
+    pub light_track: usize,
+    pub start: f32,
+    pub volume: f32,
 }
 
 impl Song {
-    fn new(path: &str, artist: String, duration: f32) -> Self {
-        let path_ref = Path::new(&path);
-        let path: PathBuf = path_ref.to_path_buf();
-        let name: String = path
-            .file_stem()
-            .unwrap()
-            .to_string_lossy()
-            .into_owned()
-            .replace('_', " ");
+    /// light_files: The light files of the song, as found by `find_light_files`
+    pub(crate) fn new(path: &Path, metadata: SongMetadata, light_files: Vec<PathBuf>) -> Self {
+        let path: PathBuf = path.to_path_buf();
+        // The title tag is preferred, as file names cannot hold every character
+        let name: String = metadata.title.unwrap_or_else(|| {
+            path.file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+                .replace('_', " ")
+        });
         Self {
             name,
-            artist,
+            artist: metadata.artist,
+            album: metadata.album,
+            year: metadata.year,
+            track: metadata.track,
+            genre: metadata.genre,
             path,
-            duration,
+            duration: metadata.duration,
+            replay_gain: metadata.replay_gain,
+            light_files,
+            light_track: 0,
+            start: 0.0,
+            volume: 1.0,
+        }
+    }
+
+    /// The album, year, track number and genre of the song, leaving out any that are not tagged
+    pub fn details(&self) -> String {
+        let mut details = Vec::new();
+        match (&self.album, self.year) {
+            (Some(album), Some(year)) => details.push(format!("{} ({})", album, year)),
+            (Some(album), None) => details.push(album.clone()),
+            (None, Some(year)) => details.push(year.to_string()),
+            (None, None) => {}
+        }
+        if let Some(track) = self.track {
+            details.push(format!("Track {}", track));
+        }
+        if let Some(genre) = &self.genre {
+            details.push(genre.clone());
         }
+        details.join("  •  ")
+    }
+
+    /// Where the song starts playing, set by its playlist file
+    pub fn start_position(&self) -> Duration {
+        Duration::from_secs_f32(self.start.max(0.0))
+    }
+
+    /// Gets the light file of the selected light track, if the song has any
+    pub fn light_file(&self) -> Option<&PathBuf> {
+        self.light_files.get(self.light_track)
     }
 }
 
@@ -56,16 +161,29 @@ pub struct AudioPlayer {
     pub looping: Arc<AtomicBool>,
     pub millisecond_position: Arc<AtomicU64>,
     pub progress: Arc<AtomicU32>,
-    volume: Arc<AtomicI8>,
-    clicked_index: Arc<AtomicUsize>,
     pub(crate) sink: Sink,
     _stream: OutputStream,
     stream_handle: OutputStreamHandle,
+    audio_clock: SharedAudioClock,
+    track_queue: Arc<TrackQueue>,
+    queued_song: Option<(usize, SharedAudioClock)>,
+    next_song_prepared: bool,
+    queue: PlayQueue,
+    play_order: PlayOrder,
     light_thread_active: Arc<AtomicBool>,
     light_thread_toggle: Arc<AtomicBool>,
     light_thread_reset: Arc<AtomicBool>,
-    #[cfg(not(target_arch = "x86_64"))]
-    gpio_pins: Arc<Mutex<HashMap<i32, OutputPin>>>,
+    light_output: SharedLightOutput,
+    latency: SharedLatency,
+    library: SharedLibrary,
+    playback: SharedPlaybackConfig,
+    loudness_sender: Sender<PathBuf>,
+    timing_recorder: SharedTimingRecorder,
+    events: SharedEventHub,
+    notification_sender: Sender<Notification>,
+    light_file_sender: Sender<LightFileCheck>,
+    checked_light_files: HashSet<PathBuf>,
+    calibrating: bool,
 }
 
 unsafe impl Sync for AudioPlayer {}
@@ -79,11 +197,15 @@ impl PartialEq for Song {
 
 impl AudioPlayer {
     pub fn new(
-        volume: Arc<AtomicI8>,
-        clicked_index: Arc<AtomicUsize>,
-        #[cfg(not(target_arch = "x86_64"))] gpio_pins: Arc<Mutex<HashMap<i32, OutputPin>>>,
+        light_output: SharedLightOutput,
+        latency: SharedLatency,
+        library: SharedLibrary,
+        playback: SharedPlaybackConfig,
+        notification_sender: Sender<Notification>,
     ) -> Self {
         let (_stream, stream_handle) = OutputStream::try_default().unwrap();
+        let light_file_sender =
+            start_light_file_thread(Arc::clone(&library), notification_sender.clone());
         Self {
             song_vec: Vec::new(),
             playing: Arc::new(AtomicBool::new(false)),
@@ -93,138 +215,644 @@ impl AudioPlayer {
             looping: Arc::new(AtomicBool::new(false)),
             millisecond_position: Arc::new(AtomicU64::new(0)),
             progress: Arc::new(AtomicU32::new(0)),
-            volume,
-            clicked_index,
             sink: Sink::try_new(&stream_handle).unwrap(),
             _stream,
             stream_handle,
+            audio_clock: Arc::new(AudioClock::new()),
+            track_queue: Arc::new(TrackQueue::default()),
+            queued_song: None,
+            next_song_prepared: false,
+            queue: PlayQueue::default(),
+            play_order: PlayOrder::default(),
             light_thread_active: Arc::new(AtomicBool::new(false)),
             light_thread_toggle: Arc::new(AtomicBool::new(false)),
             light_thread_reset: Arc::new(AtomicBool::new(false)),
-            #[cfg(not(target_arch = "x86_64"))]
-            gpio_pins,
+            light_output,
+            latency,
+            library,
+            playback,
+            loudness_sender: start_loudness_thread(notification_sender.clone()),
+            light_file_sender,
+            timing_recorder: Arc::new(Mutex::new(TimingRecorder::new())),
+            events: Arc::new(Mutex::new(EventHub::new())),
+            notification_sender,
+            checked_light_files: HashSet::new(),
+            calibrating: false,
+        }
+    }
+
+    /// Carries out an action, returning its reply or a message for the user if it failed
+    ///
+    /// action: The action sent to the audio thread
+    fn run_action(&mut self, action: AudioThreadActions) -> AudioResult {
+        match action {
+            AudioThreadActions::Play => self.play()?,
+            AudioThreadActions::Pause => self.pause(),
+            AudioThreadActions::KillThread => {}
+            AudioThreadActions::Skip => self.next_song()?,
+            AudioThreadActions::Previous => self.previous_song()?,
+            AudioThreadActions::Loop => self.toggle_looping(),
+            AudioThreadActions::SetVolume(volume) => self.set_volume(volume),
+            AudioThreadActions::Rewind => self.rewind()?,
+            AudioThreadActions::Seek(position) => self.seek(position)?,
+            AudioThreadActions::SetShuffle(mode) => self.set_shuffle(mode),
+            AudioThreadActions::PlayIndex(index) => self.play_index(index)?,
+            AudioThreadActions::Enqueue(index) => self.enqueue(index, false)?,
+            AudioThreadActions::PlayNext(index) => self.enqueue(index, true)?,
+            AudioThreadActions::Dequeue(position) => self.dequeue(position)?,
+            AudioThreadActions::MoveInQueue(from, to) => self.move_in_queue(from, to)?,
+            AudioThreadActions::SelectLightTrack(light_track) => {
+                self.select_light_track(light_track)?
+            }
+            AudioThreadActions::StartCalibration(channel) => self.start_calibration(channel),
+            AudioThreadActions::StopCalibration => self.stop_calibration(),
+            AudioThreadActions::RequestSongVec => {
+                return Ok(AudioReply::SongVec(self.song_vec.clone()));
+            }
+            AudioThreadActions::LoadPlaylist(playlist) => {
+                self.load_songs_from_playlist(&playlist)?
+            }
+            AudioThreadActions::Reset => self.clear(),
         }
+        Ok(AudioReply::Done)
+    }
+
+    /// The events of the player, which front-ends subscribe to instead of locking the player
+    pub fn events(&self) -> SharedEventHub {
+        Arc::clone(&self.events)
+    }
+
+    /// The light timing of the songs played, which front-ends read without locking the player
+    pub fn timing_recorder(&self) -> SharedTimingRecorder {
+        Arc::clone(&self.timing_recorder)
+    }
+
+    fn publish(&self, event: PlayerEvent) {
+        self.events.lock().unwrap().publish(event);
+    }
+
+    fn publish_song_list(&self) {
+        self.publish(PlayerEvent::PlaylistLoaded {
+            songs: self.song_vec.clone(),
+            song_index: self.song_index.load(Ordering::Relaxed),
+        });
+    }
+
+    fn publish_queue(&self) {
+        self.publish(PlayerEvent::QueueChanged {
+            up_next: self.queue.up_next(),
+            history: self.queue.history().to_vec(),
+        });
+    }
+
+    fn publish_position(&self) {
+        self.publish(PlayerEvent::Position {
+            position: Duration::from_millis(self.millisecond_position.load(Ordering::Relaxed)),
+            duration: Duration::from_secs_f32(get_atomic_float(&self.song_duration)),
+        });
     }
 
-    fn prepare_song(&mut self) {
-        let song = self.get_current_song();
+    fn prepare_song(&mut self) -> Result<(), String> {
+        let song = self.get_current_song()?;
         self.sink.clear();
-        self.millisecond_position.store(0, Ordering::Relaxed);
+        self.millisecond_position
+            .store(song.start_position().as_millis() as u64, Ordering::Relaxed);
         set_atomic_float(&self.song_duration, song.duration);
-        let file = File::open(&song.path).unwrap();
-        let source = Decoder::new(BufReader::new(file)).unwrap();
-        self.sink.append(source);
+        let clock = Arc::new(AudioClock::new());
+        let track = open_track(
+            &song,
+            Arc::clone(&clock),
+            Duration::ZERO,
+            self.track_gain(&song),
+        )?;
+        let track_queue = Arc::new(TrackQueue::default());
+        self.sink
+            .append(TransitionSource::new(track, Arc::clone(&track_queue)));
+        self.audio_clock = clock;
+        self.track_queue = track_queue;
+        self.queued_song = None;
+        self.next_song_prepared = false;
         self.song_loaded = true;
-        self.kill_light_thread();
+        self.restart_light_thread(&song, true);
+        self.publish(PlayerEvent::TrackChanged {
+            index: self.song_index.load(Ordering::Relaxed),
+            song,
+        });
+        Ok(())
+    }
+
+    /// The gain that brings a song to the target loudness, 1.0 if normalization is turned off
+    /// ReplayGain tags are preferred over a measured loudness. A song that was not measured yet
+    /// plays as it is and is measured in the background for the next time it plays.
+    fn track_gain(&self, song: &Song) -> f32 {
+        if !self.playback.lock().unwrap().normalize {
+            return 1.0;
+        }
+        match song.replay_gain.or_else(|| cached_loudness(&song.path)) {
+            Some(loudness) => loudness.linear_gain(),
+            None => {
+                // The loudness thread outlives failed measurements, so this only fails on shutdown
+                let _ = self.loudness_sender.send(song.path.clone());
+                1.0
+            }
+        }
+    }
+
+    /// Opens the song after the current one shortly before the crossfade into it starts
+    /// A song that cannot be opened is reported once playback reaches it.
+    fn prepare_next_song(&mut self) {
+        if self.next_song_prepared || self.song_vec.is_empty() {
+            return;
+        }
+        let crossfade = self.playback.lock().unwrap().crossfade();
+        let duration = Duration::from_secs_f32(get_atomic_float(&self.song_duration));
+        let position = Duration::from_millis(self.millisecond_position.load(Ordering::Relaxed));
+        if duration.saturating_sub(position) > crossfade + PRELOAD_TIME {
+            return;
+        }
+
+        self.next_song_prepared = true;
+        let song_index = self.song_index.load(Ordering::Relaxed);
+        let index = if self.looping.load(Ordering::Relaxed) {
+            song_index
+        } else {
+            self.next_index()
+        };
+        let song = &self.song_vec[index];
+        let clock = Arc::new(AudioClock::new());
+        let gain = self.track_gain(song);
+        if let Ok(track) = open_track(song, Arc::clone(&clock), crossfade, gain) {
+            self.track_queue.queue(track);
+            self.queued_song = Some((index, clock));
+        }
+    }
+
+    /// Makes the next song the current song once the transition reaches it
+    /// The light thread is replaced without turning the lights off, so the show flows on.
+    fn hand_over(&mut self) {
+        let Some((index, clock)) = self.queued_song.take() else {
+            return;
+        };
+        let Some(song) = self.song_vec.get(index).cloned() else {
+            return;
+        };
+        self.next_song_prepared = false;
+        // A looping song repeats itself instead of taking the front of the queue
+        let from_queue = !self.looping.load(Ordering::Relaxed) && self.queue.front() == Some(index);
+        self.move_to_song(index, from_queue);
+        self.audio_clock = clock;
+        set_atomic_float(&self.song_duration, song.duration);
+        self.millisecond_position.store(
+            self.audio_clock.position().as_millis() as u64,
+            Ordering::Relaxed,
+        );
+        self.restart_light_thread(&song, false);
+        self.publish(PlayerEvent::TrackChanged { index, song });
+    }
+
+    /// Hands over to the next song if the transition reached it since it was last checked
+    fn check_handover(&mut self) {
+        if self.track_queue.take_handover() {
+            self.hand_over();
+        }
+    }
+
+    /// Forgets the prepared next song so it is chosen again, unless the crossfade into it already
+    /// started
+    fn cancel_next_song(&mut self) {
+        if self.track_queue.cancel() {
+            self.queued_song = None;
+            self.next_song_prepared = false;
+        }
+    }
+
+    /// Forgets the next song so it is prepared again, as seeking ends any crossfade into it
+    fn reset_next_song(&mut self) {
+        self.track_queue.cancel();
+        self.queued_song = None;
+        self.next_song_prepared = false;
+    }
+
+    /// Replaces the running light thread with one for the selected light track of a song
+    ///
+    /// blackout: Whether to turn every light off first, instead of letting the new show take over
+    fn restart_light_thread(&mut self, song: &Song, blackout: bool) {
+        self.check_light_file(song);
+        if blackout || song.light_file().is_none() {
+            self.kill_light_thread();
+        } else {
+            self.stop_light_thread();
+        }
+        let Some(light_file) = song.light_file() else {
+            return;
+        };
+        match gather_light_data(light_file) {
+            Ok(light_data) => {
+                self.timing_recorder
+                    .lock()
+                    .unwrap()
+                    .start(song.name.clone());
+                self.start_light_thread(light_data);
+            }
+            Err(err) => {
+                let _ = self.notification_sender.send(Notification {
+                    title: "Light File Error".to_string(),
+                    message: format!("The light file of {} could not be read. {}", song.name, err),
+                    timer: Timer::new(Duration::from_secs(10)),
+                    id: fastrand::i32(0..i32::MAX),
+                });
+            }
+        }
+    }
+
+    fn start_light_thread(&mut self, light_data: Vec<ChannelData>) {
         start_light_thread(
-            &song.path,
-            Arc::clone(&self.millisecond_position),
+            light_data,
+            Arc::clone(&self.audio_clock),
+            Arc::clone(&self.playing),
             Arc::clone(&self.light_thread_toggle),
             Arc::clone(&self.light_thread_active),
             Arc::clone(&self.light_thread_reset),
-            #[cfg(not(target_arch = "x86_64"))]
-            Arc::clone(&self.gpio_pins),
+            Arc::clone(&self.light_output),
+            Arc::clone(&self.latency),
+            Arc::clone(&self.timing_recorder),
         );
     }
 
-    fn kill_light_thread(&mut self) {
-        #[cfg(not(target_arch = "x86_64"))]
-        {
-            // Turn all lights off
-            let mut pin = self.gpio_pins.lock().unwrap();
-            all_off(&mut *pin);
+    /// Plays a click track and flashes a channel on every click, to tune the latency offsets
+    ///
+    /// channel: The channel to flash
+    fn start_calibration(&mut self, channel: i32) {
+        self.pause();
+        self.sink.clear();
+        self.song_loaded = false;
+        self.calibrating = true;
+
+        let click_track = click_track()
+            .repeat_infinite()
+            .take_duration(CALIBRATION_LENGTH);
+        self.sink.append(CountingSource::new(
+            click_track,
+            Arc::clone(&self.audio_clock),
+        ));
+        self.kill_light_thread();
+        self.timing_recorder
+            .lock()
+            .unwrap()
+            .start("Calibration".to_string());
+        self.start_light_thread(calibration_light_data(
+            vec![channel],
+            CALIBRATION_BEAT,
+            FLASH_LENGTH,
+            CALIBRATION_LENGTH,
+        ));
+
+        self.sink.play();
+        self.playing.store(true, Ordering::Relaxed);
+    }
+
+    fn stop_calibration(&mut self) {
+        if !self.calibrating {
+            return;
+        }
+        self.pause();
+        self.sink.clear();
+        self.kill_light_thread();
+        self.calibrating = false;
+    }
+
+    /// Switches the current song to another of its light tracks
+    ///
+    /// light_track: The index of the light track in the light files of the song
+    fn select_light_track(&mut self, light_track: usize) -> Result<(), String> {
+        let song_index = self.song_index.load(Ordering::Relaxed);
+        let Some(song) = self.song_vec.get_mut(song_index) else {
+            return Err("No song is loaded".to_string());
+        };
+        if light_track >= song.light_files.len() {
+            return Err(format!("{} has no light track {}", song.name, light_track));
+        }
+        if song.light_track == light_track {
+            return Ok(());
         }
+        song.light_track = light_track;
+        let song = song.clone();
+        self.publish_song_list();
 
+        if self.song_loaded {
+            self.restart_light_thread(&song, true);
+        }
+        Ok(())
+    }
+
+    /// Validates the light file of a song once per session and reports any problems
+    /// The light file is checked in the background, as checking its song hash reads the whole song.
+    fn check_light_file(&mut self, song: &Song) {
+        let Some(path) = song.light_file().cloned() else {
+            return;
+        };
+        if !self.checked_light_files.insert(path) {
+            return;
+        }
+        let _ = self.light_file_sender.send(LightFileCheck {
+            song: song.clone(),
+            channel_count: self.light_output.lock().unwrap().channel_count(),
+        });
+    }
+
+    fn kill_light_thread(&mut self) {
+        // Turn all lights off
+        self.light_output.lock().unwrap().terminate();
+        self.stop_light_thread();
+    }
+
+    /// Stops the light thread, leaving the lights as they are
+    fn stop_light_thread(&mut self) {
         if self.light_thread_active.load(Ordering::Relaxed) {
             self.light_thread_toggle.store(true, Ordering::Relaxed);
         }
     }
 
-    fn play(&mut self) {
-        if self.song_loaded {
-            self.sink.play();
-            self.playing.store(true, Ordering::Relaxed);
-        } else {
-            self.prepare_song();
-            self.play();
+    fn play(&mut self) -> Result<(), String> {
+        if !self.song_loaded {
+            self.prepare_song()?;
+        }
+        self.sink.play();
+        if !self.playing.swap(true, Ordering::Relaxed) {
+            self.publish(PlayerEvent::PlaybackChanged(true));
         }
+        Ok(())
     }
 
     fn pause(&mut self) {
         self.sink.pause();
-        self.playing.store(false, Ordering::Relaxed);
+        if self.playing.swap(false, Ordering::Relaxed) {
+            self.publish(PlayerEvent::PlaybackChanged(false));
+        }
     }
 
-    fn get_song_index(&mut self, song: &Song) -> usize {
-        self.song_vec.iter().position(|x| x == song).unwrap()
-    }
-
-    fn shuffle(&mut self) {
-        fastrand::shuffle(&mut self.song_vec);
-        self.song_index.store(0, Ordering::Relaxed);
-        self.song_loaded = false;
-        self.play();
+    /// Changes how the songs are ordered, carrying on from the current song
+    fn set_shuffle(&mut self, mode: ShuffleMode) {
+        let song_index = self.song_index.load(Ordering::Relaxed);
+        self.play_order.set_mode(mode, &self.song_vec, song_index);
+        // The song after this one changes, unless the crossfade into it already started
+        self.cancel_next_song();
+        self.publish(PlayerEvent::ShuffleChanged(mode));
     }
 
     fn set_volume(&mut self, new_volume: f32) {
         self.sink.set_volume(new_volume);
     }
 
-    fn get_current_song(&mut self) -> Song {
+    fn get_current_song(&mut self) -> Result<Song, String> {
         self.song_vec
             .get(self.song_index.load(Ordering::Relaxed))
-            .unwrap()
-            .clone()
+            .cloned()
+            .ok_or_else(|| "No songs are loaded".to_string())
     }
 
-    fn song_override(&mut self, song: &Song) {
+    /// Checks that an index points at a song of the song list
+    fn check_index(&self, index: usize) -> Result<(), String> {
+        if index >= self.song_vec.len() {
+            return Err(format!(
+                "There is no song {} in a playlist of {} songs",
+                index + 1,
+                self.song_vec.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Plays the song at an index of the song list
+    ///
+    /// index: The index of the song in the song list
+    fn play_index(&mut self, index: usize) -> Result<(), String> {
+        self.check_index(index)?;
         self.pause();
-        let index = self.get_song_index(song);
-        self.song_index.store(index, Ordering::Relaxed);
+        self.move_to_song(index, false);
         self.song_loaded = false;
-        self.play();
+        self.play()
     }
 
-    fn next_song(&mut self) {
-        self.pause();
-        let new_index = self.song_index.load(Ordering::Relaxed) + 1;
-        self.song_index.store(new_index, Ordering::Relaxed);
+    /// The index of the song after the current one, which is the front of the up next queue or
+    /// otherwise the next song of the play order
+    fn next_index(&mut self) -> usize {
+        match self.queue.front() {
+            Some(index) => index,
+            None => self
+                .play_order
+                .next(&self.song_vec, self.song_index.load(Ordering::Relaxed)),
+        }
+    }
 
-        if new_index >= self.song_vec.len() {
-            self.song_index.store(0, Ordering::Relaxed);
+    /// Makes a song the current song, remembering the song it replaces in the play history
+    ///
+    /// index: The index of the song in the song list
+    /// from_queue: Whether the song is the front of the up next queue, which is then taken off
+    fn move_to_song(&mut self, index: usize, from_queue: bool) {
+        let song_index = self.song_index.load(Ordering::Relaxed);
+        if self.song_loaded && index != song_index {
+            self.queue.remember(song_index);
         }
+        if from_queue {
+            self.queue.pop_front();
+        }
+        self.song_index.store(index, Ordering::Relaxed);
+        self.publish_queue();
+    }
 
+    fn next_song(&mut self) -> Result<(), String> {
+        self.pause();
+        let from_queue = !self.queue.is_empty();
+        let index = self.next_index();
+        self.move_to_song(index, from_queue);
         self.song_loaded = false;
-        self.play();
+        self.play()
     }
 
-    fn rewind(&mut self) {
+    /// Goes back to the song played before the current one
+    fn previous_song(&mut self) -> Result<(), String> {
+        let index = self
+            .queue
+            .previous()
+            .ok_or_else(|| "No song was played before this one".to_string())?;
         self.pause();
-        self.sink.try_seek(Duration::ZERO).unwrap();
-        self.play();
-        self.millisecond_position.store(0, Ordering::Relaxed);
+        self.song_index.store(index, Ordering::Relaxed);
+        self.song_loaded = false;
+        self.publish_queue();
+        self.play()
+    }
+
+    /// Adds a song to the up next queue
+    ///
+    /// index: The index of the song in the song list
+    /// next: Whether the song plays right after the current one, instead of after the queue
+    fn enqueue(&mut self, index: usize, next: bool) -> Result<(), String> {
+        self.check_index(index)?;
+        let front = self.queue.front();
+        self.queue.enqueue(index, next);
+        self.queue_changed(front);
+        Ok(())
+    }
+
+    /// Takes a song off the up next queue
+    ///
+    /// position: The position of the song in the queue
+    fn dequeue(&mut self, position: usize) -> Result<(), String> {
+        let front = self.queue.front();
+        self.queue.dequeue(position)?;
+        self.queue_changed(front);
+        Ok(())
+    }
+
+    /// Moves a song to another position of the up next queue
+    ///
+    /// from: The position of the song in the queue
+    /// to: The position the song is moved to
+    fn move_in_queue(&mut self, from: usize, to: usize) -> Result<(), String> {
+        let front = self.queue.front();
+        self.queue.move_in_queue(from, to)?;
+        self.queue_changed(front);
+        Ok(())
+    }
+
+    /// Publishes a change of the up next queue, preparing the next song again if it changed
+    ///
+    /// front: The front of the queue before the change
+    fn queue_changed(&mut self, front: Option<usize>) {
+        if self.queue.front() != front {
+            self.cancel_next_song();
+        }
+        self.publish_queue();
+    }
+
+    /// Goes back to where the current song starts
+    fn rewind(&mut self) -> Result<(), String> {
+        self.pause();
+        self.check_handover();
+        let start = self.get_current_song()?.start_position();
+        self.sink
+            .try_seek(start)
+            .map_err(|err| format!("The song could not be rewound. {}", err))?;
+        self.reset_next_song();
+        self.play()?;
+        self.millisecond_position
+            .store(start.as_millis() as u64, Ordering::Relaxed);
+        self.light_thread_reset.store(true, Ordering::Relaxed);
+        self.publish_position();
+        Ok(())
+    }
+
+    /// Jumps to a position in the current song, loading the song first if needed
+    ///
+    /// position: The position to jump to, which is limited to the length of the song
+    fn seek(&mut self, position: Duration) -> Result<(), String> {
+        if !self.song_loaded {
+            self.prepare_song()?;
+        }
+        self.check_handover();
+
+        let duration = Duration::from_secs_f32(get_atomic_float(&self.song_duration));
+        let position = position.min(duration);
+        self.sink
+            .try_seek(position)
+            .map_err(|err| format!("The song could not jump to the selected position. {}", err))?;
+        self.reset_next_song();
+
+        self.millisecond_position
+            .store(position.as_millis() as u64, Ordering::Relaxed);
+        if !duration.is_zero() {
+            set_atomic_float(
+                &self.progress,
+                position.as_secs_f32() / duration.as_secs_f32(),
+            );
+        }
         self.light_thread_reset.store(true, Ordering::Relaxed);
+        self.publish_position();
+        Ok(())
     }
 
     fn toggle_looping(&mut self) {
-        self.looping
-            .store(!self.looping.load(Ordering::Relaxed), Ordering::Relaxed);
+        let looping = !self.looping.load(Ordering::Relaxed);
+        self.looping.store(looping, Ordering::Relaxed);
+        // The song after this one changes, unless the crossfade into it already started
+        self.cancel_next_song();
+        self.publish(PlayerEvent::LoopingChanged(looping));
     }
 
-    fn load_songs_from_playlist(&mut self, playlist: &String) {
-        let path = format!("{}{}/", &**PLAYLIST_DIRECTORY, &playlist);
-        self.song_vec = gather_songs_from_path(&path);
+    fn load_songs_from_playlist(&mut self, playlist: &String) -> Result<(), String> {
+        let path = Path::new(&**PLAYLIST_DIRECTORY).join(playlist);
+        if !path.is_dir() && !is_playlist_file(&path) {
+            return Err(format!("The playlist {} does not exist", playlist));
+        }
+        let mut library = self.library.lock().unwrap();
+        let mut skipped = Vec::new();
+        let (songs, read_error) = match load_playlist(&mut library, &path, &mut skipped) {
+            Ok(songs) => (songs, None),
+            Err(err) => (Vec::new(), Some(err)),
+        };
+        self.song_vec = songs;
+        if let Some(notification) = skipped_songs_notification(&skipped) {
+            let _ = self.notification_sender.send(notification);
+        }
+        if let Err(err) = library.save() {
+            self.notification_sender
+                .send(Notification {
+                    title: "Library Index Error".to_string(),
+                    message: format!("The library index could not be saved. {}", err),
+                    timer: Timer::new(Duration::from_secs(10)),
+                    id: fastrand::i32(0..i32::MAX),
+                })
+                .unwrap();
+        }
+        drop(library);
+        // Songs are measured ahead of time so they play at the right level the first time
+        if self.playback.lock().unwrap().normalize {
+            for song in self
+                .song_vec
+                .iter()
+                .filter(|song| song.replay_gain.is_none())
+            {
+                let _ = self.loudness_sender.send(song.path.clone());
+            }
+        }
+        let first = self.play_order.reset(&self.song_vec);
+        self.song_index.store(first, Ordering::Relaxed);
+        self.song_loaded = false;
+        self.queue.clear();
+        self.publish_song_list();
+        self.publish_queue();
+        if let Some(err) = read_error {
+            return Err(format!(
+                "The playlist {} could not be read. {}",
+                playlist, err
+            ));
+        }
+        if self.song_vec.is_empty() {
+            return Err(format!(
+                "The playlist {} does not contain any songs",
+                playlist
+            ));
+        }
+        Ok(())
     }
 
     fn clear(&mut self) {
+        self.stop_calibration();
         self.pause();
         self.kill_light_thread();
         self.song_vec.clear();
+        self.queue.clear();
         self.progress.store(0, Ordering::Relaxed);
         self.song_index.store(0, Ordering::Relaxed);
         self.millisecond_position.store(0, Ordering::Relaxed);
-        self.clicked_index.store(0, Ordering::Relaxed);
         self.looping.store(false, Ordering::Relaxed);
+        self.play_order = PlayOrder::default();
+        self.publish_song_list();
+        self.publish_queue();
+        self.publish_position();
+        self.publish(PlayerEvent::LoopingChanged(false));
+        self.publish(PlayerEvent::ShuffleChanged(ShuffleMode::Off));
     }
 }
 
@@ -238,72 +866,48 @@ pub fn set_atomic_float(float: &Arc<AtomicU32>, value: f32) {
     float.store(value_as_u32, Ordering::Relaxed);
 }
 
+/// Starts the audio thread, which carries out commands and moves on to the next song
+/// Every command is answered on its reply channel, if it has one, and changes are published
+/// to the event hub of the player.
+///
+/// audio_player: The player the commands are carried out on
+/// receiver: The channel commands are received from
 pub fn start_worker_thread(
     audio_player: Arc<Mutex<AudioPlayer>>,
-    receiver: Receiver<AudioThreadActions>,
-    song_vec_sender: Sender<Vec<Song>>,
+    receiver: Receiver<AudioCommand>,
 ) {
     thread::spawn(move || {
+        let mut last_position_event = Instant::now();
         loop {
             // Check for messages
-            if let Ok(action) = receiver.try_recv() {
-                let mut audio_player_safe = audio_player.lock().unwrap();
-                match action {
-                    AudioThreadActions::Play => {
-                        audio_player_safe.play();
-                    }
-                    AudioThreadActions::Pause => {
-                        audio_player_safe.pause();
-                    }
-                    AudioThreadActions::KillThread => {
-                        break;
-                    }
-                    AudioThreadActions::Skip => {
-                        audio_player_safe.next_song();
-                    }
-                    AudioThreadActions::Loop => {
-                        audio_player_safe.toggle_looping();
-                    }
-                    AudioThreadActions::Volume => {
-                        let volume =
-                            audio_player_safe.volume.load(Ordering::Relaxed) as f32 / 100.0;
-                        audio_player_safe.set_volume(volume);
-                    }
-                    AudioThreadActions::Rewind => {
-                        audio_player_safe.rewind();
-                    }
-                    AudioThreadActions::Shuffle => {
-                        audio_player_safe.shuffle();
-                    }
-                    AudioThreadActions::SongOverride => {
-                        let song = audio_player_safe
-                            .song_vec
-                            .get(audio_player_safe.clicked_index.load(Ordering::Relaxed))
-                            .unwrap()
-                            .clone();
-                        audio_player_safe.song_override(&song);
-                    }
-                    AudioThreadActions::RequestSongVec => {
-                        song_vec_sender
-                            .send(audio_player_safe.song_vec.clone())
-                            .unwrap();
-                    }
-                    AudioThreadActions::LoadFromPlaylist => {
-                        let playlist = playlist_from_index(&audio_player_safe.clicked_index);
-                        audio_player_safe.load_songs_from_playlist(&playlist);
-                        audio_player_safe.clicked_index.store(0, Ordering::Relaxed);
-                    }
-                    AudioThreadActions::Reset => {
-                        audio_player_safe.clear();
+            if let Ok(command) = receiver.try_recv() {
+                if let AudioThreadActions::KillThread = command.action {
+                    if let Some(reply) = command.reply {
+                        let _ = reply.send(Ok(AudioReply::Done));
                     }
+                    break;
+                }
+                let mut audio_player_safe = audio_player.lock().unwrap();
+                let result = audio_player_safe.run_action(command.action);
+                if let Err(err) = &result {
+                    audio_player_safe.publish(PlayerEvent::Error(err.clone()));
+                }
+                drop(audio_player_safe);
+                if let Some(reply) = command.reply {
+                    let _ = reply.send(result);
                 }
             }
 
             // Update song position and progress if playing
             {
                 let mut audio_player_safe = audio_player.lock().unwrap();
-                if audio_player_safe.playing.load(Ordering::Relaxed) {
-                    let pos = audio_player_safe.sink.get_pos().as_millis();
+                if audio_player_safe.playing.load(Ordering::Relaxed)
+                    && !audio_player_safe.calibrating
+                {
+                    audio_player_safe.check_handover();
+                    audio_player_safe.prepare_next_song();
+
+                    let pos = audio_player_safe.audio_clock.position().as_millis();
                     audio_player_safe
                         .millisecond_position
                         .store(pos as u64, Ordering::Relaxed);
@@ -313,16 +917,25 @@ pub fn start_worker_thread(
                         (seconds as f64 / get_atomic_float(&audio_player_safe.song_duration) as f64)
                             as f32,
                     );
+                    if last_position_event.elapsed() >= POSITION_INTERVAL {
+                        audio_player_safe.publish_position();
+                        last_position_event = Instant::now();
+                    }
 
                     // Check for song finished
                     if get_atomic_float(&audio_player_safe.progress) >= 0.99
                         && audio_player_safe.sink.empty()
                     {
-                        if audio_player_safe.looping.load(Ordering::Relaxed) {
-                            audio_player_safe.prepare_song();
-                            audio_player_safe.play();
+                        let result = if audio_player_safe.looping.load(Ordering::Relaxed) {
+                            audio_player_safe
+                                .prepare_song()
+                                .and_then(|_| audio_player_safe.play())
                         } else {
-                            audio_player_safe.next_song();
+                            audio_player_safe.next_song()
+                        };
+                        if let Err(err) = result {
+                            audio_player_safe.pause();
+                            audio_player_safe.publish(PlayerEvent::Error(err));
                         }
                     }
                 }
@@ -333,32 +946,210 @@ pub fn start_worker_thread(
     });
 }
 
-fn gather_metadata(path: &str) -> (f64, String) {
-    println!("{}", path);
-    let wav_reader = hound::WavReader::open(path).unwrap();
-    let spec = wav_reader.spec();
-    let duration = wav_reader.duration();
-    let duration_seconds = duration as f64 / spec.sample_rate as f64;
-
-    let mut reader = BufReader::new(File::open(path).unwrap());
-    let tagged_file = Probe::new(&mut reader)
-        .guess_file_type()
-        .unwrap()
-        .read()
-        .unwrap();
-    if let Some(tag) = tagged_file.primary_tag() {
-        let artist = tag.artist();
-        let author = artist.as_deref().unwrap_or("Unknown");
-        (duration_seconds, String::from(author))
-    } else {
-        (duration_seconds, String::from("Unknown"))
+/// Creates one beat of the calibration click track, a short tone followed by silence
+fn click_track() -> SamplesBuffer<f32> {
+    const SAMPLE_RATE: u32 = 44100;
+    let beat_samples = (CALIBRATION_BEAT.as_secs_f32() * SAMPLE_RATE as f32) as usize;
+    let click_samples = (CLICK_LENGTH.as_secs_f32() * SAMPLE_RATE as f32) as usize;
+
+    let samples: Vec<f32> = (0..beat_samples)
+        .map(|index| {
+            if index >= click_samples {
+                return 0.0;
+            }
+            let time = index as f32 / SAMPLE_RATE as f32;
+            let envelope = 1.0 - index as f32 / click_samples as f32;
+            (time * 1000.0 * TAU).sin() * envelope * 0.8
+        })
+        .collect();
+    SamplesBuffer::new(1, SAMPLE_RATE, samples)
+}
+
+/// Checks if a file is an audio file that can be played, going by its extension
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Opens an audio file for playback
+/// Opus is decoded separately as rodio cannot decode it, when built with the `opus` feature.
+pub(crate) fn open_audio(path: &Path) -> Result<Box<dyn Source<Item = i16> + Send>, String> {
+    let open = || File::open(path).map_err(|err| err.to_string());
+    #[cfg(feature = "opus")]
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    #[cfg(feature = "opus")]
+    if extension == "opus" {
+        return Ok(Box::new(OpusDecoder::new(open()?)?));
     }
+    match Decoder::new(BufReader::new(open()?)) {
+        Ok(decoder) => Ok(Box::new(decoder)),
+        // Ogg files can hold Opus as well as Vorbis
+        #[cfg(feature = "opus")]
+        Err(_) if extension == "ogg" || extension == "oga" => {
+            Ok(Box::new(OpusDecoder::new(open()?)?))
+        }
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Opens the audio of a song and prepares it for playback
+/// The song starts at its start position, with its volume applied on top of the gain.
+///
+/// clock: The clock that counts the samples of the song
+/// crossfade: How long the song fades in over the song before it
+/// gain: The factor the samples of the song are multiplied with
+fn open_track(
+    song: &Song,
+    clock: SharedAudioClock,
+    crossfade: Duration,
+    gain: f32,
+) -> Result<Track, String> {
+    let source =
+        open_audio(&song.path).map_err(|err| format!("Failed to decode {}: {}", song.name, err))?;
+    let mut track = Track::new(
+        source,
+        clock,
+        Duration::from_secs_f32(song.duration.max(0.0)),
+        crossfade,
+        gain * song.volume,
+    );
+    if song.start > 0.0 {
+        track
+            .seek(song.start_position())
+            .map_err(|err| format!("{} could not start partway through. {}", song.name, err))?;
+    }
+    Ok(track)
 }
 
-fn playlist_from_index(index: &Arc<AtomicUsize>) -> String {
-    let playlists = locate_playlists();
-    let playlist: Option<String> = playlists.get(index.load(Ordering::Relaxed)).cloned();
-    playlist.unwrap()
+/// Reads the length of an audio file in seconds, if it can be read
+pub fn audio_duration(path: &Path) -> Option<f32> {
+    let tagged_file = lofty::read_from_path(path).ok()?;
+    Some(tagged_file.properties().duration().as_secs_f32())
+}
+
+/// Reads the tags and length of an audio file
+pub(crate) fn gather_metadata(path: &Path) -> Result<SongMetadata, String> {
+    let tagged_file = lofty::read_from_path(path).map_err(|err| err.to_string())?;
+    let duration = tagged_file.properties().duration().as_secs_f32();
+
+    let Some(tag) = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())
+    else {
+        return Ok(SongMetadata {
+            artist: String::from("Unknown"),
+            duration,
+            ..Default::default()
+        });
+    };
+    let text = |value: Option<std::borrow::Cow<'_, str>>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    Ok(SongMetadata {
+        title: text(tag.title()),
+        artist: text(tag.artist()).unwrap_or_else(|| String::from("Unknown")),
+        album: text(tag.album()),
+        year: tag.year(),
+        track: tag.track(),
+        genre: text(tag.genre()),
+        duration,
+        replay_gain: tagged_loudness(tag),
+    })
+}
+
+/// Reads the cover art embedded in an audio file
+/// The front cover is preferred, otherwise the first picture is used.
+pub fn cover_art(path: &Path) -> Option<Vec<u8>> {
+    let tagged_file = lofty::read_from_path(path).ok()?;
+    let tag = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())?;
+    let pictures = tag.pictures();
+    pictures
+        .iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.first())
+        .map(|picture| picture.data().to_vec())
+}
+
+/// A light file to validate in the background
+///
+/// song: The song whose selected light file is validated
+/// channel_count: The amount of channels the light output drives
+struct LightFileCheck {
+    song: Song,
+    channel_count: usize,
+}
+
+/// Starts the thread that validates light files and reports their problems
+/// Returns the sender the light files to check are sent to.
+///
+/// library: The library song hashes are cached in
+/// notification_sender: The sender problems are reported to
+fn start_light_file_thread(
+    library: SharedLibrary,
+    notification_sender: Sender<Notification>,
+) -> Sender<LightFileCheck> {
+    let (sender, receiver) = mpsc::channel::<LightFileCheck>();
+    thread::spawn(move || {
+        for check in receiver {
+            let Some(path) = check.song.light_file() else {
+                continue;
+            };
+            let fixtures = FixtureConfig::load().unwrap_or_default();
+            let context = ValidationContext {
+                channel_count: check.channel_count,
+                song_duration: Some((check.song.duration * 1000.0) as u32),
+                song: Some(&check.song.path),
+                library: Some(&library),
+                fixtures: &fixtures,
+            };
+            let diagnostics = validate_light_file(path, &context);
+            if let Some(notification) = light_file_notification(&check.song, &diagnostics) {
+                let _ = notification_sender.send(notification);
+            }
+        }
+    });
+    sender
+}
+
+/// Creates a notification summarizing the problems in the light file of a song
+fn light_file_notification(song: &Song, diagnostics: &[Diagnostic]) -> Option<Notification> {
+    if diagnostics.is_empty() {
+        return None;
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+
+    let mut message = format!(
+        "{} errors and {} warnings were found in the light file of {}.",
+        errors, warnings, song.name
+    );
+    for diagnostic in diagnostics.iter().take(3) {
+        message.push('\n');
+        message.push_str(&diagnostic.to_string());
+    }
+    if diagnostics.len() > 3 {
+        message.push_str("\nRun \"open_lights_core check\" on the light file for the full list.");
+    }
+
+    Some(Notification {
+        title: "Light File Problems".to_string(),
+        message,
+        timer: Timer::new(Duration::from_secs(30)),
+        id: fastrand::i32(0..i32::MAX),
+    })
 }
 
 pub fn locate_playlists() -> Vec<String> {
@@ -368,7 +1159,8 @@ pub fn locate_playlists() -> Vec<String> {
         let directory = entry.unwrap();
         let path = directory.path();
 
-        if path.is_dir() {
+        // Playlist files are listed with their extension, as a folder may share their name
+        if path.is_dir() || is_playlist_file(&path) {
             if let Some(folder_name) = path.file_name().and_then(|name| name.to_str()) {
                 folder_names.push(folder_name.to_string());
             }
@@ -377,19 +1169,3 @@ pub fn locate_playlists() -> Vec<String> {
 
     folder_names
 }
-
-pub fn gather_songs_from_path(path: &String) -> Vec<Song> {
-    let mut songs: Vec<Song> = Vec::new();
-    for file in WalkDir::new(path).min_depth(2).max_depth(3) {
-        let song_file = file.unwrap();
-        let song_path = song_file.path().to_str().expect("Invalid UTF-8 sequence");
-
-        // Check if the file is a WAV file
-        if song_path.ends_with(".wav") {
-            let data = gather_metadata(song_path);
-            let song = Song::new(song_path, data.1, data.0 as f32);
-            songs.push(song);
-        }
-    }
-    songs
-}
@@ -1,10 +1,27 @@
 use std::env;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
 
 use once_cell::sync::Lazy;
 
+use crate::audio_player::Song;
+use crate::shuffle::ShuffleMode;
+
 /// The current version of OpenLightsCore
 pub const VERSION: &str = "1.0.0";
 
+/// The file extensions of the audio files that can be played, in lowercase
+#[cfg(feature = "opus")]
+pub const AUDIO_EXTENSIONS: [&str; 7] = ["wav", "mp3", "flac", "ogg", "oga", "opus", "m4a"];
+
+/// The file extensions of the audio files that can be played, in lowercase
+/// Opus files need the `opus` feature.
+#[cfg(not(feature = "opus"))]
+pub const AUDIO_EXTENSIONS: [&str; 6] = ["wav", "mp3", "flac", "ogg", "oga", "m4a"];
+
+/// The file extensions of playlist files, in lowercase
+pub const PLAYLIST_EXTENSIONS: [&str; 4] = ["m3u", "m3u8", "pls", "json"];
+
 /// The directory where playlists are stored
 pub static PLAYLIST_DIRECTORY: Lazy<String> = Lazy::new(|| {
     let mut path = env::current_dir().expect("Failed to get current directory");
@@ -14,31 +31,143 @@ pub static PLAYLIST_DIRECTORY: Lazy<String> = Lazy::new(|| {
         .to_string()
 });
 
+/// The file where the light output configuration is stored
+pub static OUTPUT_CONFIG_PATH: Lazy<String> = Lazy::new(|| {
+    let mut path = env::current_dir().expect("Failed to get current directory");
+    path.push("open_lights/output.json");
+    path.to_str()
+        .expect("Failed to convert path to string")
+        .to_string()
+});
+
+/// The file where fixture definitions are stored
+pub static FIXTURE_CONFIG_PATH: Lazy<String> = Lazy::new(|| {
+    let mut path = env::current_dir().expect("Failed to get current directory");
+    path.push("open_lights/fixtures.json");
+    path.to_str()
+        .expect("Failed to convert path to string")
+        .to_string()
+});
+
+/// The file where latency offsets are stored
+pub static LATENCY_CONFIG_PATH: Lazy<String> = Lazy::new(|| {
+    let mut path = env::current_dir().expect("Failed to get current directory");
+    path.push("open_lights/latency.json");
+    path.to_str()
+        .expect("Failed to convert path to string")
+        .to_string()
+});
+
+/// The file where the playback settings are stored
+pub static PLAYBACK_CONFIG_PATH: Lazy<String> = Lazy::new(|| {
+    let mut path = env::current_dir().expect("Failed to get current directory");
+    path.push("open_lights/playback.json");
+    path.to_str()
+        .expect("Failed to convert path to string")
+        .to_string()
+});
+
+/// The file where the media library index is stored
+pub static LIBRARY_INDEX_PATH: Lazy<String> = Lazy::new(|| {
+    let mut path = env::current_dir().expect("Failed to get current directory");
+    path.push("open_lights/library.json");
+    path.to_str()
+        .expect("Failed to convert path to string")
+        .to_string()
+});
+
+/// The directory where light timing reports are exported to
+pub static TIMING_DIRECTORY: Lazy<String> = Lazy::new(|| {
+    let mut path = env::current_dir().expect("Failed to get current directory");
+    path.push("open_lights/timing/");
+    path.to_str()
+        .expect("Failed to convert path to string")
+        .to_string()
+});
+
 /// Every action that the audio thread can invoke
 ///
 /// KillThread: Stops the audio thread
 /// Pause: Pauses the current audio
 /// Play: Plays the current audio
 /// Loops: Continues repeating the current audio when it completes
-/// Volume: Adjusts the global volume of the program
-/// Skip: Skips to the next audio in the playlist
+/// SetVolume: Sets the global volume of the program, from 0.0 to 1.0
+/// Skip: Skips to the next audio in the up next queue, or otherwise the playlist
+/// Previous: Goes back to the audio played before the current audio
 /// Rewind: Goes back to the beginning of the audio
-/// Shuffle: Randomizes the playlist and starts playing the next audio
-/// SongOverride: Plays the audio selected by the user
-/// RequestSongVec: Asks the audio thread to provide an audio list
-/// LoadFromPlaylist: Loads all audio in a playlist
+/// Seek: Jumps to a position in the current audio
+/// SetShuffle: Changes the order the playlist plays in, without reordering the audio list
+/// PlayIndex: Plays the audio at an index of the audio list
+/// Enqueue: Adds the audio at an index of the audio list to the end of the up next queue
+/// PlayNext: Adds the audio at an index of the audio list to the front of the up next queue
+/// Dequeue: Takes the audio at a position of the up next queue off the queue
+/// MoveInQueue: Moves the audio at a position of the up next queue to another position
+/// SelectLightTrack: Switches the current audio to the light track at an index
+/// StartCalibration: Plays the latency click track and flashes a channel
+/// StopCalibration: Stops the latency click track
+/// RequestSongVec: Asks the audio thread to reply with the audio list
+/// LoadPlaylist: Loads all audio in the playlist folder or playlist file with a name
 /// Reset: Resets all data in the audio thread
+#[derive(Clone, Debug)]
 pub enum AudioThreadActions {
     KillThread,
     Pause,
     Play,
     Loop,
-    Volume,
+    SetVolume(f32),
     Skip,
+    Previous,
     Rewind,
-    Shuffle,
-    SongOverride,
+    Seek(Duration),
+    SetShuffle(ShuffleMode),
+    PlayIndex(usize),
+    Enqueue(usize),
+    PlayNext(usize),
+    Dequeue(usize),
+    MoveInQueue(usize, usize),
+    SelectLightTrack(usize),
+    StartCalibration(i32),
+    StopCalibration,
     RequestSongVec,
-    LoadFromPlaylist,
+    LoadPlaylist(String),
     Reset,
 }
+
+/// A command for the audio thread
+///
+/// action: What the audio thread should do
+/// reply: Where the result of the action is sent, if the sender wants it
+pub struct AudioCommand {
+    pub action: AudioThreadActions,
+    pub reply: Option<Sender<AudioResult>>,
+}
+
+impl AudioCommand {
+    /// A command whose result is not needed
+    pub fn new(action: AudioThreadActions) -> Self {
+        Self {
+            action,
+            reply: None,
+        }
+    }
+
+    /// A command whose result is sent to a channel
+    pub fn with_reply(action: AudioThreadActions, reply: Sender<AudioResult>) -> Self {
+        Self {
+            action,
+            reply: Some(reply),
+        }
+    }
+}
+
+/// What the audio thread sends back after an action succeeds
+///
+/// Done: The action was carried out
+/// SongVec: The audio list, in reply to RequestSongVec
+pub enum AudioReply {
+    Done,
+    SongVec(Vec<Song>),
+}
+
+/// The result of an action, with a message for the user if it failed
+pub type AudioResult = Result<AudioReply, String>;
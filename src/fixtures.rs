@@ -0,0 +1,325 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::FIXTURE_CONFIG_PATH;
+
+/// The role of an output channel within a fixture
+///
+/// Intensity: A master dimmer, also used for plain channel groups
+/// Red, Green, Blue, White: Colour emitters
+/// Pan, Tilt: Moving head positions
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentKind {
+    Intensity,
+    Red,
+    Green,
+    Blue,
+    White,
+    Pan,
+    Tilt,
+}
+
+/// An output channel of a fixture
+///
+/// kind: What the channel controls
+/// channel: The OpenLights channel number
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Component {
+    pub kind: ComponentKind,
+    pub channel: i32,
+}
+
+/// A logical light made of several output channels, such as an RGB flood or a moving head
+///
+/// name: The name light files address the fixture by, prefixed with `@`
+/// components: The output channels of the fixture
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Fixture {
+    pub name: String,
+    pub components: Vec<Component>,
+}
+
+/// The fixture definitions stored in the OpenLights directory
+///
+/// fixtures: Every defined fixture
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct FixtureConfig {
+    pub fixtures: Vec<Fixture>,
+}
+
+/// An RGB colour
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// What a light file event asks a fixture to do
+/// Components without a value keep their current output.
+///
+/// intensity: The brightness of the fixture
+/// color: The colour of the fixture
+/// pan: The pan position of the fixture
+/// tilt: The tilt position of the fixture
+#[derive(Default)]
+pub struct FixtureEvent {
+    pub intensity: Option<u8>,
+    pub color: Option<Color>,
+    pub pan: Option<u8>,
+    pub tilt: Option<u8>,
+}
+
+impl FixtureConfig {
+    /// Reads the fixture definitions, falling back to none if the file does not exist
+    pub fn load() -> Result<Self, String> {
+        let path = Path::new(&*FIXTURE_CONFIG_PATH);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        serde_json::from_str(&contents).map_err(|err| err.to_string())
+    }
+
+    /// Finds a fixture by name
+    pub fn get(&self, name: &str) -> Option<&Fixture> {
+        self.fixtures.iter().find(|fixture| fixture.name == name)
+    }
+
+    /// The amount of channels needed to drive every fixture
+    pub fn channel_count(&self) -> usize {
+        self.fixtures
+            .iter()
+            .flat_map(|fixture| &fixture.components)
+            .filter(|component| component.channel >= 0)
+            .map(|component| component.channel as usize + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl Fixture {
+    /// Whether the fixture has a component of a kind
+    pub fn has(&self, kind: ComponentKind) -> bool {
+        self.components
+            .iter()
+            .any(|component| component.kind == kind)
+    }
+
+    /// The channel of the first component of a kind
+    pub fn channel_of(&self, kind: ComponentKind) -> Option<i32> {
+        self.components
+            .iter()
+            .find(|component| component.kind == kind)
+            .map(|component| component.channel)
+    }
+
+    /// The output value of a component for an event, or None if the event does not change it
+    ///
+    /// kind: The component to get the value of
+    /// event: The event being applied to the fixture
+    pub fn component_value(&self, kind: ComponentKind, event: &FixtureEvent) -> Option<u8> {
+        let color = event.color.map(|color| {
+            if self.has(ComponentKind::White) {
+                color.extract_white()
+            } else {
+                (color, 0)
+            }
+        });
+        // Without a dimmer, a plain intensity is shown as white light
+        let white_level = if self.has(ComponentKind::Intensity) {
+            None
+        } else {
+            event.intensity
+        };
+
+        match kind {
+            ComponentKind::Intensity => event.intensity.or(color.map(|_| u8::MAX)),
+            ComponentKind::Red => color.map(|(color, _)| color.r).or(white_level),
+            ComponentKind::Green => color.map(|(color, _)| color.g).or(white_level),
+            ComponentKind::Blue => color.map(|(color, _)| color.b).or(white_level),
+            ComponentKind::White => color.map(|(_, white)| white).or(white_level),
+            ComponentKind::Pan => event.pan,
+            ComponentKind::Tilt => event.tilt,
+        }
+    }
+}
+
+impl Color {
+    /// Parses a colour written as `#rrggbb` or `hsv(h, s, v)`
+    /// Hue is in degrees, saturation and value are percentages.
+    pub fn parse(value: &str) -> Option<Color> {
+        let value = value.trim();
+        if let Some(hex) = value.strip_prefix('#') {
+            if hex.len() != 6 {
+                return None;
+            }
+            let channel = |index: usize| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok();
+            return Some(Color {
+                r: channel(0)?,
+                g: channel(2)?,
+                b: channel(4)?,
+            });
+        }
+
+        let hsv = value.strip_prefix("hsv(")?.strip_suffix(')')?;
+        let mut parts = hsv.split(',').map(|part| part.trim().parse::<f32>().ok());
+        let hue = parts.next()??;
+        let saturation = parts.next()??;
+        let brightness = parts.next()??;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Color::from_hsv(hue, saturation / 100.0, brightness / 100.0))
+    }
+
+    /// Converts a colour from HSV
+    ///
+    /// hue: The hue in degrees
+    /// saturation: The saturation from 0.0 to 1.0
+    /// brightness: The value from 0.0 to 1.0
+    pub fn from_hsv(hue: f32, saturation: f32, brightness: f32) -> Color {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let saturation = saturation.clamp(0.0, 1.0);
+        let brightness = brightness.clamp(0.0, 1.0);
+
+        let chroma = brightness * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u8 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = brightness - chroma;
+        let to_byte = |value: f32| ((value + m) * 255.0).round() as u8;
+        Color {
+            r: to_byte(r),
+            g: to_byte(g),
+            b: to_byte(b),
+        }
+    }
+
+    /// The brightest component of the colour
+    pub fn brightness(&self) -> u8 {
+        self.r.max(self.g).max(self.b)
+    }
+
+    /// Splits the colour into the remaining RGB and the white level for RGBW fixtures
+    fn extract_white(&self) -> (Color, u8) {
+        let white = self.r.min(self.g).min(self.b);
+        (
+            Color {
+                r: self.r - white,
+                g: self.g - white,
+                b: self.b - white,
+            },
+            white,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+
+    fn fixture(kinds: &[ComponentKind]) -> Fixture {
+        Fixture {
+            name: "test".to_string(),
+            components: kinds
+                .iter()
+                .enumerate()
+                .map(|(channel, kind)| Component {
+                    kind: *kind,
+                    channel: channel as i32,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parse_hex() {
+        assert_eq!(Color::parse("#ff8800"), Some(color(255, 136, 0)));
+        assert_eq!(Color::parse(" #0A0b0C "), Some(color(10, 11, 12)));
+        assert_eq!(Color::parse("#ff880"), None);
+        assert_eq!(Color::parse("#ff88000"), None);
+        assert_eq!(Color::parse("#gg8800"), None);
+        assert_eq!(Color::parse("ff8800"), None);
+    }
+
+    #[test]
+    fn parse_hsv() {
+        assert_eq!(Color::parse("hsv(0, 100, 100)"), Some(color(255, 0, 0)));
+        assert_eq!(Color::parse("hsv(120,100,100)"), Some(color(0, 255, 0)));
+        assert_eq!(Color::parse("hsv(240, 100, 50)"), Some(color(0, 0, 128)));
+        assert_eq!(Color::parse("hsv(30, 100, 100)"), Some(color(255, 128, 0)));
+        assert_eq!(Color::parse("hsv(0, 0, 100)"), Some(color(255, 255, 255)));
+        assert_eq!(Color::parse("hsv(0, 100)"), None);
+        assert_eq!(Color::parse("hsv(0, 100, 100, 1)"), None);
+        assert_eq!(Color::parse("hsv(red, 100, 100)"), None);
+    }
+
+    #[test]
+    fn hsv_wraps_hue_and_clamps_the_rest() {
+        assert_eq!(Color::from_hsv(360.0, 1.0, 1.0), color(255, 0, 0));
+        assert_eq!(Color::from_hsv(-120.0, 1.0, 1.0), color(0, 0, 255));
+        assert_eq!(Color::from_hsv(300.0, 1.0, 1.0), color(255, 0, 255));
+        assert_eq!(Color::from_hsv(0.0, 2.0, 1.5), color(255, 0, 0));
+        assert_eq!(Color::from_hsv(0.0, 1.0, 0.0), color(0, 0, 0));
+    }
+
+    #[test]
+    fn white_is_extracted_for_rgbw_fixtures() {
+        use ComponentKind::*;
+        let event = FixtureEvent {
+            color: Some(color(255, 200, 100)),
+            ..Default::default()
+        };
+        let rgbw = fixture(&[Red, Green, Blue, White]);
+        assert_eq!(rgbw.component_value(Red, &event), Some(155));
+        assert_eq!(rgbw.component_value(Green, &event), Some(100));
+        assert_eq!(rgbw.component_value(Blue, &event), Some(0));
+        assert_eq!(rgbw.component_value(White, &event), Some(100));
+
+        let rgb = fixture(&[Red, Green, Blue]);
+        assert_eq!(rgb.component_value(Red, &event), Some(255));
+        assert_eq!(rgb.component_value(Blue, &event), Some(100));
+    }
+
+    #[test]
+    fn intensity_without_a_dimmer_is_white_light() {
+        use ComponentKind::*;
+        let event = FixtureEvent {
+            intensity: Some(80),
+            ..Default::default()
+        };
+        let rgb = fixture(&[Red, Green, Blue]);
+        assert_eq!(rgb.component_value(Red, &event), Some(80));
+        assert_eq!(rgb.component_value(Pan, &event), None);
+
+        let dimmed = fixture(&[Intensity, Red, Green, Blue]);
+        assert_eq!(dimmed.component_value(Intensity, &event), Some(80));
+        assert_eq!(dimmed.component_value(Red, &event), None);
+    }
+
+    #[test]
+    fn channel_count_covers_every_component() {
+        let config = FixtureConfig {
+            fixtures: vec![fixture(&[ComponentKind::Pan, ComponentKind::Tilt])],
+        };
+        assert_eq!(config.channel_count(), 2);
+        assert!(config.get("test").is_some());
+        assert!(config.get("other").is_none());
+        assert_eq!(FixtureConfig::default().channel_count(), 0);
+    }
+}
@@ -1,8 +1,23 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
+pub mod audio_clock;
 pub mod audio_player;
 pub mod bluetooth;
 pub mod constants;
+pub mod fixtures;
+pub mod library;
 pub mod lights;
+pub mod loudness;
+#[cfg(feature = "opus")]
+pub mod opus_decoder;
+pub mod outputs;
+pub mod playback;
+pub mod player_events;
+pub mod playlist;
+pub mod queue;
+pub mod shuffle;
+pub mod timing;
+pub mod transition;
+pub mod validator;
 pub use app::OpenLightsCore;
@@ -0,0 +1,469 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::app::{Notification, Timer};
+use crate::audio_player::{gather_metadata, is_audio_file, Song, SongMetadata};
+use crate::constants::LIBRARY_INDEX_PATH;
+use crate::lights::{find_light_files, song_hash, LIGHTS_DIRECTORY};
+
+/// The version of the library index, raised whenever the stored metadata changes
+/// An index with another version is thrown away and rebuilt.
+const LIBRARY_VERSION: u32 = 2;
+
+/// What the library knows about one audio file
+///
+/// modified: When the file was last modified, in nanoseconds since the Unix epoch
+/// size: The size of the file in bytes
+/// metadata: The tags and length read from the file
+/// light_files: The light files found for the song when it was last scanned
+/// light_folders_modified: When the folders light files are searched in last changed, for
+///     `light_files`
+/// song_hash: The hash light files are matched against, once the file has been hashed
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct LibraryEntry {
+    pub modified: u64,
+    pub size: u64,
+    pub metadata: SongMetadata,
+    pub light_files: Vec<PathBuf>,
+    #[serde(default)]
+    pub light_folders_modified: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song_hash: Option<String>,
+}
+
+/// An index of every song scanned so far, stored on disk so the tags of unchanged files are
+/// never read twice
+///
+/// version: The version of the index format
+/// entries: The entry of every audio file by its path
+/// changed: Whether the index differs from the one on disk
+#[derive(Serialize, Deserialize)]
+pub struct MediaLibrary {
+    version: u32,
+    entries: BTreeMap<PathBuf, LibraryEntry>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl Default for MediaLibrary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The media library shared between the audio thread and the GUI
+pub type SharedLibrary = Arc<Mutex<MediaLibrary>>;
+
+impl MediaLibrary {
+    /// Reads the library index, starting an empty one if the file does not exist or is outdated
+    pub fn load() -> Result<Self, String> {
+        let path = Path::new(&*LIBRARY_INDEX_PATH);
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let library: Self = serde_json::from_str(&contents).map_err(|err| err.to_string())?;
+        if library.version != LIBRARY_VERSION {
+            return Ok(Self::new());
+        }
+        Ok(library)
+    }
+
+    pub fn new() -> Self {
+        Self {
+            version: LIBRARY_VERSION,
+            entries: BTreeMap::new(),
+            changed: true,
+        }
+    }
+
+    /// Writes the library index to disk if it changed since it was last read or written
+    pub fn save(&mut self) -> Result<(), String> {
+        if !self.changed {
+            return Ok(());
+        }
+        let contents = serde_json::to_string(self).map_err(|err| err.to_string())?;
+        fs::write(&*LIBRARY_INDEX_PATH, contents).map_err(|err| err.to_string())?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Gathers the songs of a playlist directory
+    /// Only files that are new or were modified since the last scan have their tags read, and
+    /// songs that were removed from the directory are forgotten.
+    ///
+    /// directory: The playlist directory, holding a folder for every song
+    /// skipped: Where a message is added for every song that could not be read
+    pub fn scan(&mut self, directory: &Path, skipped: &mut Vec<String>) -> Vec<Song> {
+        let mut songs = Vec::new();
+        let mut found = HashSet::new();
+        for file in WalkDir::new(directory).min_depth(2).max_depth(3) {
+            let Ok(file) = file else {
+                continue;
+            };
+            let path = file.path();
+            if !is_audio_file(path) {
+                continue;
+            }
+            found.insert(path.to_path_buf());
+            match self.song(path) {
+                Ok(song) => songs.push(song),
+                Err(err) => skipped.push(format!("{}: {}", path.display(), err)),
+            }
+        }
+
+        let count = self.entries.len();
+        self.entries
+            .retain(|path, _| !path.starts_with(directory) || found.contains(path));
+        self.changed |= self.entries.len() != count;
+        songs
+    }
+
+    /// Gets a song from the index, only reading its tags if it is new or was modified
+    /// Light files change without touching the audio, so they are looked up again whenever a
+    /// file is added to, removed from or renamed in the folders they are searched in.
+    ///
+    /// path: The audio file of the song
+    pub fn song(&mut self, path: &Path) -> Result<Song, String> {
+        let (modified, size) = file_version(path)?;
+        let light_folders_modified = light_folders_modified(path);
+        let entry = self.entries.get(path);
+        let (metadata, song_hash) = match entry {
+            Some(entry) if entry.modified == modified && entry.size == size => {
+                (entry.metadata.clone(), entry.song_hash.clone())
+            }
+            _ => (gather_metadata(path)?, None),
+        };
+        let light_files = match entry {
+            Some(entry)
+                if light_folders_modified != 0
+                    && entry.light_folders_modified == light_folders_modified =>
+            {
+                entry.light_files.clone()
+            }
+            _ => find_light_files(path),
+        };
+
+        let song = Song::new(path, metadata.clone(), light_files.clone());
+        let entry = LibraryEntry {
+            modified,
+            size,
+            metadata,
+            light_files,
+            light_folders_modified,
+            song_hash,
+        };
+        if self.entries.get(path) != Some(&entry) {
+            self.entries.insert(path.to_path_buf(), entry);
+            self.changed = true;
+        }
+        Ok(song)
+    }
+
+    /// Forgets every song inside a directory, such as after it was deleted
+    pub fn forget(&mut self, directory: &Path) {
+        let count = self.entries.len();
+        self.entries.retain(|path, _| !path.starts_with(directory));
+        self.changed |= self.entries.len() != count;
+    }
+}
+
+/// When a file was last modified, in nanoseconds since the Unix epoch, and its size in bytes
+///
+/// path: Path to the file
+fn file_version(path: &Path) -> Result<(u64, u64), String> {
+    let file_metadata = fs::metadata(path).map_err(|err| err.to_string())?;
+    let modified = file_metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_nanos() as u64);
+    Ok((modified, file_metadata.len()))
+}
+
+/// When the folders the light files of a song are searched in last changed, in nanoseconds since
+/// the Unix epoch
+/// This is the latest change of the song folder and its `lights/` folder, or 0 if unknown.
+///
+/// song_path: Path to the audio
+fn light_folders_modified(song_path: &Path) -> u64 {
+    let Some(directory) = song_path.parent() else {
+        return 0;
+    };
+    [directory.to_path_buf(), directory.join(LIGHTS_DIRECTORY)]
+        .iter()
+        .filter_map(|folder| file_version(folder).ok())
+        .map(|(modified, _)| modified)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Hashes an audio file, keeping the hash in its library entry until the file changes
+/// Hashing reads the whole file, so the library is not locked while it runs.
+///
+/// library: The library the hash is kept in
+/// path: Path to the audio
+pub fn cached_song_hash(library: &SharedLibrary, path: &Path) -> Result<String, String> {
+    let version = file_version(path)?;
+    let is_current = |entry: &&LibraryEntry| (entry.modified, entry.size) == version;
+    let cached = library
+        .lock()
+        .unwrap()
+        .entries
+        .get(path)
+        .filter(is_current)
+        .and_then(|entry| entry.song_hash.clone());
+    if let Some(hash) = cached {
+        return Ok(hash);
+    }
+
+    let hash = song_hash(path).map_err(|err| err.to_string())?;
+    let mut library = library.lock().unwrap();
+    if let Some(entry) = library.entries.get_mut(path) {
+        if (entry.modified, entry.size) == version {
+            entry.song_hash = Some(hash.clone());
+            library.changed = true;
+        }
+    }
+    Ok(hash)
+}
+
+/// Creates a notification listing the songs of a playlist that could not be read
+///
+/// skipped: A message for every song that was skipped
+pub fn skipped_songs_notification(skipped: &[String]) -> Option<Notification> {
+    if skipped.is_empty() {
+        return None;
+    }
+    let mut message = format!(
+        "{} songs could not be read and were skipped.",
+        skipped.len()
+    );
+    for song in skipped.iter().take(3) {
+        message.push('\n');
+        message.push_str(song);
+    }
+    if skipped.len() > 3 {
+        message.push_str("\n...");
+    }
+
+    Some(Notification {
+        title: "Skipped Songs".to_string(),
+        message,
+        timer: Timer::new(Duration::from_secs(30)),
+        id: fastrand::i32(0..i32::MAX),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty folder for a test in the temporary directory
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("open_lights_{}", name));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// An entry for a file as it is on disk
+    fn entry(path: &Path) -> LibraryEntry {
+        let (modified, size) = file_version(path).unwrap();
+        LibraryEntry {
+            modified,
+            size,
+            metadata: SongMetadata::default(),
+            light_files: Vec::new(),
+            light_folders_modified: 0,
+            song_hash: None,
+        }
+    }
+
+    /// Writes a silent mono WAV file
+    ///
+    /// samples: The amount of samples, which sets the size of the file
+    fn write_wav(path: &Path, samples: u32) {
+        let data_length = samples * 2;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_length).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&44100u32.to_le_bytes());
+        bytes.extend_from_slice(&88200u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_length.to_le_bytes());
+        bytes.resize(bytes.len() + data_length as usize, 0);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, bytes).unwrap();
+    }
+
+    /// Scans a playlist directory, expecting every song to be read
+    fn scan(library: &mut MediaLibrary, directory: &Path) -> Vec<Song> {
+        let mut skipped = Vec::new();
+        let songs = library.scan(directory, &mut skipped);
+        assert!(skipped.is_empty(), "{:?}", skipped);
+        songs
+    }
+
+    #[test]
+    fn unchanged_songs_keep_their_cached_metadata() {
+        let folder = test_folder("library_unchanged");
+        let path = folder.join("Song").join("Song.wav");
+        write_wav(&path, 4410);
+
+        let mut library = MediaLibrary::new();
+        assert_eq!(scan(&mut library, &folder)[0].name, "Song");
+        library.changed = false;
+
+        // Tags are not read again, so a title only in the index is kept
+        library.entries.get_mut(&path).unwrap().metadata.title = Some("Cached".to_string());
+        assert_eq!(scan(&mut library, &folder)[0].name, "Cached");
+        assert!(!library.changed);
+    }
+
+    #[test]
+    fn changed_songs_are_read_again() {
+        let folder = test_folder("library_changed");
+        let path = folder.join("Song").join("Song.wav");
+        write_wav(&path, 4410);
+
+        let mut library = MediaLibrary::new();
+        scan(&mut library, &folder);
+        library.entries.get_mut(&path).unwrap().metadata.title = Some("Cached".to_string());
+        library.changed = false;
+
+        write_wav(&path, 8820);
+        assert_eq!(scan(&mut library, &folder)[0].name, "Song");
+        assert!(library.changed);
+
+        // The same size with another modification time is a change too
+        library.entries.get_mut(&path).unwrap().metadata.title = Some("Cached".to_string());
+        library.entries.get_mut(&path).unwrap().modified -= 1;
+        assert_eq!(scan(&mut library, &folder)[0].name, "Song");
+    }
+
+    #[test]
+    fn removed_songs_are_forgotten() {
+        let folder = test_folder("library_removed");
+        let kept = folder.join("Kept").join("Kept.wav");
+        let removed = folder.join("Removed").join("Removed.wav");
+        write_wav(&kept, 441);
+        write_wav(&removed, 441);
+        let other = test_folder("library_removed_other")
+            .join("Other")
+            .join("Other.wav");
+        write_wav(&other, 441);
+
+        let mut library = MediaLibrary::new();
+        assert_eq!(scan(&mut library, &folder).len(), 2);
+        scan(&mut library, other.parent().unwrap().parent().unwrap());
+        library.changed = false;
+
+        fs::remove_dir_all(removed.parent().unwrap()).unwrap();
+        let songs = scan(&mut library, &folder);
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0].path, kept);
+        assert!(library.changed);
+        assert!(!library.entries.contains_key(&removed));
+        // Songs of other playlists are left alone
+        assert!(library.entries.contains_key(&other));
+    }
+
+    #[test]
+    fn forget_removes_a_directory() {
+        let folder = test_folder("library_forget");
+        let first = folder.join("First").join("First.wav");
+        let second = folder.join("Second").join("Second.wav");
+        write_wav(&first, 441);
+        write_wav(&second, 441);
+
+        let mut library = MediaLibrary::new();
+        scan(&mut library, &folder);
+        library.changed = false;
+
+        library.forget(&folder.join("Missing"));
+        assert!(!library.changed);
+        library.forget(first.parent().unwrap());
+        assert!(library.changed);
+        assert!(!library.entries.contains_key(&first));
+        assert!(library.entries.contains_key(&second));
+    }
+
+    #[test]
+    fn light_files_are_cached_until_their_folder_changes() {
+        let folder = test_folder("library_light_files");
+        let path = folder.join("Song").join("Song.wav");
+        write_wav(&path, 441);
+
+        let mut library = MediaLibrary::new();
+        assert!(scan(&mut library, &folder)[0].light_files.is_empty());
+
+        // The light files are not looked up again while the folder is unchanged
+        let cached = vec![PathBuf::from("Cached.json")];
+        library.entries.get_mut(&path).unwrap().light_files = cached.clone();
+        assert_eq!(scan(&mut library, &folder)[0].light_files, cached);
+
+        let lights = path.parent().unwrap().join(LIGHTS_DIRECTORY);
+        fs::create_dir_all(&lights).unwrap();
+        fs::write(lights.join("Song.json"), "{}").unwrap();
+        assert_eq!(
+            scan(&mut library, &folder)[0].light_files,
+            vec![lights.join("Song.json")]
+        );
+    }
+
+    #[test]
+    fn song_hash_is_cached_until_the_file_changes() {
+        let folder = test_folder("library_song_hash");
+        let path = folder.join("Song.mp3");
+        fs::write(&path, b"first version").unwrap();
+
+        let mut library = MediaLibrary::new();
+        library.entries.insert(path.clone(), entry(&path));
+        library.changed = false;
+        let library: SharedLibrary = Arc::new(Mutex::new(library));
+
+        let hash = cached_song_hash(&library, &path).unwrap();
+        assert_eq!(hash, song_hash(&path).unwrap());
+        {
+            let mut library = library.lock().unwrap();
+            assert!(library.changed);
+            // A stored hash is used without reading the file again
+            library.entries.get_mut(&path).unwrap().song_hash = Some("cached".to_string());
+        }
+        assert_eq!(cached_song_hash(&library, &path).unwrap(), "cached");
+
+        fs::write(&path, b"second, longer version").unwrap();
+        let hash = cached_song_hash(&library, &path).unwrap();
+        assert_eq!(hash, song_hash(&path).unwrap());
+        // The entry is out of date, so the hash is not stored in it
+        let library = library.lock().unwrap();
+        assert_eq!(library.entries[&path].song_hash.as_deref(), Some("cached"));
+    }
+
+    #[test]
+    fn song_hash_of_songs_outside_the_library() {
+        let folder = test_folder("library_song_hash_outside");
+        let path = folder.join("Song.mp3");
+        fs::write(&path, b"audio").unwrap();
+        let library: SharedLibrary = Arc::new(Mutex::new(MediaLibrary::new()));
+        assert_eq!(
+            cached_song_hash(&library, &path).unwrap(),
+            song_hash(&path).unwrap()
+        );
+        assert!(library.lock().unwrap().entries.is_empty());
+        assert!(cached_song_hash(&library, &folder.join("Missing.mp3")).is_err());
+    }
+}
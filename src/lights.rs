@@ -1,38 +1,66 @@
 use std::collections::HashMap;
+use std::fs;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 
-#[cfg(not(target_arch = "x86_64"))]
-use rppal::gpio::Gpio;
-#[cfg(not(target_arch = "x86_64"))]
-use rppal::gpio::OutputPin;
+use crate::audio_clock::{ClockReader, SharedAudioClock};
+use crate::audio_player::is_audio_file;
+use crate::constants::AUDIO_EXTENSIONS;
+use crate::fixtures::{Color, FixtureConfig, FixtureEvent};
+use crate::outputs::{SharedLatency, SharedLightOutput};
+use crate::timing::SharedTimingRecorder;
+
+/// How often the intensity of fading channels is updated
+const FADE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The longest the light thread sleeps before checking whether it was stopped or reset
+const MAX_WAIT: Duration = Duration::from_millis(20);
+
+/// How far the position may move between two updates before it is treated as a jump,
+/// after which every channel is chased to the new position
+const JUMP_THRESHOLD: Duration = Duration::from_millis(250);
+
+/// How long before an event the light thread stops sleeping and waits actively,
+/// since sleeping can overshoot by about a millisecond
+const SPIN_WAIT: Duration = Duration::from_millis(1);
 
 /// Creates a new thread for reading light data
-/// The lighting thread is in charge of toggling lights when the light file specifies.
+/// The lighting thread is in charge of toggling lights when the light file specifies,
+/// and of interpolating the intensity of channels that are fading.
+/// Time is read from the samples played by the audio, and the thread sleeps until the next
+/// event is due instead of polling.
+/// Latency offsets are read on every update, so they can be tuned while the audio plays.
+/// Whenever playback jumps, resumes or is reset, every channel is set to the status it should
+/// have at the new position instead of waiting for its next event.
 ///
-/// song_path: The current path of the audio being played (to get the light file)
-/// millisecond_position: The current position in the audio
+/// light_data: The data of every channel, from `gather_light_data` or `calibration_light_data`
+/// audio_clock: The clock counting the samples of the audio being played
+/// playing: Whether the audio is playing
 /// toggle: Whether the light thread should be executing
 /// active: If the thread is current executing
-/// reset: If the thread should reset its data
-/// gpio_pins: A hashmap of all channels
+/// reset: If the thread should chase every channel to the current position
+/// light_output: The output that channel changes are sent to
+/// latency: The offsets between the audio and each channel
+/// timing_recorder: Records when each event reached the output
+#[allow(clippy::too_many_arguments)]
 pub fn start_light_thread(
-    song_path: &Path,
-    millisecond_position: Arc<AtomicU64>,
+    mut light_data: Vec<ChannelData>,
+    audio_clock: SharedAudioClock,
+    playing: Arc<AtomicBool>,
     toggle: Arc<AtomicBool>,
     active: Arc<AtomicBool>,
     reset: Arc<AtomicBool>,
-    #[cfg(not(target_arch = "x86_64"))] gpio_pins: Arc<Mutex<HashMap<i32, OutputPin>>>,
+    light_output: SharedLightOutput,
+    latency: SharedLatency,
+    timing_recorder: SharedTimingRecorder,
 ) {
-    let mut light_data = gather_light_data(song_path.to_string_lossy().to_string());
-
     if !light_data.is_empty() {
         while toggle.load(Ordering::Relaxed) {
             // Ensure there aren't duplicate threads
@@ -40,170 +68,1174 @@ pub fn start_light_thread(
         }
 
         active.store(true, Ordering::Relaxed);
+        let mut clock_reader = ClockReader::new(audio_clock);
+        let mut last_position: Option<Duration> = None;
+        let mut was_playing = false;
         thread::spawn(move || loop {
             if toggle.load(Ordering::Relaxed) {
                 active.store(false, Ordering::Relaxed);
                 toggle.store(false, Ordering::Relaxed);
                 break;
             }
-            if reset.load(Ordering::Relaxed) {
-                reset.store(false, Ordering::Relaxed);
+
+            {
+                let latency = latency.lock().unwrap();
+                let backend = light_output.lock().unwrap().backend();
                 for channel_data in &mut light_data {
-                    channel_data.index = 0;
-                }
-                #[cfg(not(target_arch = "x86_64"))]
-                {
-                    let mut pin1 = gpio_pins.lock().unwrap();
-                    all_off(&mut *pin1);
+                    channel_data.latency = latency.offset(backend, &channel_data.channels);
                 }
             }
-            for channel_data in &mut light_data {
-                if let Some(target_time) = channel_data.data.get(channel_data.index) {
-                    if target_time.timestamp <= millisecond_position.load(Ordering::Relaxed) as i32
-                    {
-                        for _channel in &channel_data.channels {
-                            #[cfg(target_arch = "x86_64")]
-                            println!(
-                                "Correct: {}; Actual: {}",
-                                target_time.timestamp,
-                                millisecond_position.load(Ordering::Relaxed)
-                            );
-
-                            #[cfg(not(target_arch = "x86_64"))]
-                            let mut pin = gpio_pins.lock().unwrap();
-                            #[cfg(not(target_arch = "x86_64"))]
-                            interface_gpio(
-                                pin.get_mut(&(*_channel as i32)).unwrap(),
-                                &target_time.light_type,
-                            );
-                        }
-                        channel_data.index += 1;
+
+            let playing = playing.load(Ordering::Relaxed);
+            let position = clock_reader.position(playing);
+            let jumped = match last_position {
+                Some(last) if position > last => position - last > JUMP_THRESHOLD,
+                Some(last) => last - position > JUMP_THRESHOLD,
+                // The thread was started partway through the audio
+                None => position > JUMP_THRESHOLD,
+            };
+            let resumed = playing && !was_playing && last_position.is_some();
+            let reset = reset.swap(false, Ordering::Relaxed);
+            last_position = Some(position);
+            was_playing = playing;
+
+            if reset || jumped || resumed {
+                chase_to_position(&mut light_data, position, &light_output);
+            } else {
+                let mut changed = false;
+                for channel_data in &mut light_data {
+                    if let Some(light_type) = channel_data.update(position.as_millis() as i32) {
+                        light_output
+                            .lock()
+                            .unwrap()
+                            .set_channels(&channel_data.channels, light_type);
+                        changed = true;
                     }
                 }
+                if changed {
+                    light_output.lock().unwrap().flush();
+                }
             }
+            record_timing(
+                &mut light_data,
+                &mut clock_reader,
+                playing,
+                &timing_recorder,
+            );
 
-            thread::sleep(Duration::from_millis(5));
+            wait_for_next_event(&light_data, position, playing);
         });
     }
 }
 
+/// Sets every channel to the status it should have at a position and applies it immediately
+///
+/// light_data: The data of every channel
+/// position: The position in the audio that playback jumped to
+/// light_output: The output that channel changes are sent to
+fn chase_to_position(
+    light_data: &mut [ChannelData],
+    position: Duration,
+    light_output: &SharedLightOutput,
+) {
+    let mut output = light_output.lock().unwrap();
+    for channel_data in light_data {
+        let light_type = channel_data.chase(position.as_millis() as i32);
+        output.set_channels(&channel_data.channels, light_type);
+    }
+    output.flush();
+}
+
+/// Records the timing of every event fired by the last update
+///
+/// light_data: The data of every channel
+/// clock_reader: The reader of the audio clock, to know when the output was flushed
+/// playing: Whether the audio is playing
+/// timing_recorder: The recorder of the song
+fn record_timing(
+    light_data: &mut [ChannelData],
+    clock_reader: &mut ClockReader,
+    playing: bool,
+    timing_recorder: &SharedTimingRecorder,
+) {
+    if !light_data
+        .iter()
+        .any(|channel_data| channel_data.fired.is_some())
+    {
+        return;
+    }
+
+    let actual = clock_reader.position(playing);
+    let mut recorder = timing_recorder.lock().unwrap();
+    for channel_data in light_data {
+        if let Some(fired) = channel_data.fired.take() {
+            recorder.record(&channel_data.channels, fired.due, actual);
+            if fired.dropped > 0 {
+                recorder.record_dropped(fired.dropped);
+            }
+        }
+    }
+}
+
+/// Sleeps until the next event of any channel is due
+/// Fading channels are woken up every `FADE_INTERVAL`, and no wait is longer than `MAX_WAIT`.
+///
+/// light_data: The data of every channel
+/// position: The current position in the audio
+/// playing: Whether the audio is playing
+fn wait_for_next_event(light_data: &[ChannelData], position: Duration, playing: bool) {
+    if !playing {
+        thread::sleep(MAX_WAIT);
+        return;
+    }
+
+    let mut wait = light_data
+        .iter()
+        .filter_map(ChannelData::next_due)
+        .min()
+        .map(|timestamp| Duration::from_millis(timestamp.max(0) as u64).saturating_sub(position))
+        .unwrap_or(MAX_WAIT);
+    if light_data.iter().any(ChannelData::is_fading) {
+        wait = wait.min(FADE_INTERVAL);
+    }
+
+    if wait >= MAX_WAIT {
+        thread::sleep(MAX_WAIT);
+        return;
+    }
+
+    // Sleep for most of the wait, then spin so the event is not late
+    let deadline = Instant::now() + wait;
+    if wait > SPIN_WAIT {
+        thread::sleep(wait - SPIN_WAIT);
+    }
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
 /// The status of the channel
 ///
-/// On: Powered on
+/// On: Powered on at full intensity
 /// Off: Powered off
+/// Dimmed: Powered on at an intensity between 1 and 254
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum LightType {
     On,
     Off,
+    Dimmed(u8),
+}
+
+impl LightType {
+    /// Gets the status for an intensity from 0 to 255
+    pub fn from_intensity(intensity: u8) -> Self {
+        match intensity {
+            0 => LightType::Off,
+            u8::MAX => LightType::On,
+            _ => LightType::Dimmed(intensity),
+        }
+    }
+
+    /// The intensity of the status from 0 to 255
+    pub fn intensity(&self) -> u8 {
+        match self {
+            LightType::On => u8::MAX,
+            LightType::Off => 0,
+            LightType::Dimmed(intensity) => *intensity,
+        }
+    }
+
+    /// Whether the channel is powered at all
+    pub fn is_on(&self) -> bool {
+        self.intensity() > 0
+    }
+}
+
+/// The light file format version written by OpenLights
+pub const LIGHT_FILE_VERSION: u32 = 2;
+
+/// A light file in the v2 format
+/// Version 1 files are converted to this structure when they are loaded.
+///
+/// Example:
+/// ```json
+/// {
+///     "format_version": 2,
+///     "metadata": {
+///         "song_hash": "fnv1a64:9c2b6f1e3a7d5c40",
+///         "duration": 183400,
+///         "author": "Jane Doe",
+///         "channel_count": 16
+///     },
+///     "tracks": [
+///         {
+///             "channels": [0, 1],
+///             "events": [
+///                 { "time": 1000, "intensity": 255 },
+///                 { "time": 3000, "intensity": 0, "fade": 1500 }
+///             ]
+///         },
+///         {
+///             "fixture": "flood",
+///             "events": [{ "time": 2000, "color": "#ff8800" }]
+///         }
+///     ]
+/// }
+/// ```
+///
+/// format_version: The version of the format, 1 for converted v1 files
+/// metadata: Information about the song and the show
+/// tracks: The events of every channel group and fixture
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LightFile {
+    pub format_version: u32,
+    #[serde(default)]
+    pub metadata: LightMetadata,
+    pub tracks: Vec<LightTrack>,
+}
+
+/// Information about the song a light file was made for
+///
+/// song_hash: The hash of the audio file from `song_hash`
+/// duration: The length of the song in milliseconds
+/// author: The person who made the show
+/// channel_count: The amount of channels the show was made for
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct LightMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub song_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_count: Option<u32>,
 }
 
-/// The data structure of a light file
+/// The events sent to a group of channels or a fixture
+///
+/// channels: The channels the events are sent to
+/// fixture: The fixture the events are sent to, instead of channels
+/// events: The events of the track
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LightTrack {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixture: Option<String>,
+    pub events: Vec<LightEvent>,
+}
+
+/// A change of a track at a point in the song
+/// Values that are not set keep their current output.
+///
+/// time: The position in the song in milliseconds
+/// intensity: The new intensity from 0 to 255
+/// color: The new colour of a fixture as `#rrggbb` or `hsv(h, s, v)`
+/// pan: The new pan position of a fixture
+/// tilt: The new tilt position of a fixture
+/// fade: How long to ramp from the current values, in milliseconds
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct LightEvent {
+    pub time: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub intensity: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pan: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tilt: Option<u8>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub fade: u32,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+impl LightEvent {
+    /// What the event asks a fixture to do
+    fn fixture_event(&self) -> FixtureEvent {
+        FixtureEvent {
+            intensity: self.intensity,
+            color: self.color.as_deref().and_then(Color::parse),
+            pan: self.pan,
+            tilt: self.tilt,
+        }
+    }
+}
+
+/// The data structure of a v1 light file
 /// Contains all the channels and their respective data
 #[derive(Serialize, Deserialize, Debug)]
 struct Data {
     #[serde(flatten)]
-    fields: HashMap<String, HashMap<String, i8>>,
+    fields: HashMap<String, HashMap<String, EventValue>>,
 }
 
-/// The data structure of a light file
+/// The value of a v1 light file event
 ///
-/// Example:
-///     "1000, 1" # 1000ms in, turn on
+/// Intensity: Changes the channel to an intensity instantly
+/// Color: Changes a fixture to a colour written as `#rrggbb` or `hsv(h, s, v)` instantly
+/// Event: Changes any of the intensity, colour, pan and tilt,
+///     ramping from the current values over `fade` milliseconds
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub(crate) enum EventValue {
+    Intensity(u8),
+    Color(String),
+    Event {
+        intensity: Option<u8>,
+        color: Option<String>,
+        pan: Option<u8>,
+        tilt: Option<u8>,
+        #[serde(default)]
+        fade: u32,
+    },
+}
+
+/// An event of a channel, ready to be scheduled
+///
+/// timestamp: The position in the song in milliseconds
+/// light_type: The status of the channel after the event
+/// fade: How long to ramp from the current status, in milliseconds
 struct LightData {
     timestamp: i32,
     light_type: LightType,
+    fade: u32,
 }
 
 /// The data structure of a channel
-struct ChannelData {
-    channels: Vec<i8>,
+///
+/// channels: The outputs this data is sent to
+/// data: The events of the channel, sorted by timestamp
+/// index: The next event to fire
+/// light_type: The status last sent to the outputs
+/// fade: The fade currently in progress
+/// latency: The offset of the channel in milliseconds, positive to fire later
+/// fired: The events fired by the last update, until they are recorded
+pub struct ChannelData {
+    channels: Vec<i32>,
     data: Vec<LightData>,
     index: usize,
+    light_type: LightType,
+    fade: Option<Fade>,
+    latency: i32,
+    fired: Option<FiredEvents>,
 }
 
-/// Gets the Light Data for an audio path as a vector
+/// The events fired by one update of a channel
 ///
-/// song_path: Path to the audio
-fn gather_light_data(song_path: String) -> Vec<ChannelData> {
-    let path_string = song_path.replace("wav", "json");
-    let path = PathBuf::from(path_string);
+/// due: The position in the audio the last event was due at, including latency
+/// dropped: The amount of earlier events that were overtaken by the last event
+struct FiredEvents {
+    due: i32,
+    dropped: usize,
+}
 
-    let file = match File::open(path) {
-        Ok(file) => file,
-        Err(_) => return Vec::new(),
-    };
-    let reader = BufReader::new(file);
-    let parsed_data: Data = serde_json::from_reader(reader).unwrap();
+/// A fade in progress
+///
+/// start: The timestamp the fade started at
+/// duration: The length of the fade in milliseconds
+/// from: The intensity at the start of the fade
+/// to: The intensity at the end of the fade
+struct Fade {
+    start: i32,
+    duration: u32,
+    from: u8,
+    to: u8,
+}
+
+impl Fade {
+    /// The intensity of the fade at a position, or None once the fade has completed
+    ///
+    /// position: The current position in the audio in milliseconds
+    fn intensity_at(&self, position: i32) -> Option<u8> {
+        let elapsed = position - self.start;
+        if elapsed >= self.duration as i32 {
+            return None;
+        }
+        let progress = elapsed.max(0) as f32 / self.duration as f32;
+        let intensity = self.from as f32 + (self.to as f32 - self.from as f32) * progress;
+        Some(intensity.round() as u8)
+    }
+}
+
+impl ChannelData {
+    fn new(channels: Vec<i32>, data: Vec<LightData>) -> Self {
+        Self {
+            channels,
+            data,
+            index: 0,
+            light_type: LightType::Off,
+            fade: None,
+            latency: 0,
+            fired: None,
+        }
+    }
+
+    /// Fires every event that is due and advances any fade
+    /// Returns the new status if the output needs changing.
+    ///
+    /// position: The current position in the audio in milliseconds
+    fn update(&mut self, position: i32) -> Option<LightType> {
+        let position = position - self.latency;
+        let target = self.fire_due_events(position, true);
+        let target = self.advance_fade(position, target);
+
+        if target == self.light_type {
+            None
+        } else {
+            self.light_type = target;
+            Some(target)
+        }
+    }
+
+    /// Jumps to a position, reconstructing the status the channel should have there
+    /// Every earlier event is applied without being recorded, and a fade that is in progress
+    /// at the position continues from its midpoint.
+    /// Returns the status of the channel at the position.
+    ///
+    /// position: The position in the audio in milliseconds
+    fn chase(&mut self, position: i32) -> LightType {
+        self.reset();
+        let position = position - self.latency;
+        let target = self.fire_due_events(position, false);
+        self.light_type = self.advance_fade(position, target);
+        self.light_type
+    }
+
+    /// Applies every event up to a position
+    /// Returns the status set by the last event, or the current status if no event was due.
+    ///
+    /// position: The position in the audio in milliseconds, after the latency is applied
+    /// record: Whether the events are noted for the timing recorder
+    fn fire_due_events(&mut self, position: i32, record: bool) -> LightType {
+        let mut target = self.light_type;
+
+        while let Some(event) = self.data.get(self.index) {
+            if event.timestamp > position {
+                break;
+            }
+            if record {
+                self.fired = Some(match self.fired.take() {
+                    Some(fired) => FiredEvents {
+                        due: event.timestamp + self.latency,
+                        dropped: fired.dropped + 1,
+                    },
+                    None => FiredEvents {
+                        due: event.timestamp + self.latency,
+                        dropped: 0,
+                    },
+                });
+            }
+            if event.fade > 0 {
+                self.fade = Some(Fade {
+                    start: event.timestamp,
+                    duration: event.fade,
+                    from: target.intensity(),
+                    to: event.light_type.intensity(),
+                });
+            } else {
+                self.fade = None;
+            }
+            target = event.light_type;
+            self.index += 1;
+        }
+        target
+    }
+
+    /// Moves any fade in progress forward to a position
+    /// Returns the status of the channel, which is the target unless a fade is in progress.
+    ///
+    /// position: The position in the audio in milliseconds, after the latency is applied
+    /// target: The status set by the last event
+    fn advance_fade(&mut self, position: i32, target: LightType) -> LightType {
+        let Some(fade) = &self.fade else {
+            return target;
+        };
+        match fade.intensity_at(position) {
+            Some(intensity) => LightType::from_intensity(intensity),
+            None => {
+                let target = LightType::from_intensity(fade.to);
+                self.fade = None;
+                target
+            }
+        }
+    }
+
+    /// The position in the audio at which the next event fires, including the latency
+    fn next_due(&self) -> Option<i32> {
+        self.data
+            .get(self.index)
+            .map(|event| event.timestamp + self.latency)
+    }
+
+    /// Whether the channel is ramping between two intensities
+    fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    /// Returns to the start of the audio with the channel off
+    fn reset(&mut self) {
+        self.index = 0;
+        self.light_type = LightType::Off;
+        self.fade = None;
+        self.fired = None;
+    }
+}
+
+/// Gets the Light Data of a light file as a vector
+/// Fixture events are split into one channel per fixture component.
+///
+/// path: Path to the light file
+pub fn gather_light_data(path: &Path) -> Result<Vec<ChannelData>, String> {
+    let light_file = load_light_file(path)?;
+    let fixtures = FixtureConfig::load().unwrap_or_default();
 
     let mut data_vec: Vec<ChannelData> = Vec::new();
 
-    for (channel, light_data) in parsed_data.fields {
-        let mut light_data_vec: Vec<LightData> = Vec::new();
-        for (timestamp_str, light_type_pre) in light_data {
-            let timestamp = timestamp_str.parse::<i32>().unwrap();
-            let light_type = match light_type_pre {
-                0 => LightType::Off,
-                1 => LightType::On,
-                _ => LightType::Off,
+    for mut track in light_file.tracks {
+        track.events.sort_by_key(|event| event.time);
+
+        if let Some(name) = &track.fixture {
+            let Some(fixture) = fixtures.get(name) else {
+                continue;
             };
-            light_data_vec.push(LightData {
-                timestamp,
-                light_type,
-            })
+            let events: Vec<(i32, FixtureEvent, u32)> = track
+                .events
+                .iter()
+                .map(|event| (event.time, event.fixture_event(), event.fade))
+                .collect();
+            for component in &fixture.components {
+                let light_data_vec = events
+                    .iter()
+                    .filter_map(|(timestamp, event, fade)| {
+                        let intensity = fixture.component_value(component.kind, event)?;
+                        Some(LightData {
+                            timestamp: *timestamp,
+                            light_type: LightType::from_intensity(intensity),
+                            fade: *fade,
+                        })
+                    })
+                    .collect();
+                data_vec.push(ChannelData::new(vec![component.channel], light_data_vec));
+            }
+        } else {
+            let light_data_vec = track
+                .events
+                .iter()
+                .filter_map(|event| {
+                    let intensity = event.intensity.or(event
+                        .color
+                        .as_deref()
+                        .and_then(Color::parse)
+                        .map(|color| color.brightness()))?;
+                    Some(LightData {
+                        timestamp: event.time,
+                        light_type: LightType::from_intensity(intensity),
+                        fade: event.fade,
+                    })
+                })
+                .collect();
+            data_vec.push(ChannelData::new(track.channels, light_data_vec));
         }
-        light_data_vec.sort_by_key(|ld| ld.timestamp);
-        data_vec.push(ChannelData {
-            channels: parse_channels(channel),
-            data: light_data_vec,
-            index: 0,
+    }
+    Ok(data_vec)
+}
+
+/// Creates Light Data that flashes channels on every beat of a click track
+/// Used to tune the latency offsets by eye and ear.
+///
+/// channels: The channels to flash
+/// beat_interval: The time between two beats
+/// flash_length: How long the channels stay on for each beat
+/// duration: How long the flashing lasts
+pub fn calibration_light_data(
+    channels: Vec<i32>,
+    beat_interval: Duration,
+    flash_length: Duration,
+    duration: Duration,
+) -> Vec<ChannelData> {
+    let beat_interval = beat_interval.as_millis() as i32;
+    let flash_length = flash_length.as_millis() as i32;
+    let duration = duration.as_millis() as i32;
+
+    let mut light_data_vec = Vec::new();
+    for beat in (0..duration).step_by(beat_interval.max(1) as usize) {
+        light_data_vec.push(LightData {
+            timestamp: beat,
+            light_type: LightType::On,
+            fade: 0,
+        });
+        light_data_vec.push(LightData {
+            timestamp: beat + flash_length,
+            light_type: LightType::Off,
+            fade: 0,
+        });
+    }
+    vec![ChannelData::new(channels, light_data_vec)]
+}
+
+/// The folder next to the audio where light files can also be stored
+pub const LIGHTS_DIRECTORY: &str = "lights";
+
+/// Finds every light file of a song
+/// Light files share the stem of the audio and are searched for next to the audio first,
+/// then in the `lights/` folder. Alternative light tracks add a name between the stem and
+/// the extension, such as `Song.calm.json`. The default light track of each folder comes
+/// before its alternatives. Light files of other songs whose name starts the same way, such as
+/// `Song.live.json` of `Song.live.mp3`, are left out.
+///
+/// song_path: Path to the audio
+pub fn find_light_files(song_path: &Path) -> Vec<PathBuf> {
+    let (Some(directory), Some(stem)) = (song_path.parent(), song_path.file_stem()) else {
+        return Vec::new();
+    };
+    let stem = stem.to_string_lossy();
+    let prefix = format!("{}.", stem);
+
+    // The stems of the other songs in the folder that start with the stem of this song
+    let other_songs: Vec<String> = fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| is_audio_file(path))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .filter(|other| other.starts_with(&prefix))
+        .collect();
+    let belongs_to_other_song = |light_stem: &str| {
+        other_songs.iter().any(|other| {
+            light_stem == other
+                || light_stem
+                    .strip_prefix(other.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
         })
+    };
+
+    let mut light_files = Vec::new();
+    for folder in [directory.to_path_buf(), directory.join(LIGHTS_DIRECTORY)] {
+        let default = folder.join(format!("{}.json", stem));
+        if default.is_file() {
+            light_files.push(default.clone());
+        }
+
+        let Ok(entries) = fs::read_dir(&folder) else {
+            continue;
+        };
+        let mut alternatives: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path != &default
+                    && path.is_file()
+                    && path
+                        .extension()
+                        .is_some_and(|extension| extension == "json")
+                    && path.file_stem().is_some_and(|light_stem| {
+                        let light_stem = light_stem.to_string_lossy();
+                        light_stem.starts_with(&prefix) && !belongs_to_other_song(&light_stem)
+                    })
+            })
+            .collect();
+        alternatives.sort();
+        light_files.extend(alternatives);
+    }
+    light_files
+}
+
+/// Finds the song a light file belongs to, the reverse of `find_light_files`
+/// The song is searched for next to the light file, and next to the `lights/` folder the light
+/// file is in. Names of alternative light tracks are dropped from the stem one at a time, so
+/// `Song.calm.json` finds `Song.mp3`.
+///
+/// light_file: Path to the light file
+pub fn find_song(light_file: &Path) -> Option<PathBuf> {
+    let directory = light_file.parent()?;
+    let mut folders = vec![directory];
+    if directory
+        .file_name()
+        .is_some_and(|folder| folder == LIGHTS_DIRECTORY)
+    {
+        folders.extend(directory.parent());
+    }
+
+    let stem = light_file.file_stem()?.to_string_lossy().into_owned();
+    let mut song_stem = Some(stem.as_str());
+    while let Some(candidate) = song_stem {
+        for folder in &folders {
+            for extension in AUDIO_EXTENSIONS {
+                let song = folder.join(format!("{}.{}", candidate, extension));
+                if song.is_file() {
+                    return Some(song);
+                }
+            }
+        }
+        song_stem = candidate.rsplit_once('.').map(|(rest, _)| rest);
+    }
+    None
+}
+
+/// Gets the name of a light track to show to the user
+/// The default light track is called "Default", while alternatives use the name in their file name.
+///
+/// song_path: Path to the audio
+/// light_file: Path to one of the light files found by `find_light_files`, or set by a playlist file
+pub fn light_track_name(song_path: &Path, light_file: &Path) -> String {
+    let song_stem = song_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let light_stem = light_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let name = match light_stem.strip_prefix(&format!("{}.", song_stem)) {
+        Some(alternative) => alternative.replace('_', " "),
+        None if light_stem == song_stem => "Default".to_string(),
+        // A light file set by a playlist file can have any name
+        None => light_stem.replace('_', " "),
+    };
+    let in_lights_directory = light_file
+        .parent()
+        .and_then(Path::file_name)
+        .is_some_and(|folder| folder == LIGHTS_DIRECTORY)
+        && light_file.parent() != song_path.parent();
+    if in_lights_directory {
+        format!("{} ({}/)", name, LIGHTS_DIRECTORY)
+    } else {
+        name
+    }
+}
+
+/// Reads a light file of any supported version
+///
+/// path: Path to the light file
+pub fn load_light_file(path: &Path) -> Result<LightFile, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let value: serde_json::Value =
+        serde_json::from_reader(BufReader::new(file)).map_err(|err| err.to_string())?;
+    parse_light_file(value)
+}
+
+/// Converts parsed JSON of any supported version into a light file
+///
+/// value: The JSON contents of the light file
+pub fn parse_light_file(value: serde_json::Value) -> Result<LightFile, String> {
+    match value.get("format_version") {
+        None => {
+            let data: Data = serde_json::from_value(value).map_err(|err| err.to_string())?;
+            convert_v1(data)
+        }
+        Some(version) if version.as_u64() == Some(LIGHT_FILE_VERSION as u64) => {
+            serde_json::from_value(value).map_err(|err| err.to_string())
+        }
+        Some(version) => Err(format!("Unsupported light file version {}", version)),
+    }
+}
+
+/// Converts a v1 light file into the current format
+///
+/// data: The v1 light file
+fn convert_v1(data: Data) -> Result<LightFile, String> {
+    let mut tracks = Vec::new();
+    for (channel, light_data) in data.fields {
+        let mut events = Vec::new();
+        for (timestamp_str, value) in light_data {
+            let time = timestamp_str.trim().parse::<i32>().map_err(|_| {
+                format!("Invalid timestamp \"{}\" in \"{}\"", timestamp_str, channel)
+            })?;
+            events.push(parse_event(time, value));
+        }
+        events.sort_by_key(|event| event.time);
+
+        let track = match channel.strip_prefix('@') {
+            Some(name) => LightTrack {
+                channels: Vec::new(),
+                fixture: Some(name.trim().to_string()),
+                events,
+            },
+            None => LightTrack {
+                channels: parse_channels(channel),
+                fixture: None,
+                events,
+            },
+        };
+        tracks.push(track);
+    }
+
+    Ok(LightFile {
+        format_version: 1,
+        metadata: LightMetadata::default(),
+        tracks,
+    })
+}
+
+/// Converts a v1 event value into an event
+///
+/// time: The timestamp of the event
+/// value: The value stored in the light file
+pub(crate) fn parse_event(time: i32, value: EventValue) -> LightEvent {
+    match value {
+        EventValue::Intensity(intensity) => LightEvent {
+            time,
+            intensity: Some(parse_v1_intensity(intensity).intensity()),
+            ..Default::default()
+        },
+        EventValue::Color(color) => LightEvent {
+            time,
+            color: Some(color),
+            ..Default::default()
+        },
+        EventValue::Event {
+            intensity,
+            color,
+            pan,
+            tilt,
+            fade,
+        } => LightEvent {
+            time,
+            intensity,
+            color,
+            pan,
+            tilt,
+            fade,
+        },
+    }
+}
+
+/// Gets the status of a plain number in a v1 light file
+/// Values are intensities from 0 to 255, except 1 which keeps its original meaning of fully on.
+/// Only plain numbers of v1 files are read like this. Intensities of event objects and of v2
+/// files are always 0 to 255, so 1 is the dimmest setting there.
+///
+/// value: The value stored in the light file
+fn parse_v1_intensity(value: u8) -> LightType {
+    match value {
+        1 => LightType::On,
+        _ => LightType::from_intensity(value),
     }
-    data_vec
 }
 
 /// Gets the channels from a string
 ///
 /// channels_str: The string containing a list of channels
-fn parse_channels(channels_str: String) -> Vec<i8> {
+fn parse_channels(channels_str: String) -> Vec<i32> {
     channels_str
         .split(',')
-        .filter_map(|s| s.trim().parse::<i8>().ok())
+        .filter_map(|s| s.trim().parse::<i32>().ok())
         .collect()
 }
 
-/// Sets the output for channels
+/// Hashes an audio file so a light file can be matched to the exact song it was made for
+/// Uses 64 bit FNV-1a, which is stable across platforms and releases.
 ///
-/// pin: The GPIO pin to interface with
-/// light_type: Whether to turn it on or off
-#[cfg(not(target_arch = "x86_64"))]
-pub fn interface_gpio(pin: &mut OutputPin, light_type: &LightType) {
-    match light_type {
-        LightType::On => {
-            pin.set_high();
+/// path: Path to the audio
+pub fn song_hash(path: &Path) -> std::io::Result<String> {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hash = OFFSET_BASIS;
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
         }
-        LightType::Off => {
-            pin.set_low();
+        for byte in &buffer[..read] {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(PRIME);
         }
     }
+    Ok(format!("fnv1a64:{:016x}", hash))
 }
 
-/// Gets a hashmap of GPIO pins
-#[cfg(not(target_arch = "x86_64"))]
-pub fn get_gpio_map() -> HashMap<i32, OutputPin> {
-    let mut map = HashMap::new();
-    for i in 0..16 {
-        let gpio = Gpio::new().unwrap();
-        let out = gpio.get(i).unwrap().into_output();
-        map.insert(i as i32, out);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty folder for a test in the temporary directory
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("open_lights_{}", name));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(folder.join(LIGHTS_DIRECTORY)).unwrap();
+        folder
     }
-    map
-}
 
-/// Turns off all GPIO pins
-///
-/// pins: hashmap of GPIO pins
-#[cfg(not(target_arch = "x86_64"))]
-pub fn all_off(pins: &mut HashMap<i32, OutputPin>) {
-    for index in 0..pins.len() {
-        interface_gpio(pins.get_mut(&(index as i32)).unwrap(), &LightType::Off);
+    /// A channel with events of (timestamp, intensity, fade)
+    fn channel(events: &[(i32, u8, u32)]) -> ChannelData {
+        let data = events
+            .iter()
+            .map(|(timestamp, intensity, fade)| LightData {
+                timestamp: *timestamp,
+                light_type: LightType::from_intensity(*intensity),
+                fade: *fade,
+            })
+            .collect();
+        ChannelData::new(vec![0], data)
+    }
+
+    #[test]
+    fn fade_interpolates_linearly() {
+        let fade = Fade {
+            start: 1000,
+            duration: 1000,
+            from: 0,
+            to: 200,
+        };
+        assert_eq!(fade.intensity_at(900), Some(0));
+        assert_eq!(fade.intensity_at(1000), Some(0));
+        assert_eq!(fade.intensity_at(1250), Some(50));
+        assert_eq!(fade.intensity_at(1500), Some(100));
+        assert_eq!(fade.intensity_at(1999), Some(200));
+        assert_eq!(fade.intensity_at(2000), None);
+
+        let fade_out = Fade {
+            start: 0,
+            duration: 400,
+            from: 255,
+            to: 55,
+        };
+        assert_eq!(fade_out.intensity_at(100), Some(205));
+        assert_eq!(fade_out.intensity_at(300), Some(105));
+    }
+
+    #[test]
+    fn update_ramps_through_a_fade() {
+        let mut channel = channel(&[(0, 255, 0), (1000, 0, 1000)]);
+        assert_eq!(channel.update(500), Some(LightType::On));
+        assert_eq!(channel.update(600), None);
+        assert_eq!(channel.update(1000), None);
+        assert!(channel.is_fading());
+        assert_eq!(channel.update(1500), Some(LightType::Dimmed(128)));
+        assert_eq!(channel.update(1750), Some(LightType::Dimmed(64)));
+        assert_eq!(channel.update(2000), Some(LightType::Off));
+        assert!(!channel.is_fading());
+        assert_eq!(channel.next_due(), None);
+    }
+
+    #[test]
+    fn event_without_fade_ends_a_fade() {
+        let mut channel = channel(&[(0, 0, 1000), (0, 200, 1000), (500, 30, 0)]);
+        assert_eq!(channel.update(250), Some(LightType::Dimmed(50)));
+        assert!(channel.is_fading());
+        assert_eq!(channel.update(500), Some(LightType::Dimmed(30)));
+        assert!(!channel.is_fading());
+        assert_eq!(channel.update(900), None);
+    }
+
+    #[test]
+    fn skipped_events_are_counted() {
+        let mut channel = channel(&[(100, 255, 0), (200, 0, 0), (300, 255, 0)]);
+        assert_eq!(channel.update(350), Some(LightType::On));
+        let fired = channel.fired.take().unwrap();
+        assert_eq!(fired.due, 300);
+        assert_eq!(fired.dropped, 2);
+    }
+
+    /// The intensity of the first event of every track of a light file
+    fn first_intensities(json: &str) -> Vec<Option<u8>> {
+        let light_file = parse_light_file(serde_json::from_str(json).unwrap()).unwrap();
+        let mut tracks = light_file.tracks;
+        tracks.sort_by_key(|track| track.channels.clone());
+        tracks
+            .iter()
+            .map(|track| track.events[0].intensity)
+            .collect()
+    }
+
+    #[test]
+    fn v1_plain_one_is_fully_on() {
+        let intensities = first_intensities(
+            r#"{ "0": { "100": 1 }, "1": { "100": 128 }, "2": { "100": { "intensity": 1 } } }"#,
+        );
+        assert_eq!(intensities, vec![Some(255), Some(128), Some(1)]);
+    }
+
+    #[test]
+    fn v2_one_is_the_dimmest_setting() {
+        let intensities = first_intensities(
+            r#"{
+                "format_version": 2,
+                "tracks": [
+                    { "channels": [0], "events": [{ "time": 100, "intensity": 1 }] },
+                    { "channels": [1], "events": [{ "time": 100, "intensity": 255 }] }
+                ]
+            }"#,
+        );
+        assert_eq!(intensities, vec![Some(1), Some(255)]);
+        assert_eq!(LightType::from_intensity(1), LightType::Dimmed(1));
+    }
+
+    #[test]
+    fn chase_continues_a_fade_from_its_midpoint() {
+        let mut channel = channel(&[(0, 255, 0), (1000, 0, 1000), (3000, 255, 0)]);
+        assert_eq!(channel.chase(1500), LightType::Dimmed(128));
+        assert!(channel.is_fading());
+        assert!(channel.fired.is_none());
+        assert_eq!(channel.update(1750), Some(LightType::Dimmed(64)));
+        assert_eq!(channel.update(2000), Some(LightType::Off));
+        assert_eq!(channel.next_due(), Some(3000));
+    }
+
+    #[test]
+    fn chase_goes_back_and_forth() {
+        let mut channel = channel(&[(0, 255, 0), (1000, 0, 1000), (3000, 128, 0)]);
+        assert_eq!(channel.chase(3500), LightType::Dimmed(128));
+        assert!(!channel.is_fading());
+        assert_eq!(channel.next_due(), None);
+
+        assert_eq!(channel.chase(500), LightType::On);
+        assert_eq!(channel.next_due(), Some(1000));
+        assert_eq!(channel.chase(-100), LightType::Off);
+        assert_eq!(channel.next_due(), Some(0));
+    }
+
+    #[test]
+    fn chase_applies_the_latency() {
+        let mut channel = channel(&[(1000, 255, 0)]);
+        channel.latency = 100;
+        assert_eq!(channel.chase(1050), LightType::Off);
+        assert_eq!(channel.next_due(), Some(1100));
+        assert_eq!(channel.chase(1100), LightType::On);
+    }
+
+    #[test]
+    fn v1_files_are_converted() {
+        let light_file = parse_light_file(
+            serde_json::from_str(
+                r##"{
+                    "0, 2": { "300": 0, " 100": 255, "200": { "intensity": 64, "fade": 50 } },
+                    "@ Spot": { "100": "#ff0000" }
+                }"##,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(light_file.format_version, 1);
+        assert!(light_file.metadata.song_hash.is_none());
+
+        let mut tracks = light_file.tracks;
+        tracks.sort_by_key(|track| track.fixture.is_some());
+        assert_eq!(tracks[0].channels, vec![0, 2]);
+        assert!(tracks[0].fixture.is_none());
+        let times: Vec<i32> = tracks[0].events.iter().map(|event| event.time).collect();
+        assert_eq!(times, vec![100, 200, 300]);
+        assert_eq!(tracks[0].events[1].intensity, Some(64));
+        assert_eq!(tracks[0].events[1].fade, 50);
+
+        assert!(tracks[1].channels.is_empty());
+        assert_eq!(tracks[1].fixture.as_deref(), Some("Spot"));
+        assert_eq!(tracks[1].events[0].color.as_deref(), Some("#ff0000"));
+    }
+
+    #[test]
+    fn v1_invalid_timestamp_is_an_error() {
+        let result = parse_light_file(serde_json::from_str(r#"{ "0": { "soon": 255 } }"#).unwrap());
+        assert_eq!(result.unwrap_err(), "Invalid timestamp \"soon\" in \"0\"");
+    }
+
+    #[test]
+    fn v2_files_are_read() {
+        let light_file = parse_light_file(
+            serde_json::from_str(
+                r##"{
+                    "format_version": 2,
+                    "metadata": { "song_hash": "fnv1a64:0123456789abcdef", "duration": 180000 },
+                    "tracks": [
+                        { "fixture": "Spot", "events": [{ "time": 0, "pan": 10, "tilt": 20 }] }
+                    ]
+                }"##,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(light_file.format_version, LIGHT_FILE_VERSION);
+        assert_eq!(
+            light_file.metadata.song_hash.as_deref(),
+            Some("fnv1a64:0123456789abcdef")
+        );
+        assert_eq!(light_file.metadata.duration, Some(180000));
+        assert!(light_file.metadata.author.is_none());
+        let event = &light_file.tracks[0].events[0];
+        assert_eq!(
+            (event.pan, event.tilt, event.intensity),
+            (Some(10), Some(20), None)
+        );
+    }
+
+    #[test]
+    fn unsupported_versions_are_an_error() {
+        let result = parse_light_file(
+            serde_json::from_str(r#"{ "format_version": 3, "tracks": [] }"#).unwrap(),
+        );
+        assert_eq!(result.unwrap_err(), "Unsupported light file version 3");
+    }
+
+    #[test]
+    fn light_files_of_other_songs_are_left_out() {
+        let folder = test_folder("find_light_files");
+        for file in [
+            "Intro.mp3",
+            "Intro.live.mp3",
+            "Intro.json",
+            "Intro.calm.json",
+            "Intro.live.json",
+            "Intro.live.calm.json",
+            "lights/Intro.bright.json",
+            "lights/Intro.live.bright.json",
+        ] {
+            fs::write(folder.join(file), []).unwrap();
+        }
+
+        assert_eq!(
+            find_light_files(&folder.join("Intro.mp3")),
+            vec![
+                folder.join("Intro.json"),
+                folder.join("Intro.calm.json"),
+                folder.join("lights/Intro.bright.json"),
+            ]
+        );
+        assert_eq!(
+            find_light_files(&folder.join("Intro.live.mp3")),
+            vec![
+                folder.join("Intro.live.json"),
+                folder.join("Intro.live.calm.json"),
+                folder.join("lights/Intro.live.bright.json"),
+            ]
+        );
+    }
+
+    #[test]
+    fn find_song_of_light_files() {
+        let folder = test_folder("find_song");
+        let song = folder.join("Song.mp3");
+        fs::write(&song, []).unwrap();
+
+        assert_eq!(find_song(&folder.join("Song.json")), Some(song.clone()));
+        assert_eq!(
+            find_song(&folder.join("Song.calm.json")),
+            Some(song.clone())
+        );
+        assert_eq!(
+            find_song(&folder.join(LIGHTS_DIRECTORY).join("Song.json")),
+            Some(song.clone())
+        );
+        assert_eq!(
+            find_song(&folder.join(LIGHTS_DIRECTORY).join("Song.calm.json")),
+            Some(song.clone())
+        );
+        assert_eq!(find_song(&folder.join("Other.json")), None);
+
+        // A song whose name contains a dot is found before the shorter stem
+        let dotted = folder.join("Song.live.flac");
+        fs::write(&dotted, []).unwrap();
+        assert_eq!(find_song(&folder.join("Song.live.json")), Some(dotted));
+        assert_eq!(find_song(&folder.join("Song.json")), Some(song));
     }
 }
@@ -0,0 +1,470 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use lofty::tag::{ItemKey, Tag};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+
+use crate::app::{Notification, Timer};
+use crate::audio_player::open_audio;
+
+/// The loudness every song is brought to in LUFS, the ReplayGain 2.0 reference level
+/// ReplayGain tags are relative to this level, so tagged and analysed songs match.
+pub const TARGET_LOUDNESS: f64 = -18.0;
+
+/// The extension of the loudness cache stored beside each song
+const CACHE_EXTENSION: &str = "loudness";
+
+/// The length of a gating block in seconds
+const BLOCK_LENGTH: f64 = 0.4;
+
+/// How many gating blocks overlap, each starting a quarter block after the last
+const BLOCK_OVERLAP: usize = 4;
+
+/// Blocks quieter than this are silence and ignored, in LUFS
+const ABSOLUTE_GATE: f64 = -70.0;
+
+/// Blocks this much quieter than the average of the loud blocks are ignored, in LU
+const RELATIVE_GATE: f64 = -10.0;
+
+/// How loud a song is compared to the target loudness
+///
+/// gain: The gain that brings the song to the target loudness in dB
+/// peak: The largest sample of the song, where 1.0 is full scale
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Loudness {
+    pub gain: f32,
+    pub peak: f32,
+}
+
+impl Loudness {
+    /// The gain as a factor to multiply samples with
+    /// The gain is lowered if it would make the loudest sample clip.
+    pub fn linear_gain(&self) -> f32 {
+        let gain = 10f32.powf(self.gain / 20.0);
+        if self.peak > 0.0 {
+            gain.min(1.0 / self.peak)
+        } else {
+            gain
+        }
+    }
+}
+
+/// Reads the ReplayGain track gain and peak of a tag, if the song was tagged with them
+pub fn tagged_loudness(tag: &Tag) -> Option<Loudness> {
+    // Values are written like "-6.54 dB", so the unit is dropped before parsing
+    let parse = |key: &ItemKey| -> Option<f32> {
+        tag.get_string(key)?
+            .trim()
+            .trim_end_matches(|c: char| c.is_alphabetic())
+            .trim()
+            .parse()
+            .ok()
+    };
+    Some(Loudness {
+        gain: parse(&ItemKey::ReplayGainTrackGain)?,
+        peak: parse(&ItemKey::ReplayGainTrackPeak).unwrap_or(0.0),
+    })
+}
+
+/// The loudness cache of a song, stored beside the song
+///
+/// modified: When the song was last modified, in nanoseconds since the Unix epoch
+/// size: The size of the song in bytes
+/// loudness: The measured loudness
+#[derive(Serialize, Deserialize)]
+struct LoudnessCache {
+    modified: u64,
+    size: u64,
+    loudness: Loudness,
+}
+
+/// The path of the loudness cache of a song, such as `Song.mp3.loudness`
+fn cache_path(song_path: &Path) -> PathBuf {
+    let mut file_name = song_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(CACHE_EXTENSION);
+    song_path.with_file_name(file_name)
+}
+
+/// The modification time and size of a file, to know when a cache is outdated
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos() as u64;
+    Some((modified, metadata.len()))
+}
+
+/// Reads the cached loudness of a song, if it was measured since the song last changed
+pub fn cached_loudness(song_path: &Path) -> Option<Loudness> {
+    let contents = fs::read_to_string(cache_path(song_path)).ok()?;
+    let cache: LoudnessCache = serde_json::from_str(&contents).ok()?;
+    let (modified, size) = file_stamp(song_path)?;
+    (cache.modified == modified && cache.size == size).then_some(cache.loudness)
+}
+
+/// Measures the loudness of a song and caches it beside the song
+pub fn analyse_loudness(song_path: &Path) -> Result<Loudness, String> {
+    let source = open_audio(song_path)?;
+    let mut meter = LoudnessMeter::new(source.channels(), source.sample_rate());
+    for sample in source {
+        meter.push(sample as f64 / i16::MAX as f64);
+    }
+    let loudness = meter.loudness();
+
+    if let Some((modified, size)) = file_stamp(song_path) {
+        let cache = LoudnessCache {
+            modified,
+            size,
+            loudness,
+        };
+        let contents = serde_json::to_string(&cache).map_err(|err| err.to_string())?;
+        fs::write(cache_path(song_path), contents).map_err(|err| err.to_string())?;
+    }
+    Ok(loudness)
+}
+
+/// Starts a thread that measures the loudness of songs one at a time, in the background
+/// Songs that already have a cached loudness are skipped. Songs that cannot be measured are
+/// reported, and a decoder panic only skips the song so the thread keeps running.
+/// Returns the channel that songs are sent to the thread with.
+///
+/// notification_sender: Where failed measurements are reported
+pub fn start_loudness_thread(notification_sender: Sender<Notification>) -> Sender<PathBuf> {
+    let (sender, receiver) = mpsc::channel::<PathBuf>();
+    thread::spawn(move || {
+        for song_path in receiver {
+            if cached_loudness(&song_path).is_some() {
+                continue;
+            }
+            let err = match panic::catch_unwind(|| analyse_loudness(&song_path)) {
+                Ok(Ok(_)) => continue,
+                Ok(Err(err)) => err,
+                Err(_) => "The decoder crashed".to_string(),
+            };
+            let _ = notification_sender.send(Notification {
+                title: "Loudness Error".to_string(),
+                message: format!(
+                    "The loudness of {} could not be measured, so it plays at its own level. {}",
+                    song_path.display(),
+                    err
+                ),
+                timer: Timer::new(Duration::from_secs(10)),
+                id: fastrand::i32(0..i32::MAX),
+            });
+        }
+    });
+    sender
+}
+
+/// A biquad filter, as used for the K-weighting of ITU-R BS.1770
+///
+/// b: The feed forward coefficients
+/// a: The feedback coefficients, without the leading 1
+/// state: The previous inputs and outputs of each channel
+#[derive(Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: Vec<[f64; 4]>,
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2], channels: usize) -> Self {
+        Self {
+            b,
+            a,
+            state: vec![[0.0; 4]; channels],
+        }
+    }
+
+    fn process(&mut self, channel: usize, input: f64) -> f64 {
+        let [x1, x2, y1, y2] = self.state[channel];
+        let output =
+            self.b[0] * input + self.b[1] * x1 + self.b[2] * x2 - self.a[0] * y1 - self.a[1] * y2;
+        self.state[channel] = [input, x1, output, y1];
+        output
+    }
+}
+
+/// Measures the integrated loudness of audio as described by EBU R128
+/// Samples are K-weighted, their power is averaged over overlapping 400 ms blocks, and blocks
+/// that are silent or much quieter than the rest are left out.
+///
+/// channels: The channel count of the audio
+/// shelf: The first K-weighting stage, boosting high frequencies like the head does
+/// high_pass: The second K-weighting stage, removing low frequencies
+/// channel: The channel of the next sample
+/// hop_length: The amount of frames between the starts of two blocks
+/// hop_power: The summed power of every channel in the current hop
+/// hop_frames: The amount of frames in the current hop
+/// hops: The mean power of the last hops, which make up the current block
+/// blocks: The mean power of every block
+/// peak: The largest sample so far
+struct LoudnessMeter {
+    channels: usize,
+    shelf: Biquad,
+    high_pass: Biquad,
+    channel: usize,
+    hop_length: usize,
+    hop_power: f64,
+    hop_frames: usize,
+    hops: Vec<f64>,
+    blocks: Vec<f64>,
+    peak: f64,
+}
+
+impl LoudnessMeter {
+    fn new(channels: u16, sample_rate: u32) -> Self {
+        let channels = channels.max(1) as usize;
+        let rate = sample_rate.max(1) as f64;
+
+        // The coefficients of both stages, calculated for any sample rate
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            channels,
+        );
+
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            channels,
+        );
+
+        Self {
+            channels,
+            shelf,
+            high_pass,
+            channel: 0,
+            hop_length: ((rate * BLOCK_LENGTH) as usize / BLOCK_OVERLAP).max(1),
+            hop_power: 0.0,
+            hop_frames: 0,
+            hops: Vec::with_capacity(BLOCK_OVERLAP),
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Adds the next sample, with the channels interleaved
+    fn push(&mut self, sample: f64) {
+        self.peak = self.peak.max(sample.abs());
+        let weighted = self
+            .high_pass
+            .process(self.channel, self.shelf.process(self.channel, sample));
+        self.hop_power += weighted * weighted;
+
+        self.channel += 1;
+        if self.channel < self.channels {
+            return;
+        }
+        self.channel = 0;
+        self.hop_frames += 1;
+        if self.hop_frames < self.hop_length {
+            return;
+        }
+
+        if self.hops.len() == BLOCK_OVERLAP {
+            self.hops.remove(0);
+        }
+        self.hops.push(self.hop_power / self.hop_length as f64);
+        self.hop_power = 0.0;
+        self.hop_frames = 0;
+        if self.hops.len() == BLOCK_OVERLAP {
+            self.blocks
+                .push(self.hops.iter().sum::<f64>() / BLOCK_OVERLAP as f64);
+        }
+    }
+
+    /// The gain that brings the measured audio to the target loudness
+    /// Audio that is silent throughout is left as it is.
+    fn loudness(&self) -> Loudness {
+        let loud_blocks: Vec<f64> = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|power| block_loudness(*power) > ABSOLUTE_GATE)
+            .collect();
+        let peak = self.peak as f32;
+        if loud_blocks.is_empty() {
+            return Loudness { gain: 0.0, peak };
+        }
+
+        let threshold = block_loudness(mean(&loud_blocks)) + RELATIVE_GATE;
+        let gated: Vec<f64> = loud_blocks
+            .into_iter()
+            .filter(|power| block_loudness(*power) > threshold)
+            .collect();
+        let loudness = block_loudness(mean(&gated));
+        Loudness {
+            gain: (TARGET_LOUDNESS - loudness) as f32,
+            peak,
+        }
+    }
+}
+
+/// The loudness of a mean power in LUFS
+fn block_loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.max(f64::MIN_POSITIVE).log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len().max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lofty::tag::TagType;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Measures a 997 Hz sine of ten seconds
+    ///
+    /// amplitudes: The amplitude of the sine in every channel
+    fn measure_sine(amplitudes: &[f64]) -> Loudness {
+        let mut meter = LoudnessMeter::new(amplitudes.len() as u16, SAMPLE_RATE);
+        for frame in 0..SAMPLE_RATE * 10 {
+            let phase = 2.0 * PI * 997.0 * frame as f64 / SAMPLE_RATE as f64;
+            for amplitude in amplitudes {
+                meter.push(amplitude * phase.sin());
+            }
+        }
+        meter.loudness()
+    }
+
+    /// The loudness of the measured audio in LUFS
+    fn integrated_loudness(loudness: &Loudness) -> f64 {
+        TARGET_LOUDNESS - loudness.gain as f64
+    }
+
+    #[test]
+    fn full_scale_sine_in_one_channel_is_the_reference_level() {
+        let loudness = measure_sine(&[1.0, 0.0]);
+        assert!((integrated_loudness(&loudness) + 3.01).abs() < 0.05);
+        assert!((loudness.peak - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn full_scale_sine_in_both_channels_adds_their_power() {
+        let loudness = measure_sine(&[1.0, 1.0]);
+        assert!(integrated_loudness(&loudness).abs() < 0.05);
+    }
+
+    #[test]
+    fn quieter_sine_measures_lower() {
+        // -20 dBFS
+        let loudness = measure_sine(&[0.1, 0.0]);
+        assert!((integrated_loudness(&loudness) + 23.01).abs() < 0.05);
+    }
+
+    #[test]
+    fn silence_is_left_as_it_is() {
+        let mut meter = LoudnessMeter::new(2, SAMPLE_RATE);
+        for _ in 0..SAMPLE_RATE * 2 {
+            meter.push(0.0);
+        }
+        assert_eq!(
+            meter.loudness(),
+            Loudness {
+                gain: 0.0,
+                peak: 0.0
+            }
+        );
+        assert_eq!(LoudnessMeter::new(2, SAMPLE_RATE).loudness().gain, 0.0);
+    }
+
+    #[test]
+    fn silent_parts_do_not_lower_the_loudness() {
+        let mut meter = LoudnessMeter::new(1, SAMPLE_RATE);
+        for frame in 0..SAMPLE_RATE * 5 {
+            let phase = 2.0 * PI * 997.0 * frame as f64 / SAMPLE_RATE as f64;
+            meter.push(0.5 * phase.sin());
+        }
+        let loud = meter.loudness();
+        for _ in 0..SAMPLE_RATE * 5 {
+            meter.push(0.0);
+        }
+        // Only the blocks that overlap the end of the sine are a little quieter
+        assert!((meter.loudness().gain - loud.gain).abs() < 0.2);
+    }
+
+    #[test]
+    fn tagged_loudness_drops_the_unit() {
+        let mut tag = Tag::new(TagType::VorbisComments);
+        tag.insert_text(ItemKey::ReplayGainTrackGain, "-6.54 dB".to_string());
+        tag.insert_text(ItemKey::ReplayGainTrackPeak, "0.988547".to_string());
+        assert_eq!(
+            tagged_loudness(&tag),
+            Some(Loudness {
+                gain: -6.54,
+                peak: 0.988547
+            })
+        );
+    }
+
+    #[test]
+    fn tagged_loudness_needs_a_gain() {
+        let mut tag = Tag::new(TagType::VorbisComments);
+        assert_eq!(tagged_loudness(&tag), None);
+        tag.insert_text(ItemKey::ReplayGainTrackGain, "+2.5 dB".to_string());
+        assert_eq!(
+            tagged_loudness(&tag),
+            Some(Loudness {
+                gain: 2.5,
+                peak: 0.0
+            })
+        );
+    }
+
+    #[test]
+    fn linear_gain_is_limited_by_the_peak() {
+        let boost = Loudness {
+            gain: 6.0,
+            peak: 0.0,
+        };
+        assert!((boost.linear_gain() - 1.995).abs() < 0.001);
+        let clipping = Loudness {
+            gain: 6.0,
+            peak: 0.8,
+        };
+        assert_eq!(clipping.linear_gain(), 1.25);
+        let cut = Loudness {
+            gain: -6.0,
+            peak: 0.8,
+        };
+        assert!((cut.linear_gain() - 0.501).abs() < 0.001);
+    }
+
+    #[test]
+    fn cache_is_stored_beside_the_song() {
+        assert_eq!(
+            cache_path(Path::new("music/Song.mp3")),
+            Path::new("music/Song.mp3.loudness")
+        );
+    }
+}
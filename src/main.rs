@@ -1,16 +1,38 @@
 #![warn(clippy::all, rust_2018_idioms)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+use open_lights_core::audio_player::audio_duration;
 use open_lights_core::constants::PLAYLIST_DIRECTORY;
-use std::fs;
-use std::path::Path;
+use open_lights_core::fixtures::FixtureConfig;
+use open_lights_core::library::MediaLibrary;
+use open_lights_core::lights::find_song;
+use open_lights_core::outputs::DEFAULT_CHANNEL_COUNT;
+use open_lights_core::playlist::{load_playlist, PlaylistFile};
+use open_lights_core::validator::{validate_light_file, Severity, ValidationContext};
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
 
 /// Open Lights Core
 /// This project reads light files produced by <https://github.com/Open-Lights/BeatMaker>, then
 /// outputs as a light show.
 ///
 /// Read more on the wiki: <https://github.com/Open-Lights/OpenLightsCore/wiki>
+///
+/// Run `open_lights_core check LIGHT_FILE... [--channels COUNT] [--song SONG]` to validate light
+/// files without starting the GUI. The song is found next to the light files unless `--song` is
+/// given.
+///
+/// Run `open_lights_core import PLAYLIST_FILE` to copy an M3U, PLS or JSON playlist into the
+/// playlists folder, and `open_lights_core export PLAYLIST OUTPUT_FILE` to write a playlist out.
 fn main() -> eframe::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("check") => process::exit(check_light_files(&args[1..])),
+        Some("import") => process::exit(import_playlist(&args[1..])),
+        Some("export") => process::exit(export_playlist(&args[1..])),
+        _ => {}
+    }
+
     fs::create_dir_all(Path::new(&*PLAYLIST_DIRECTORY)).unwrap();
 
     let native_options = eframe::NativeOptions {
@@ -42,3 +64,160 @@ fn main() -> eframe::Result<()> {
         }),
     )
 }
+
+/// Validates light files from the command line and prints every problem found
+/// Returns the exit code, which is 1 if any file has errors.
+///
+/// args: The light files to check, optionally with `--channels COUNT` and `--song SONG`
+fn check_light_files(args: &[String]) -> i32 {
+    let mut channel_count = DEFAULT_CHANNEL_COUNT;
+    let mut song_path = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--channels" {
+            match args.next().and_then(|count| count.parse::<usize>().ok()) {
+                Some(count) => channel_count = count,
+                None => {
+                    eprintln!("--channels needs a number of channels");
+                    return 2;
+                }
+            }
+        } else if arg == "--song" {
+            match args.next() {
+                Some(song) => song_path = Some(PathBuf::from(song)),
+                None => {
+                    eprintln!("--song needs the audio file the light files belong to");
+                    return 2;
+                }
+            }
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("Usage: open_lights_core check LIGHT_FILE... [--channels COUNT] [--song SONG]");
+        return 2;
+    }
+
+    let fixtures = FixtureConfig::load().unwrap_or_else(|err| {
+        eprintln!("Failed to read the fixture definitions: {}", err);
+        FixtureConfig::default()
+    });
+
+    let mut failed = false;
+    for path in paths {
+        let song = song_path.clone().or_else(|| find_song(&path));
+        let song_duration = song
+            .as_deref()
+            .and_then(audio_duration)
+            .map(|duration| (duration * 1000.0) as u32);
+        let context = ValidationContext {
+            channel_count,
+            song_duration,
+            song: song.as_deref(),
+            library: None,
+            fixtures: &fixtures,
+        };
+
+        let diagnostics = validate_light_file(&path, &context);
+        if diagnostics.is_empty() {
+            println!("{}: OK", path.display());
+        }
+        for diagnostic in &diagnostics {
+            println!("{}: {}", path.display(), diagnostic);
+        }
+        failed |= diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error);
+    }
+
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+/// Copies a playlist file into the playlists folder, making its paths absolute so they still
+/// point at the songs from there
+/// Returns the exit code.
+///
+/// args: The playlist file to import
+fn import_playlist(args: &[String]) -> i32 {
+    let [path] = args else {
+        eprintln!("Usage: open_lights_core import PLAYLIST_FILE");
+        return 2;
+    };
+    let path = Path::new(path);
+    let mut playlist = match PlaylistFile::read(path) {
+        Ok(playlist) => playlist,
+        Err(err) => {
+            eprintln!("Failed to read {}: {}", path.display(), err);
+            return 1;
+        }
+    };
+    let directory = fs::canonicalize(path)
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+        .unwrap_or_default();
+    playlist.resolve(&directory);
+
+    fs::create_dir_all(Path::new(&*PLAYLIST_DIRECTORY)).unwrap();
+    let destination = Path::new(&*PLAYLIST_DIRECTORY).join(path.file_name().unwrap_or_default());
+    if destination.exists() {
+        eprintln!("{} already exists", destination.display());
+        return 1;
+    }
+    if let Err(err) = playlist.write(&destination) {
+        eprintln!("Failed to write {}: {}", destination.display(), err);
+        return 1;
+    }
+    println!(
+        "Imported {} songs to {}",
+        playlist.entries.len(),
+        destination.display()
+    );
+    0
+}
+
+/// Writes a playlist folder or playlist file out as a playlist file, going by the extension of
+/// the output file for the format
+/// Returns the exit code.
+///
+/// args: The name of the playlist and the file to write
+fn export_playlist(args: &[String]) -> i32 {
+    let [playlist, output] = args else {
+        eprintln!("Usage: open_lights_core export PLAYLIST OUTPUT_FILE");
+        return 2;
+    };
+    let mut library = MediaLibrary::load().unwrap_or_else(|err| {
+        eprintln!("Failed to read the library index: {}", err);
+        MediaLibrary::new()
+    });
+    let mut skipped = Vec::new();
+    let songs = match load_playlist(
+        &mut library,
+        &Path::new(&*PLAYLIST_DIRECTORY).join(playlist),
+        &mut skipped,
+    ) {
+        Ok(songs) => songs,
+        Err(err) => {
+            eprintln!("Failed to read the playlist {}: {}", playlist, err);
+            return 1;
+        }
+    };
+    for song in &skipped {
+        eprintln!("Skipping {}", song);
+    }
+    if let Err(err) = library.save() {
+        eprintln!("Failed to save the library index: {}", err);
+    }
+
+    if let Err(err) = PlaylistFile::from_songs(&songs).write(Path::new(output)) {
+        eprintln!("Failed to write {}: {}", output, err);
+        return 1;
+    }
+    println!("Exported {} songs to {}", songs.len(), output);
+    0
+}
@@ -0,0 +1,203 @@
+use std::fs::File;
+use std::io::{self, BufReader, SeekFrom};
+use std::time::Duration;
+
+use audiopus::coder::Decoder;
+use audiopus::packet::Packet;
+use audiopus::{Channels, MutSignals, SampleRate};
+use ogg::PacketReader;
+use rodio::source::SeekError;
+use rodio::Source;
+
+/// The sample rate Opus audio is always decoded at
+const OPUS_SAMPLE_RATE: u32 = 48000;
+
+/// The most samples per channel a single Opus packet can hold, 120 ms at 48 kHz
+const MAX_PACKET_SAMPLES: usize = 5760;
+
+/// The length of the Opus identification header up to and including the channel mapping family
+const HEADER_LENGTH: usize = 19;
+
+/// Decodes Ogg Opus files, which rodio does not support
+/// Only mono and stereo files are supported, as they are all that music is released in.
+///
+/// reader: The Ogg packets of the file
+/// decoder: The Opus decoder
+/// channels: The channel count of the audio
+/// pre_skip: The amount of samples per channel at the start of the stream that are not part of the audio
+/// decoded: The amount of samples per channel decoded so far, including the pre-skip
+/// samples: The samples of the last decoded packet, interleaved
+/// sample_index: The index of the next sample in `samples`
+pub struct OpusDecoder {
+    reader: PacketReader<BufReader<File>>,
+    decoder: Decoder,
+    channels: u16,
+    pre_skip: u64,
+    decoded: u64,
+    samples: Vec<i16>,
+    sample_index: usize,
+}
+
+impl OpusDecoder {
+    pub fn new(file: File) -> Result<Self, String> {
+        let mut reader = PacketReader::new(BufReader::new(file));
+        let (channels, pre_skip) = read_headers(&mut reader)?;
+        Ok(Self {
+            reader,
+            decoder: create_decoder(channels)?,
+            channels,
+            pre_skip,
+            decoded: 0,
+            samples: Vec::new(),
+            sample_index: 0,
+        })
+    }
+
+    /// The position of the next sample in samples per channel, not counting the pre-skip
+    fn frame_position(&self) -> u64 {
+        let buffered = ((self.samples.len() - self.sample_index) / self.channels as usize) as u64;
+        (self.decoded - buffered).saturating_sub(self.pre_skip)
+    }
+
+    /// Decodes the next packet into `samples`, dropping any of the pre-skip
+    /// Returns false at the end of the stream.
+    fn decode_packet(&mut self) -> bool {
+        let channels = self.channels as usize;
+        loop {
+            let packet = match self.reader.read_packet() {
+                Ok(Some(packet)) => packet,
+                _ => return false,
+            };
+            // Empty packets carry no audio
+            let Ok(input) = Packet::try_from(&packet.data) else {
+                continue;
+            };
+
+            self.samples.resize(MAX_PACKET_SAMPLES * channels, 0);
+            let Ok(output) = MutSignals::try_from(&mut self.samples) else {
+                return false;
+            };
+            // A damaged packet is skipped instead of ending the song
+            let Ok(decoded) = self.decoder.decode(Some(input), output, false) else {
+                continue;
+            };
+            self.samples.truncate(decoded * channels);
+
+            let skipped = self
+                .pre_skip
+                .saturating_sub(self.decoded)
+                .min(decoded as u64);
+            self.decoded += decoded as u64;
+            self.sample_index = skipped as usize * channels;
+            if self.sample_index < self.samples.len() {
+                return true;
+            }
+        }
+    }
+
+    /// Goes back to the start of the file
+    fn restart(&mut self) -> Result<(), String> {
+        self.reader
+            .seek_bytes(SeekFrom::Start(0))
+            .map_err(|err| err.to_string())?;
+        read_headers(&mut self.reader)?;
+        self.decoder = create_decoder(self.channels)?;
+        self.decoded = 0;
+        self.samples.clear();
+        self.sample_index = 0;
+        Ok(())
+    }
+}
+
+/// Reads the identification and comment headers at the start of an Opus stream
+/// Returns the channel count and the pre-skip.
+fn read_headers(reader: &mut PacketReader<BufReader<File>>) -> Result<(u16, u64), String> {
+    let header = reader
+        .read_packet()
+        .map_err(|err| err.to_string())?
+        .ok_or("The file is empty")?;
+    let data = &header.data;
+    if data.len() < HEADER_LENGTH || &data[..8] != b"OpusHead" {
+        return Err("The file is not an Opus file".to_string());
+    }
+    let channels = data[9] as u16;
+    let pre_skip = u16::from_le_bytes([data[10], data[11]]) as u64;
+
+    // The comment header holds the tags, which are read with lofty instead
+    reader.read_packet().map_err(|err| err.to_string())?;
+    Ok((channels, pre_skip))
+}
+
+fn create_decoder(channels: u16) -> Result<Decoder, String> {
+    let channels = match channels {
+        1 => Channels::Mono,
+        2 => Channels::Stereo,
+        _ => {
+            return Err(format!(
+                "Opus files with {} channels are not supported",
+                channels
+            ))
+        }
+    };
+    Decoder::new(SampleRate::Hz48000, channels).map_err(|err| err.to_string())
+}
+
+impl Iterator for OpusDecoder {
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.sample_index >= self.samples.len() && !self.decode_packet() {
+            return None;
+        }
+        let sample = self.samples[self.sample_index];
+        self.sample_index += 1;
+        Some(sample)
+    }
+}
+
+impl Source for OpusDecoder {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        OPUS_SAMPLE_RATE
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Seeks by decoding up to the position, going back to the start of the file first when
+    /// seeking backwards, so the position is exact without relying on the page index
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        let target = (position.as_secs_f64() * OPUS_SAMPLE_RATE as f64) as u64;
+        if target < self.frame_position() {
+            self.restart()
+                .map_err(|err| SeekError::Other(Box::new(io::Error::other(err))))?;
+        }
+
+        let channels = self.channels as usize;
+        while self.frame_position() < target {
+            let buffered = self.samples.len() - self.sample_index;
+            if buffered == 0 {
+                if !self.decode_packet() {
+                    break;
+                }
+                continue;
+            }
+            let remaining = (target - self.frame_position()) as usize * channels;
+            self.sample_index += remaining.min(buffered);
+        }
+        Ok(())
+    }
+}
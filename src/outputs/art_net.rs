@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lights::LightType;
+use crate::outputs::dmx::{default_patch, DmxPatch, DmxUniverses, UNIVERSE_SIZE};
+use crate::outputs::{LightOutput, OutputBackend};
+
+/// The UDP port used by Art-Net
+pub const ART_NET_PORT: u16 = 6454;
+
+/// The Art-Net protocol revision implemented
+const PROTOCOL_VERSION: u16 = 14;
+
+/// The OpCode of an ArtDmx packet
+const OP_DMX: u16 = 0x5000;
+
+/// The Art-Net output configuration
+///
+/// target: The address ArtDmx packets are sent to, either a node or a broadcast address
+/// refresh_rate: How many times per second every universe is resent
+/// patch: The mapping of OpenLights channels to DMX addresses
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ArtNetConfig {
+    pub target: String,
+    pub refresh_rate: f32,
+    pub patch: Vec<DmxPatch>,
+}
+
+impl Default for ArtNetConfig {
+    fn default() -> Self {
+        Self {
+            target: format!("255.255.255.255:{}", ART_NET_PORT),
+            refresh_rate: 40.0,
+            patch: default_patch(0),
+        }
+    }
+}
+
+/// Sends DMX512 data to Art-Net nodes over UDP
+/// Every universe is resent at a steady rate, and immediately whenever it is flushed.
+pub struct ArtNetOutput {
+    pending: DmxUniverses,
+    sender: Arc<Mutex<ArtNetSender>>,
+    running: Arc<AtomicBool>,
+}
+
+/// The socket and the last flushed data of an Art-Net output
+///
+/// socket: The UDP socket packets are sent from
+/// target: The address packets are sent to
+/// frames: The DMX data that is currently being sent
+/// sequence: The sequence number of the next packet, from 1 to 255
+struct ArtNetSender {
+    socket: UdpSocket,
+    target: String,
+    frames: BTreeMap<u16, [u8; UNIVERSE_SIZE]>,
+    sequence: u8,
+}
+
+impl ArtNetOutput {
+    /// Opens a socket and starts the refresh thread
+    ///
+    /// config: The Art-Net configuration
+    pub fn new(config: &ArtNetConfig) -> Result<Self, String> {
+        if config.refresh_rate <= 0.0 {
+            return Err("The Art-Net refresh rate must be above zero".to_string());
+        }
+        let socket = UdpSocket::bind("0.0.0.0:0").map_err(|err| err.to_string())?;
+        socket.set_broadcast(true).map_err(|err| err.to_string())?;
+
+        let pending = DmxUniverses::new(&config.patch)?;
+        let sender = Arc::new(Mutex::new(ArtNetSender {
+            socket,
+            target: config.target.clone(),
+            frames: pending.frames().clone(),
+            sequence: 1,
+        }));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let sender_clone = Arc::clone(&sender);
+        let running_clone = Arc::clone(&running);
+        let interval = Duration::from_secs_f32(1.0 / config.refresh_rate);
+        thread::spawn(move || {
+            while running_clone.load(Ordering::Relaxed) {
+                sender_clone.lock().unwrap().send_all();
+                thread::sleep(interval);
+            }
+        });
+
+        Ok(Self {
+            pending,
+            sender,
+            running,
+        })
+    }
+}
+
+impl ArtNetSender {
+    /// Sends one ArtDmx packet for every universe
+    fn send_all(&mut self) {
+        for (universe, data) in &self.frames {
+            let packet = art_dmx_packet(self.sequence, *universe, data);
+            // Nodes may be offline while the show runs, so failed sends are retried on the next refresh
+            let _ = self.socket.send_to(&packet, &self.target);
+        }
+        self.sequence = self.sequence.checked_add(1).unwrap_or(1);
+    }
+}
+
+impl Drop for ArtNetOutput {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+impl LightOutput for ArtNetOutput {
+    fn set_channel(&mut self, channel: i32, light_type: LightType) {
+        self.pending.set_channel(channel, light_type);
+    }
+
+    fn all_off(&mut self) {
+        self.pending.blackout();
+    }
+
+    fn flush(&mut self) {
+        let mut sender = self.sender.lock().unwrap();
+        sender.frames.clone_from(self.pending.frames());
+        sender.send_all();
+    }
+
+    fn channel_count(&self) -> usize {
+        self.pending.channel_count()
+    }
+
+    fn backend(&self) -> OutputBackend {
+        OutputBackend::ArtNet
+    }
+}
+
+/// Builds an ArtDmx packet
+///
+/// sequence: The packet sequence number, or 0 to disable reordering
+/// universe: The 15 bit Port-Address of the universe
+/// data: The DMX slots, at most 512
+pub fn art_dmx_packet(sequence: u8, universe: u16, data: &[u8]) -> Vec<u8> {
+    // The length must be even and at least 2
+    let length = (data.len() + data.len() % 2).max(2);
+
+    let mut packet = Vec::with_capacity(18 + length);
+    packet.extend_from_slice(b"Art-Net\0");
+    packet.extend_from_slice(&OP_DMX.to_le_bytes());
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    packet.push(sequence);
+    packet.push(0); // Physical
+    packet.push((universe & 0xFF) as u8); // SubUni
+    packet.push(((universe >> 8) & 0x7F) as u8); // Net
+    packet.extend_from_slice(&(length as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet.resize(18 + length, 0);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A socket on the loopback address that the output sends to
+    fn receiver() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket
+    }
+
+    fn receive(socket: &UdpSocket) -> Vec<u8> {
+        let mut buffer = [0; 1024];
+        let (length, _) = socket.recv_from(&mut buffer).unwrap();
+        buffer[..length].to_vec()
+    }
+
+    #[test]
+    fn art_dmx_packet_layout() {
+        let packet = art_dmx_packet(7, 0x0123, &[1, 2, 3]);
+        assert_eq!(&packet[..8], b"Art-Net\0");
+        assert_eq!(packet[8..10], [0x00, 0x50]);
+        assert_eq!(packet[10..12], [0, 14]);
+        assert_eq!(packet[12], 7);
+        assert_eq!(packet[13], 0);
+        assert_eq!(packet[14..16], [0x23, 0x01]);
+        // Odd lengths are padded to an even length
+        assert_eq!(packet[16..18], [0, 4]);
+        assert_eq!(packet[18..], [1, 2, 3, 0]);
+
+        let full = art_dmx_packet(1, 0, &[0; UNIVERSE_SIZE]);
+        assert_eq!(full.len(), 18 + UNIVERSE_SIZE);
+        assert_eq!(full[16..18], [0x02, 0x00]);
+        assert_eq!(art_dmx_packet(1, 0, &[]).len(), 20);
+    }
+
+    #[test]
+    fn art_dmx_packet_masks_universe() {
+        let packet = art_dmx_packet(1, 0xFFFF, &[0; 2]);
+        assert_eq!(packet[14..16], [0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn sequence_wraps_to_one() {
+        let socket = receiver();
+        let mut sender = ArtNetSender {
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            target: socket.local_addr().unwrap().to_string(),
+            frames: BTreeMap::from([(0, [0; UNIVERSE_SIZE])]),
+            sequence: 254,
+        };
+        for expected in [254, 255, 1, 2] {
+            sender.send_all();
+            assert_eq!(receive(&socket)[12], expected);
+        }
+    }
+
+    #[test]
+    fn flush_sends_patched_universes() {
+        let socket = receiver();
+        let config = ArtNetConfig {
+            target: socket.local_addr().unwrap().to_string(),
+            // Slow enough that the refresh thread only sends once while the test runs
+            refresh_rate: 0.1,
+            patch: vec![
+                DmxPatch {
+                    channel: 0,
+                    universe: 0x0102,
+                    address: 1,
+                },
+                DmxPatch {
+                    channel: 1,
+                    universe: 0x0102,
+                    address: 512,
+                },
+                DmxPatch {
+                    channel: 1,
+                    universe: 3,
+                    address: 10,
+                },
+            ],
+        };
+        let mut output = ArtNetOutput::new(&config).unwrap();
+        assert_eq!(output.channel_count(), 2);
+        output.set_channel(0, LightType::Dimmed(100));
+        output.set_channel(1, LightType::On);
+        // Unpatched channels are ignored
+        output.set_channel(5, LightType::On);
+        output.flush();
+
+        // Skip the blacked out packets the refresh thread may have sent first
+        let mut universes = BTreeMap::new();
+        while universes.len() < 2 {
+            let packet = receive(&socket);
+            assert_eq!(&packet[..8], b"Art-Net\0");
+            assert_eq!(packet.len(), 18 + UNIVERSE_SIZE);
+            assert_ne!(packet[12], 0);
+            if packet[18..].iter().any(|slot| *slot != 0) {
+                let universe = u16::from_le_bytes([packet[14], packet[15]]);
+                universes.insert(universe, packet[18..].to_vec());
+            }
+        }
+
+        let first = &universes[&0x0102];
+        assert_eq!(first[0], 100);
+        assert_eq!(first[511], 255);
+        assert_eq!(first.iter().filter(|slot| **slot != 0).count(), 2);
+        let second = &universes[&3];
+        assert_eq!(second[9], 255);
+        assert_eq!(second.iter().filter(|slot| **slot != 0).count(), 1);
+    }
+
+    #[test]
+    fn patch_outside_universe_is_rejected() {
+        let mut config = ArtNetConfig::default();
+        config.patch[0].address = 513;
+        assert!(ArtNetOutput::new(&config).is_err());
+        config.patch.clear();
+        assert!(ArtNetOutput::new(&config).is_err());
+    }
+}
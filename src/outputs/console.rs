@@ -0,0 +1,34 @@
+use crate::lights::LightType;
+use crate::outputs::{LightOutput, OutputBackend};
+
+/// Prints every channel change to stdout
+/// Used when no lighting hardware is attached.
+pub struct ConsoleOutput {
+    channel_count: usize,
+}
+
+impl ConsoleOutput {
+    pub fn new(channel_count: usize) -> Self {
+        Self { channel_count }
+    }
+}
+
+impl LightOutput for ConsoleOutput {
+    fn set_channel(&mut self, channel: i32, light_type: LightType) {
+        println!("Channel {}: {:?}", channel, light_type);
+    }
+
+    fn all_off(&mut self) {
+        println!("All channels: Off");
+    }
+
+    fn flush(&mut self) {}
+
+    fn channel_count(&self) -> usize {
+        self.channel_count
+    }
+
+    fn backend(&self) -> OutputBackend {
+        OutputBackend::Console
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::lights::LightType;
+
+/// The amount of slots in a DMX512 universe
+pub const UNIVERSE_SIZE: usize = 512;
+
+/// Connects an OpenLights channel to a DMX address
+///
+/// channel: The OpenLights channel number
+/// universe: The DMX universe
+/// address: The DMX address within the universe, from 1 to 512
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DmxPatch {
+    pub channel: i32,
+    pub universe: u16,
+    pub address: u16,
+}
+
+/// Patches the standard 16 channels to the first 16 addresses of a universe
+///
+/// universe: The universe to patch into
+pub fn default_patch(universe: u16) -> Vec<DmxPatch> {
+    (0..16)
+        .map(|channel| DmxPatch {
+            channel,
+            universe,
+            address: channel as u16 + 1,
+        })
+        .collect()
+}
+
+/// The DMX data of every patched universe
+///
+/// patch: The (universe, slot) pairs of every channel
+/// frames: The current data of every universe, sorted by universe
+pub struct DmxUniverses {
+    patch: HashMap<i32, Vec<(u16, usize)>>,
+    frames: BTreeMap<u16, [u8; UNIVERSE_SIZE]>,
+}
+
+impl DmxUniverses {
+    /// Creates blacked out universes for a patch
+    ///
+    /// patch: The channel to address mapping
+    pub fn new(patch: &[DmxPatch]) -> Result<Self, String> {
+        let mut channels: HashMap<i32, Vec<(u16, usize)>> = HashMap::new();
+        let mut frames = BTreeMap::new();
+        for entry in patch {
+            if entry.address == 0 || entry.address as usize > UNIVERSE_SIZE {
+                return Err(format!(
+                    "Channel {} is patched to DMX address {}, which is outside of 1-512",
+                    entry.channel, entry.address
+                ));
+            }
+            channels
+                .entry(entry.channel)
+                .or_default()
+                .push((entry.universe, entry.address as usize - 1));
+            frames.insert(entry.universe, [0; UNIVERSE_SIZE]);
+        }
+        if frames.is_empty() {
+            return Err("The DMX patch does not contain any channels".to_string());
+        }
+        Ok(Self {
+            patch: channels,
+            frames,
+        })
+    }
+
+    /// Sets every address patched to a channel
+    /// Unpatched channels are ignored.
+    pub fn set_channel(&mut self, channel: i32, light_type: LightType) {
+        let value = light_type.intensity();
+        if let Some(slots) = self.patch.get(&channel) {
+            for (universe, slot) in slots {
+                if let Some(frame) = self.frames.get_mut(universe) {
+                    frame[*slot] = value;
+                }
+            }
+        }
+    }
+
+    /// Sets every slot of every universe to zero
+    pub fn blackout(&mut self) {
+        for frame in self.frames.values_mut() {
+            frame.fill(0);
+        }
+    }
+
+    /// The data of every universe, sorted by universe
+    pub fn frames(&self) -> &BTreeMap<u16, [u8; UNIVERSE_SIZE]> {
+        &self.frames
+    }
+
+    /// The amount of channels covered by the patch
+    pub fn channel_count(&self) -> usize {
+        self.patch
+            .keys()
+            .filter(|channel| **channel >= 0)
+            .max()
+            .map_or(0, |channel| *channel as usize + 1)
+    }
+}
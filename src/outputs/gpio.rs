@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use rppal::gpio::{Gpio, OutputPin};
+
+use crate::lights::LightType;
+use crate::outputs::{LightOutput, OutputBackend};
+
+/// The frequency of the software PWM used for dimmed channels, in Hz
+const PWM_FREQUENCY: f64 = 200.0;
+
+/// Drives relays connected to the Raspberry Pi GPIO header
+/// Channel `n` is wired to BCM pin `n`.
+/// Dimmed channels are driven with software PWM, so they need a dimmer or SSR instead of a relay.
+pub struct GpioOutput {
+    pins: HashMap<i32, OutputPin>,
+}
+
+impl GpioOutput {
+    /// Claims the first `channel_count` GPIO pins as outputs
+    pub fn new(channel_count: usize) -> Result<Self, rppal::gpio::Error> {
+        let gpio = Gpio::new()?;
+        let mut pins = HashMap::new();
+        for i in 0..channel_count {
+            let out = gpio.get(i as u8)?.into_output_low();
+            pins.insert(i as i32, out);
+        }
+        Ok(Self { pins })
+    }
+}
+
+/// Sets the output for a pin
+///
+/// pin: The GPIO pin to interface with
+/// light_type: Whether to turn it on, off or dim it
+fn interface_gpio(pin: &mut OutputPin, light_type: LightType) {
+    match light_type {
+        LightType::On => {
+            let _ = pin.clear_pwm();
+            pin.set_high();
+        }
+        LightType::Off => {
+            let _ = pin.clear_pwm();
+            pin.set_low();
+        }
+        LightType::Dimmed(intensity) => {
+            let duty_cycle = intensity as f64 / u8::MAX as f64;
+            if pin.set_pwm_frequency(PWM_FREQUENCY, duty_cycle).is_err() {
+                pin.set_high();
+            }
+        }
+    }
+}
+
+impl LightOutput for GpioOutput {
+    fn set_channel(&mut self, channel: i32, light_type: LightType) {
+        if let Some(pin) = self.pins.get_mut(&channel) {
+            interface_gpio(pin, light_type);
+        }
+    }
+
+    fn all_off(&mut self) {
+        for pin in self.pins.values_mut() {
+            interface_gpio(pin, LightType::Off);
+        }
+    }
+
+    fn flush(&mut self) {}
+
+    fn channel_count(&self) -> usize {
+        self.pins.len()
+    }
+
+    fn backend(&self) -> OutputBackend {
+        OutputBackend::Gpio
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::LATENCY_CONFIG_PATH;
+use crate::outputs::OutputBackend;
+
+/// The largest offset that can be set in the GUI, in milliseconds
+pub const MAX_LATENCY_OFFSET: i32 = 1000;
+
+/// Offsets that line the lights up with the audio the audience hears
+/// Every offset is in milliseconds. Positive offsets delay the lights, for audio that lags
+/// behind such as Bluetooth speakers. Negative offsets fire the lights early, for outputs that
+/// are slow to react such as relays.
+/// The offsets of the global setting, the active backend and the group of a channel are added up.
+///
+/// global: The offset applied to every channel
+/// backends: The offset applied while a backend is the active output
+/// groups: The offsets applied to groups of channels
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct LatencyConfig {
+    pub global: i32,
+    pub backends: BTreeMap<OutputBackend, i32>,
+    pub groups: Vec<LatencyGroup>,
+}
+
+/// An offset for a group of channels, such as the relays of one board
+///
+/// name: The name shown in the GUI
+/// channels: The OpenLights channel numbers in the group
+/// offset: The offset of the group in milliseconds
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct LatencyGroup {
+    pub name: String,
+    pub channels: Vec<i32>,
+    pub offset: i32,
+}
+
+/// The latency configuration shared between the light thread and the GUI
+pub type SharedLatency = Arc<Mutex<LatencyConfig>>;
+
+impl LatencyConfig {
+    /// The total offset of a set of channels in milliseconds
+    /// Only the first group containing one of the channels is applied.
+    ///
+    /// backend: The active output backend
+    /// channels: The OpenLights channel numbers
+    pub fn offset(&self, backend: OutputBackend, channels: &[i32]) -> i32 {
+        let backend_offset = self.backends.get(&backend).copied().unwrap_or(0);
+        let group_offset = self
+            .groups
+            .iter()
+            .find(|group| {
+                channels
+                    .iter()
+                    .any(|channel| group.channels.contains(channel))
+            })
+            .map(|group| group.offset)
+            .unwrap_or(0);
+        self.global + backend_offset + group_offset
+    }
+
+    /// Reads the latency configuration, falling back to no offsets if the file does not exist
+    pub fn load() -> Result<Self, String> {
+        let path = Path::new(&*LATENCY_CONFIG_PATH);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        serde_json::from_str(&contents).map_err(|err| err.to_string())
+    }
+
+    /// Writes the latency configuration to disk
+    pub fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(&*LATENCY_CONFIG_PATH, contents).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LatencyConfig {
+        LatencyConfig {
+            global: 120,
+            backends: BTreeMap::from([(OutputBackend::Gpio, -40), (OutputBackend::ArtNet, 15)]),
+            groups: vec![
+                LatencyGroup {
+                    name: "Relays".to_string(),
+                    channels: vec![0, 1, 2],
+                    offset: -25,
+                },
+                LatencyGroup {
+                    name: "Spots".to_string(),
+                    channels: vec![2, 3],
+                    offset: 10,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn offsets_are_added_up() {
+        let config = config();
+        assert_eq!(config.offset(OutputBackend::Gpio, &[0]), 120 - 40 - 25);
+        assert_eq!(config.offset(OutputBackend::ArtNet, &[3]), 120 + 15 + 10);
+        assert_eq!(config.offset(OutputBackend::Virtual, &[3]), 120 + 10);
+        assert_eq!(config.offset(OutputBackend::Virtual, &[7]), 120);
+        assert_eq!(config.offset(OutputBackend::Gpio, &[]), 80);
+    }
+
+    #[test]
+    fn only_the_first_matching_group_applies() {
+        let config = config();
+        assert_eq!(config.offset(OutputBackend::Virtual, &[2]), 120 - 25);
+        assert_eq!(config.offset(OutputBackend::Virtual, &[3, 1]), 120 - 25);
+    }
+
+    #[test]
+    fn no_offsets_by_default() {
+        assert_eq!(
+            LatencyConfig::default().offset(OutputBackend::Sacn, &[0]),
+            0
+        );
+        let config: LatencyConfig =
+            serde_json::from_str(r#"{ "backends": { "sacn": 30 } }"#).unwrap();
+        assert_eq!(config.offset(OutputBackend::Sacn, &[0]), 30);
+        assert_eq!(config.offset(OutputBackend::Console, &[0]), 0);
+    }
+}
@@ -0,0 +1,208 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::OUTPUT_CONFIG_PATH;
+use crate::fixtures::FixtureConfig;
+use crate::lights::LightType;
+
+mod art_net;
+mod console;
+pub mod dmx;
+#[cfg(not(target_arch = "x86_64"))]
+mod gpio;
+mod latency;
+mod sacn;
+mod virtual_rig;
+
+pub use art_net::{art_dmx_packet, ArtNetConfig, ArtNetOutput, ART_NET_PORT};
+pub use console::ConsoleOutput;
+#[cfg(not(target_arch = "x86_64"))]
+pub use gpio::GpioOutput;
+pub use latency::{LatencyConfig, LatencyGroup, SharedLatency, MAX_LATENCY_OFFSET};
+pub use sacn::{e131_data_packet, multicast_address, SacnConfig, SacnOutput, SACN_PORT};
+pub use virtual_rig::{ChannelState, RigState, SharedRigState, Transition, VirtualRig};
+
+/// The amount of channels on the standard relay board
+pub const DEFAULT_CHANNEL_COUNT: usize = 16;
+
+/// A destination for light data
+///
+/// The light thread and the Debug screen only talk to this trait, so the same build can
+/// drive relays, network controllers or a simulator.
+/// Backends may buffer changes until `flush` is called.
+pub trait LightOutput: Send {
+    /// Sets the output of a single channel
+    ///
+    /// channel: The OpenLights channel number
+    /// light_type: The new status of the channel
+    fn set_channel(&mut self, channel: i32, light_type: LightType);
+
+    /// Sets the output of several channels at once
+    ///
+    /// channels: The OpenLights channel numbers
+    /// light_type: The new status of the channels
+    fn set_channels(&mut self, channels: &[i32], light_type: LightType) {
+        for channel in channels {
+            self.set_channel(*channel, light_type);
+        }
+    }
+
+    /// Turns off every channel
+    fn all_off(&mut self);
+
+    /// Sends any buffered changes to the hardware
+    fn flush(&mut self);
+
+    /// Blacks out every channel and ends the output stream
+    /// Called when a song stops, so streaming protocols can release their receivers.
+    fn terminate(&mut self) {
+        self.all_off();
+        self.flush();
+    }
+
+    /// The amount of channels this output drives
+    fn channel_count(&self) -> usize;
+
+    /// The backend this output belongs to
+    fn backend(&self) -> OutputBackend;
+
+    /// The simulated state of this output, if it is a virtual rig
+    fn virtual_rig(&self) -> Option<SharedRigState> {
+        None
+    }
+}
+
+/// A light output shared between the light thread, the audio thread and the GUI
+pub type SharedLightOutput = Arc<Mutex<Box<dyn LightOutput>>>;
+
+/// Every light output backend that can be selected at runtime
+///
+/// Gpio: Relays wired to the Raspberry Pi GPIO header
+/// Virtual: An in-process simulated rig shown in the GUI
+/// ArtNet: DMX512 sent to Art-Net nodes over UDP
+/// Sacn: DMX512 streamed to sACN (E1.31) receivers
+/// Console: Prints channel changes to stdout
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputBackend {
+    Gpio,
+    Virtual,
+    ArtNet,
+    Sacn,
+    Console,
+}
+
+impl Default for OutputBackend {
+    fn default() -> Self {
+        if cfg!(target_arch = "x86_64") {
+            OutputBackend::Virtual
+        } else {
+            OutputBackend::Gpio
+        }
+    }
+}
+
+impl OutputBackend {
+    /// All backends in the order they are listed in the GUI
+    pub const ALL: [OutputBackend; 5] = [
+        OutputBackend::Gpio,
+        OutputBackend::Virtual,
+        OutputBackend::ArtNet,
+        OutputBackend::Sacn,
+        OutputBackend::Console,
+    ];
+
+    /// The display name of the backend
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputBackend::Gpio => "GPIO",
+            OutputBackend::Virtual => "Virtual Rig",
+            OutputBackend::ArtNet => "Art-Net",
+            OutputBackend::Sacn => "sACN (E1.31)",
+            OutputBackend::Console => "Console",
+        }
+    }
+}
+
+/// The light output configuration stored in the OpenLights directory
+///
+/// backend: The backend to use for light output
+/// art_net: The settings of the Art-Net backend
+/// sacn: The settings of the sACN backend
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub backend: OutputBackend,
+    pub art_net: ArtNetConfig,
+    pub sacn: SacnConfig,
+}
+
+impl OutputConfig {
+    /// Creates a new output for the configured backend
+    pub fn create_output(&self) -> Result<Box<dyn LightOutput>, String> {
+        match self.backend {
+            #[cfg(not(target_arch = "x86_64"))]
+            OutputBackend::Gpio => GpioOutput::new(DEFAULT_CHANNEL_COUNT)
+                .map(|output| Box::new(output) as Box<dyn LightOutput>)
+                .map_err(|err| format!("Failed to open the GPIO pins: {}", err)),
+            #[cfg(target_arch = "x86_64")]
+            OutputBackend::Gpio => Err("GPIO output is not supported on this platform".to_string()),
+            OutputBackend::Virtual => Ok(Box::new(VirtualRig::new(virtual_channel_count()))),
+            OutputBackend::ArtNet => ArtNetOutput::new(&self.art_net)
+                .map(|output| Box::new(output) as Box<dyn LightOutput>)
+                .map_err(|err| format!("Failed to start the Art-Net output: {}", err)),
+            OutputBackend::Sacn => SacnOutput::new(&self.sacn)
+                .map(|output| Box::new(output) as Box<dyn LightOutput>)
+                .map_err(|err| format!("Failed to start the sACN output: {}", err)),
+            OutputBackend::Console => Ok(Box::new(ConsoleOutput::new(DEFAULT_CHANNEL_COUNT))),
+        }
+    }
+
+    /// Reads the output configuration, falling back to the defaults if the file does not exist
+    /// The sACN CID is generated on the first load and saved, so it stays the same from then on.
+    pub fn load() -> Result<Self, String> {
+        let path = Path::new(&*OUTPUT_CONFIG_PATH);
+        let mut config: Self = if path.exists() {
+            let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+            serde_json::from_str(&contents).map_err(|err| err.to_string())?
+        } else {
+            Self::default()
+        };
+        if config.sacn.ensure_cid() {
+            config.save()?;
+        }
+        Ok(config)
+    }
+
+    /// Writes the output configuration to disk
+    pub fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(&*OUTPUT_CONFIG_PATH, contents).map_err(|err| err.to_string())
+    }
+}
+
+/// Creates the shared output for the configured backend
+/// If the backend cannot be opened, a virtual rig is used instead and the error is returned.
+///
+/// config: The output configuration
+pub fn create_shared_output(config: &OutputConfig) -> (SharedLightOutput, Option<String>) {
+    match config.create_output() {
+        Ok(output) => (Arc::new(Mutex::new(output)), None),
+        Err(err) => (
+            Arc::new(Mutex::new(Box::new(VirtualRig::new(
+                virtual_channel_count(),
+            )))),
+            Some(err),
+        ),
+    }
+}
+
+/// The amount of channels a virtual rig simulates
+/// Covers the standard relay board and every channel used by a fixture.
+fn virtual_channel_count() -> usize {
+    let fixtures = FixtureConfig::load().unwrap_or_default();
+    DEFAULT_CHANNEL_COUNT.max(fixtures.channel_count())
+}
@@ -0,0 +1,487 @@
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lights::LightType;
+use crate::outputs::dmx::{default_patch, DmxPatch, DmxUniverses, UNIVERSE_SIZE};
+use crate::outputs::{LightOutput, OutputBackend};
+
+/// The UDP port used by sACN
+pub const SACN_PORT: u16 = 5568;
+
+/// The size of an E1.31 data packet carrying a full universe
+const PACKET_SIZE: usize = 126 + UNIVERSE_SIZE;
+
+/// The ACN packet identifier that starts every root layer
+const ACN_PACKET_IDENTIFIER: [u8; 12] = [
+    0x41, 0x53, 0x43, 0x2d, 0x45, 0x31, 0x2e, 0x31, 0x37, 0x00, 0x00, 0x00,
+];
+
+/// The framing layer option bit that marks the end of a stream
+const STREAM_TERMINATED: u8 = 0x40;
+
+/// How many terminating packets are sent per universe, as required by E1.31
+const TERMINATION_PACKETS: usize = 3;
+
+/// The sACN (E1.31) output configuration
+///
+/// source_name: The name receivers display for this source
+/// cid: The unique identifier of this source, all zeros until one is generated
+/// priority: The universe priority, from 0 to 200
+/// multicast: Whether to send to the multicast group of each universe
+/// unicast_targets: Addresses that every universe is also sent to directly
+/// refresh_rate: How many times per second every universe is resent
+/// patch: The mapping of OpenLights channels to DMX addresses
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SacnConfig {
+    pub source_name: String,
+    pub cid: [u8; 16],
+    pub priority: u8,
+    pub multicast: bool,
+    pub unicast_targets: Vec<String>,
+    pub refresh_rate: f32,
+    pub patch: Vec<DmxPatch>,
+}
+
+impl Default for SacnConfig {
+    fn default() -> Self {
+        Self {
+            source_name: "Open Lights".to_string(),
+            cid: [0; 16],
+            priority: 100,
+            multicast: true,
+            unicast_targets: Vec::new(),
+            refresh_rate: 40.0,
+            patch: default_patch(1),
+        }
+    }
+}
+
+impl SacnConfig {
+    /// Generates a random CID if there is none yet
+    /// E1.31 requires a source to keep its CID, so the config has to be saved afterwards.
+    /// Returns whether a CID was generated.
+    pub fn ensure_cid(&mut self) -> bool {
+        if self.cid != [0; 16] {
+            return false;
+        }
+        self.cid.fill_with(|| fastrand::u8(..));
+        // Mark the CID as a random (version 4) UUID
+        self.cid[6] = (self.cid[6] & 0x0F) | 0x40;
+        self.cid[8] = (self.cid[8] & 0x3F) | 0x80;
+        true
+    }
+}
+
+/// Streams DMX512 data to sACN receivers
+/// Every universe is resent at a steady rate, and immediately whenever it is flushed.
+pub struct SacnOutput {
+    pending: DmxUniverses,
+    sender: Arc<Mutex<SacnSender>>,
+    running: Arc<AtomicBool>,
+}
+
+/// The socket and the last flushed data of an sACN output
+///
+/// socket: The UDP socket packets are sent from
+/// source_name: The name receivers display for this source
+/// cid: The unique identifier of this source
+/// priority: The universe priority
+/// multicast: Whether to send to the multicast group of each universe
+/// unicast_targets: Addresses that every universe is also sent to directly
+/// frames: The DMX data that is currently being sent
+/// sequences: The sequence number of the next packet of every universe
+/// terminated: Whether the stream has ended and refreshing is paused
+struct SacnSender {
+    socket: UdpSocket,
+    source_name: String,
+    cid: [u8; 16],
+    priority: u8,
+    multicast: bool,
+    unicast_targets: Vec<SocketAddr>,
+    frames: BTreeMap<u16, [u8; UNIVERSE_SIZE]>,
+    sequences: BTreeMap<u16, u8>,
+    terminated: bool,
+}
+
+impl SacnOutput {
+    /// Opens a socket and starts the refresh thread
+    ///
+    /// config: The sACN configuration
+    pub fn new(config: &SacnConfig) -> Result<Self, String> {
+        if config.refresh_rate <= 0.0 {
+            return Err("The sACN refresh rate must be above zero".to_string());
+        }
+        if config.priority > 200 {
+            return Err("The sACN priority must be between 0 and 200".to_string());
+        }
+        if !config.multicast && config.unicast_targets.is_empty() {
+            return Err("sACN needs multicast enabled or at least one unicast target".to_string());
+        }
+        let pending = DmxUniverses::new(&config.patch)?;
+        if let Some(universe) = pending
+            .frames()
+            .keys()
+            .find(|universe| **universe == 0 || **universe > 63999)
+        {
+            return Err(format!("sACN universe {} is outside of 1-63999", universe));
+        }
+
+        let unicast_targets = config
+            .unicast_targets
+            .iter()
+            .map(|target| {
+                if target.contains(':') {
+                    target.parse::<SocketAddr>()
+                } else {
+                    format!("{}:{}", target, SACN_PORT).parse::<SocketAddr>()
+                }
+                .map_err(|err| format!("Invalid sACN target {}: {}", target, err))
+            })
+            .collect::<Result<Vec<SocketAddr>, String>>()?;
+
+        let socket = UdpSocket::bind("0.0.0.0:0").map_err(|err| err.to_string())?;
+        let sender = Arc::new(Mutex::new(SacnSender {
+            socket,
+            source_name: config.source_name.clone(),
+            cid: config.cid,
+            priority: config.priority,
+            multicast: config.multicast,
+            unicast_targets,
+            frames: pending.frames().clone(),
+            sequences: BTreeMap::new(),
+            terminated: false,
+        }));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let sender_clone = Arc::clone(&sender);
+        let running_clone = Arc::clone(&running);
+        let interval = Duration::from_secs_f32(1.0 / config.refresh_rate);
+        // Flushes send straight away, so the refresh only resends frames that were already sent
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if !running_clone.load(Ordering::Relaxed) {
+                break;
+            }
+            let mut sender = sender_clone.lock().unwrap();
+            if !sender.terminated {
+                sender.send_all(0);
+            }
+        });
+
+        Ok(Self {
+            pending,
+            sender,
+            running,
+        })
+    }
+}
+
+impl SacnSender {
+    /// Sends one data packet for every universe
+    ///
+    /// options: The framing layer option flags
+    fn send_all(&mut self, options: u8) {
+        for (universe, data) in &self.frames {
+            let sequence = self.sequences.entry(*universe).or_insert(0);
+            let packet = e131_data_packet(
+                &self.cid,
+                &self.source_name,
+                self.priority,
+                *sequence,
+                options,
+                *universe,
+                data,
+            );
+            *sequence = sequence.wrapping_add(1);
+
+            // Receivers may be offline while the show runs, so failed sends are retried on the next refresh
+            if self.multicast {
+                let _ = self.socket.send_to(
+                    &packet,
+                    SocketAddrV4::new(multicast_address(*universe), SACN_PORT),
+                );
+            }
+            for target in &self.unicast_targets {
+                let _ = self.socket.send_to(&packet, target);
+            }
+        }
+    }
+
+    /// Sends a blacked out frame and ends the stream of every universe
+    fn terminate(&mut self) {
+        for frame in self.frames.values_mut() {
+            frame.fill(0);
+        }
+        self.send_all(0);
+        for _ in 0..TERMINATION_PACKETS {
+            self.send_all(STREAM_TERMINATED);
+        }
+        self.terminated = true;
+    }
+}
+
+impl Drop for SacnOutput {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        let mut sender = self.sender.lock().unwrap();
+        if !sender.terminated {
+            sender.terminate();
+        }
+    }
+}
+
+impl LightOutput for SacnOutput {
+    fn set_channel(&mut self, channel: i32, light_type: LightType) {
+        self.pending.set_channel(channel, light_type);
+    }
+
+    fn all_off(&mut self) {
+        self.pending.blackout();
+    }
+
+    fn flush(&mut self) {
+        let mut sender = self.sender.lock().unwrap();
+        sender.frames.clone_from(self.pending.frames());
+        sender.terminated = false;
+        sender.send_all(0);
+    }
+
+    fn terminate(&mut self) {
+        self.pending.blackout();
+        self.sender.lock().unwrap().terminate();
+    }
+
+    fn channel_count(&self) -> usize {
+        self.pending.channel_count()
+    }
+
+    fn backend(&self) -> OutputBackend {
+        OutputBackend::Sacn
+    }
+}
+
+/// The multicast group of a universe
+///
+/// universe: The sACN universe, from 1 to 63999
+pub fn multicast_address(universe: u16) -> Ipv4Addr {
+    let [high, low] = universe.to_be_bytes();
+    Ipv4Addr::new(239, 255, high, low)
+}
+
+/// Builds an E1.31 data packet for a full universe
+///
+/// cid: The unique identifier of the source
+/// source_name: The name of the source, truncated to 63 bytes
+/// priority: The universe priority
+/// sequence: The sequence number of the packet
+/// options: The framing layer option flags
+/// universe: The sACN universe
+/// data: The DMX slots, at most 512
+pub fn e131_data_packet(
+    cid: &[u8; 16],
+    source_name: &str,
+    priority: u8,
+    sequence: u8,
+    options: u8,
+    universe: u16,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(PACKET_SIZE);
+
+    // Root layer
+    packet.extend_from_slice(&0x0010u16.to_be_bytes()); // Preamble size
+    packet.extend_from_slice(&0x0000u16.to_be_bytes()); // Postamble size
+    packet.extend_from_slice(&ACN_PACKET_IDENTIFIER);
+    packet.extend_from_slice(&flags_and_length(PACKET_SIZE - 16));
+    packet.extend_from_slice(&0x0000_0004u32.to_be_bytes()); // VECTOR_ROOT_E131_DATA
+    packet.extend_from_slice(cid);
+
+    // Framing layer
+    packet.extend_from_slice(&flags_and_length(PACKET_SIZE - 38));
+    packet.extend_from_slice(&0x0000_0002u32.to_be_bytes()); // VECTOR_E131_DATA_PACKET
+    let mut name = [0u8; 64];
+    let name_bytes = source_name.as_bytes();
+    let name_length = name_bytes.len().min(63);
+    name[..name_length].copy_from_slice(&name_bytes[..name_length]);
+    packet.extend_from_slice(&name);
+    packet.push(priority);
+    packet.extend_from_slice(&0u16.to_be_bytes()); // Synchronization address
+    packet.push(sequence);
+    packet.push(options);
+    packet.extend_from_slice(&universe.to_be_bytes());
+
+    // DMP layer
+    packet.extend_from_slice(&flags_and_length(PACKET_SIZE - 115));
+    packet.push(0x02); // VECTOR_DMP_SET_PROPERTY
+    packet.push(0xa1); // Address and data type
+    packet.extend_from_slice(&0u16.to_be_bytes()); // First property address
+    packet.extend_from_slice(&1u16.to_be_bytes()); // Address increment
+    packet.extend_from_slice(&(UNIVERSE_SIZE as u16 + 1).to_be_bytes()); // Property value count
+    packet.push(0x00); // DMX start code
+    let data_length = data.len().min(UNIVERSE_SIZE);
+    packet.extend_from_slice(&data[..data_length]);
+    packet.resize(PACKET_SIZE, 0);
+    packet
+}
+
+/// Encodes the flags and length field of a PDU
+fn flags_and_length(length: usize) -> [u8; 2] {
+    (0x7000 | length as u16).to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The offsets of the framing layer fields in a data packet
+    const PRIORITY: usize = 108;
+    const SEQUENCE: usize = 111;
+    const OPTIONS: usize = 112;
+    const UNIVERSE: usize = 113;
+    const DATA: usize = 126;
+
+    /// A socket on the loopback address and an output that sends to it
+    fn loopback_output() -> (UdpSocket, SacnOutput) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let config = SacnConfig {
+            priority: 150,
+            multicast: false,
+            unicast_targets: vec![socket.local_addr().unwrap().to_string()],
+            // Slow enough that the refresh thread never sends while the test runs
+            refresh_rate: 0.1,
+            ..Default::default()
+        };
+        (socket, SacnOutput::new(&config).unwrap())
+    }
+
+    fn receive(socket: &UdpSocket) -> Vec<u8> {
+        let mut buffer = [0; 1024];
+        let (length, _) = socket.recv_from(&mut buffer).unwrap();
+        buffer[..length].to_vec()
+    }
+
+    #[test]
+    fn data_packet_layout() {
+        let cid = [7; 16];
+        let packet = e131_data_packet(&cid, "Open Lights", 100, 42, 0, 0x1234, &[1, 2, 3]);
+        assert_eq!(packet.len(), 638);
+        assert_eq!(packet[0..4], [0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(packet[4..16], ACN_PACKET_IDENTIFIER);
+        assert_eq!(packet[16..18], [0x72, 0x6e]);
+        assert_eq!(packet[18..22], [0, 0, 0, 4]);
+        assert_eq!(packet[22..38], cid);
+        assert_eq!(packet[38..40], [0x72, 0x58]);
+        assert_eq!(packet[40..44], [0, 0, 0, 2]);
+        assert_eq!(&packet[44..55], b"Open Lights");
+        assert!(packet[55..108].iter().all(|byte| *byte == 0));
+        assert_eq!(packet[PRIORITY], 100);
+        assert_eq!(packet[SEQUENCE], 42);
+        assert_eq!(packet[OPTIONS], 0);
+        assert_eq!(packet[UNIVERSE..UNIVERSE + 2], [0x12, 0x34]);
+        assert_eq!(packet[115..117], [0x72, 0x0b]);
+        assert_eq!(packet[117..125], [0x02, 0xa1, 0, 0, 0, 1, 0x02, 0x01]);
+        assert_eq!(packet[125], 0);
+        assert_eq!(packet[DATA..DATA + 4], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn long_source_names_are_truncated() {
+        let name = "a".repeat(100);
+        let packet = e131_data_packet(&[0; 16], &name, 100, 0, 0, 1, &[]);
+        assert_eq!(packet.len(), 638);
+        assert!(packet[44..107].iter().all(|byte| *byte == b'a'));
+        assert_eq!(packet[107], 0);
+    }
+
+    #[test]
+    fn multicast_address_of_universe() {
+        assert_eq!(multicast_address(1), Ipv4Addr::new(239, 255, 0, 1));
+        assert_eq!(multicast_address(63999), Ipv4Addr::new(239, 255, 249, 255));
+    }
+
+    #[test]
+    fn unicast_stream_and_termination() {
+        let (socket, mut output) = loopback_output();
+        output.flush();
+        output.set_channel(0, LightType::On);
+        output.flush();
+        output.set_channel(1, LightType::Dimmed(60));
+        output.flush();
+        output.terminate();
+        let packets: Vec<Vec<u8>> = (0..4 + TERMINATION_PACKETS)
+            .map(|_| receive(&socket))
+            .collect();
+
+        for (index, packet) in packets.iter().enumerate() {
+            assert_eq!(packet.len(), PACKET_SIZE);
+            assert_eq!(packet[PRIORITY], 150);
+            assert_eq!(packet[UNIVERSE..UNIVERSE + 2], [0, 1]);
+            if index > 0 {
+                let previous = packets[index - 1][SEQUENCE];
+                assert_eq!(packet[SEQUENCE], previous.wrapping_add(1));
+            }
+        }
+
+        assert!(packets[0][DATA..].iter().all(|slot| *slot == 0));
+        assert_eq!(packets[1][DATA..DATA + 2], [255, 0]);
+        assert_eq!(packets[2][DATA..DATA + 2], [255, 60]);
+        // The blacked out frame before the stream ends
+        assert_eq!(packets[3][OPTIONS], 0);
+        assert!(packets[3][DATA..].iter().all(|slot| *slot == 0));
+        for packet in &packets[4..] {
+            assert_eq!(packet[OPTIONS], STREAM_TERMINATED);
+            assert!(packet[DATA..].iter().all(|slot| *slot == 0));
+        }
+    }
+
+    #[test]
+    fn dropping_the_output_terminates_the_stream() {
+        let (socket, mut output) = loopback_output();
+        output.flush();
+        drop(output);
+
+        let mut terminated = 0;
+        while terminated < TERMINATION_PACKETS {
+            if receive(&socket)[OPTIONS] == STREAM_TERMINATED {
+                terminated += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn cid_is_generated_once() {
+        let mut config = SacnConfig::default();
+        assert_eq!(config.cid, [0; 16]);
+        assert!(config.ensure_cid());
+        assert_ne!(config.cid, [0; 16]);
+        assert_eq!(config.cid[6] >> 4, 4);
+        let cid = config.cid;
+        assert!(!config.ensure_cid());
+        assert_eq!(config.cid, cid);
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let mut config = SacnConfig {
+            priority: 201,
+            ..Default::default()
+        };
+        assert!(SacnOutput::new(&config).is_err());
+        config.priority = 100;
+        config.multicast = false;
+        assert!(SacnOutput::new(&config).is_err());
+        config.unicast_targets = vec!["127.0.0.1".to_string()];
+        config.patch[0].universe = 0;
+        assert!(SacnOutput::new(&config).is_err());
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::lights::LightType;
+use crate::outputs::{LightOutput, OutputBackend};
+
+/// The maximum amount of transitions kept in the history
+const HISTORY_LENGTH: usize = 500;
+
+/// Simulates a light rig in memory
+/// Lets shows be authored and verified without a Raspberry Pi.
+pub struct VirtualRig {
+    state: SharedRigState,
+}
+
+impl VirtualRig {
+    pub fn new(channel_count: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(RigState::new(channel_count))),
+        }
+    }
+}
+
+impl LightOutput for VirtualRig {
+    fn set_channel(&mut self, channel: i32, light_type: LightType) {
+        self.state.lock().unwrap().set_channel(channel, light_type);
+    }
+
+    fn set_channels(&mut self, channels: &[i32], light_type: LightType) {
+        let mut state = self.state.lock().unwrap();
+        for channel in channels {
+            state.set_channel(*channel, light_type);
+        }
+    }
+
+    fn all_off(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        for channel in 0..state.channels.len() {
+            state.set_channel(channel as i32, LightType::Off);
+        }
+    }
+
+    fn flush(&mut self) {}
+
+    fn channel_count(&self) -> usize {
+        self.state.lock().unwrap().channels.len()
+    }
+
+    fn backend(&self) -> OutputBackend {
+        OutputBackend::Virtual
+    }
+
+    fn virtual_rig(&self) -> Option<SharedRigState> {
+        Some(Arc::clone(&self.state))
+    }
+}
+
+/// The state of a virtual rig shared with the GUI
+pub type SharedRigState = Arc<Mutex<RigState>>;
+
+/// The simulated channels and their history
+///
+/// started: When statistics started being recorded
+/// channels: The state of every channel
+/// history: The most recent transitions, oldest first
+pub struct RigState {
+    started: Instant,
+    channels: Vec<ChannelState>,
+    history: VecDeque<Transition>,
+}
+
+/// The state of a simulated channel
+///
+/// light_type: The current status of the channel
+/// last_change: The time of the last transition since statistics started
+/// on_time: How long the channel has been on, excluding the current on period
+/// transitions: How many times the channel has changed
+#[derive(Clone)]
+pub struct ChannelState {
+    pub light_type: LightType,
+    pub last_change: Duration,
+    on_time: Duration,
+    pub transitions: u32,
+}
+
+/// A change of a simulated channel
+///
+/// time: The time since statistics started
+/// channel: The channel that changed
+/// light_type: The new status of the channel
+#[derive(Clone)]
+pub struct Transition {
+    pub time: Duration,
+    pub channel: i32,
+    pub light_type: LightType,
+}
+
+impl RigState {
+    fn new(channel_count: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            channels: vec![
+                ChannelState {
+                    light_type: LightType::Off,
+                    last_change: Duration::ZERO,
+                    on_time: Duration::ZERO,
+                    transitions: 0,
+                };
+                channel_count
+            ],
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+        }
+    }
+
+    /// Changes a channel and records the transition
+    /// Channels outside the rig and repeated states are ignored.
+    fn set_channel(&mut self, channel: i32, light_type: LightType) {
+        let now = self.started.elapsed();
+        let Some(state) = usize::try_from(channel)
+            .ok()
+            .and_then(|index| self.channels.get_mut(index))
+        else {
+            return;
+        };
+        if state.light_type == light_type {
+            return;
+        }
+
+        if state.light_type.is_on() {
+            state.on_time += now - state.last_change;
+        }
+        state.light_type = light_type;
+        state.last_change = now;
+        state.transitions += 1;
+
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(Transition {
+            time: now,
+            channel,
+            light_type,
+        });
+    }
+
+    /// The state of every channel
+    pub fn channels(&self) -> &[ChannelState] {
+        &self.channels
+    }
+
+    /// The most recent transitions, oldest first
+    pub fn history(&self) -> &VecDeque<Transition> {
+        &self.history
+    }
+
+    /// The time since statistics started being recorded
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// The fraction of time a channel has been on, from 0.0 to 1.0
+    ///
+    /// channel: The index of the channel
+    pub fn duty_cycle(&self, channel: usize) -> f32 {
+        let Some(state) = self.channels.get(channel) else {
+            return 0.0;
+        };
+        let elapsed = self.started.elapsed();
+        let mut on_time = state.on_time;
+        if state.light_type.is_on() {
+            on_time += elapsed - state.last_change;
+        }
+        if elapsed.is_zero() {
+            0.0
+        } else {
+            on_time.as_secs_f32() / elapsed.as_secs_f32()
+        }
+    }
+
+    /// Clears the history and statistics while keeping the current channel states
+    pub fn reset_statistics(&mut self) {
+        self.started = Instant::now();
+        self.history.clear();
+        for state in &mut self.channels {
+            state.last_change = Duration::ZERO;
+            state.on_time = Duration::ZERO;
+            state.transitions = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn changes_are_recorded() {
+        let mut rig = VirtualRig::new(4);
+        let state = rig.virtual_rig().unwrap();
+        rig.set_channel(1, LightType::On);
+        rig.set_channels(&[2, 3], LightType::Dimmed(40));
+        rig.set_channel(1, LightType::Off);
+
+        let state = state.lock().unwrap();
+        let channels = state.channels();
+        assert_eq!(channels[0].light_type, LightType::Off);
+        assert_eq!(channels[1].light_type, LightType::Off);
+        assert_eq!(channels[1].transitions, 2);
+        assert_eq!(channels[2].light_type, LightType::Dimmed(40));
+        let history: Vec<(i32, LightType)> = state
+            .history()
+            .iter()
+            .map(|transition| (transition.channel, transition.light_type))
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                (1, LightType::On),
+                (2, LightType::Dimmed(40)),
+                (3, LightType::Dimmed(40)),
+                (1, LightType::Off),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_states_and_unknown_channels_are_ignored() {
+        let mut rig = VirtualRig::new(2);
+        let state = rig.virtual_rig().unwrap();
+        rig.set_channel(0, LightType::Off);
+        rig.set_channel(1, LightType::On);
+        rig.set_channel(1, LightType::On);
+        rig.set_channel(2, LightType::On);
+        rig.set_channel(-1, LightType::On);
+        assert_eq!(rig.channel_count(), 2);
+
+        let state = state.lock().unwrap();
+        assert_eq!(state.history().len(), 1);
+        assert_eq!(state.channels()[0].transitions, 0);
+        assert_eq!(state.channels()[1].transitions, 1);
+    }
+
+    #[test]
+    fn history_keeps_the_latest_transitions() {
+        let mut rig = VirtualRig::new(1);
+        let state = rig.virtual_rig().unwrap();
+        for index in 0..HISTORY_LENGTH + 10 {
+            let light_type = if index % 2 == 0 {
+                LightType::On
+            } else {
+                LightType::Off
+            };
+            rig.set_channel(0, light_type);
+        }
+
+        let state = state.lock().unwrap();
+        assert_eq!(state.history().len(), HISTORY_LENGTH);
+        assert_eq!(state.history()[0].light_type, LightType::On);
+        assert_eq!(
+            state.channels()[0].transitions as usize,
+            HISTORY_LENGTH + 10
+        );
+    }
+
+    #[test]
+    fn all_off_and_reset_statistics() {
+        let mut rig = VirtualRig::new(3);
+        let state = rig.virtual_rig().unwrap();
+        rig.set_channels(&[0, 2], LightType::On);
+        rig.all_off();
+        rig.set_channel(1, LightType::On);
+
+        let mut state = state.lock().unwrap();
+        assert_eq!(state.history().len(), 5);
+        state.reset_statistics();
+        assert!(state.history().is_empty());
+        assert_eq!(state.channels()[0].light_type, LightType::Off);
+        assert_eq!(state.channels()[1].light_type, LightType::On);
+        assert!(state
+            .channels()
+            .iter()
+            .all(|channel| channel.transitions == 0));
+    }
+
+    #[test]
+    fn duty_cycle_follows_the_time_on() {
+        let mut rig = VirtualRig::new(2);
+        let state = rig.virtual_rig().unwrap();
+        state.lock().unwrap().reset_statistics();
+        rig.set_channel(0, LightType::On);
+        thread::sleep(Duration::from_millis(40));
+        rig.set_channel(0, LightType::Off);
+        thread::sleep(Duration::from_millis(40));
+
+        let state = state.lock().unwrap();
+        let duty_cycle = state.duty_cycle(0);
+        assert!(duty_cycle > 0.2 && duty_cycle < 0.8, "{}", duty_cycle);
+        assert_eq!(state.duty_cycle(1), 0.0);
+        assert_eq!(state.duty_cycle(5), 0.0);
+    }
+}
@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::PLAYBACK_CONFIG_PATH;
+
+/// The longest crossfade that can be set, in seconds
+pub const MAX_CROSSFADE: f32 = 10.0;
+
+/// How songs are played back
+///
+/// crossfade: How long one song fades into the next in seconds, 0 for gapless playback
+/// normalize: Whether every song is brought to the same loudness
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PlaybackConfig {
+    pub crossfade: f32,
+    pub normalize: bool,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> Self {
+        Self {
+            crossfade: 0.0,
+            normalize: true,
+        }
+    }
+}
+
+/// The playback configuration shared between the audio thread and the GUI
+pub type SharedPlaybackConfig = Arc<Mutex<PlaybackConfig>>;
+
+impl PlaybackConfig {
+    /// The length of the crossfade, limited to `MAX_CROSSFADE`
+    pub fn crossfade(&self) -> Duration {
+        Duration::from_secs_f32(self.crossfade.clamp(0.0, MAX_CROSSFADE))
+    }
+
+    /// Reads the playback configuration, falling back to the defaults if the file does not exist
+    pub fn load() -> Result<Self, String> {
+        let path = Path::new(&*PLAYBACK_CONFIG_PATH);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        serde_json::from_str(&contents).map_err(|err| err.to_string())
+    }
+
+    /// Writes the playback configuration to disk
+    pub fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(&*PLAYBACK_CONFIG_PATH, contents).map_err(|err| err.to_string())
+    }
+}
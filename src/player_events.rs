@@ -0,0 +1,299 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::audio_player::Song;
+use crate::shuffle::ShuffleMode;
+
+/// Something that happened in the audio thread
+///
+/// PlaylistLoaded: The song list was loaded or changed, with the index of the current song
+/// TrackChanged: A song was loaded for playback
+/// Position: The playback position moved, sent regularly while playing and after seeking
+/// PlaybackChanged: The audio started or stopped playing
+/// LoopingChanged: Looping of the current song was turned on or off
+/// ShuffleChanged: The order the songs play in changed
+/// QueueChanged: The up next queue or the play history changed, both as indices of the song list
+/// Error: Something went wrong without a command to reply to, such as moving to the next song
+#[derive(Clone)]
+pub enum PlayerEvent {
+    PlaylistLoaded {
+        songs: Vec<Song>,
+        song_index: usize,
+    },
+    TrackChanged {
+        index: usize,
+        song: Song,
+    },
+    Position {
+        position: Duration,
+        duration: Duration,
+    },
+    PlaybackChanged(bool),
+    LoopingChanged(bool),
+    ShuffleChanged(ShuffleMode),
+    QueueChanged {
+        up_next: Vec<usize>,
+        history: Vec<usize>,
+    },
+    Error(String),
+}
+
+/// The state of the player as seen through its events
+/// Subscribers can keep their own copy up to date with `apply`.
+///
+/// songs: The song list
+/// song_index: The index of the current song in the song list
+/// playing: Whether the audio is playing
+/// looping: Whether the current song repeats
+/// shuffle: The order the songs play in
+/// position: The playback position in the current song
+/// duration: The length of the current song
+/// up_next: The indices of the songs queued to play next, in order
+/// history: The indices of the songs played before the current one, the latest last
+#[derive(Clone, Default)]
+pub struct PlayerState {
+    pub songs: Vec<Song>,
+    pub song_index: usize,
+    pub playing: bool,
+    pub looping: bool,
+    pub shuffle: ShuffleMode,
+    pub position: Duration,
+    pub duration: Duration,
+    pub up_next: Vec<usize>,
+    pub history: Vec<usize>,
+}
+
+impl PlayerState {
+    /// Updates the state with an event
+    pub fn apply(&mut self, event: &PlayerEvent) {
+        match event {
+            PlayerEvent::PlaylistLoaded { songs, song_index } => {
+                self.songs.clone_from(songs);
+                self.song_index = *song_index;
+            }
+            PlayerEvent::TrackChanged { index, song } => {
+                self.song_index = *index;
+                self.duration = Duration::from_secs_f32(song.duration.max(0.0));
+                self.position = Duration::ZERO;
+            }
+            PlayerEvent::Position { position, duration } => {
+                self.position = *position;
+                self.duration = *duration;
+            }
+            PlayerEvent::PlaybackChanged(playing) => self.playing = *playing,
+            PlayerEvent::LoopingChanged(looping) => self.looping = *looping,
+            PlayerEvent::ShuffleChanged(shuffle) => self.shuffle = *shuffle,
+            PlayerEvent::QueueChanged { up_next, history } => {
+                self.up_next.clone_from(up_next);
+                self.history.clone_from(history);
+            }
+            PlayerEvent::Error(_) => {}
+        }
+    }
+
+    /// The song at the current index, if any songs are loaded
+    pub fn current_song(&self) -> Option<&Song> {
+        self.songs.get(self.song_index)
+    }
+
+    /// How far through the current song playback is, from 0.0 to 1.0
+    pub fn progress(&self) -> f32 {
+        if self.duration.is_zero() {
+            return 0.0;
+        }
+        (self.position.as_secs_f32() / self.duration.as_secs_f32()).min(1.0)
+    }
+
+    /// The events that bring an empty state up to this state
+    fn snapshot(&self) -> Vec<PlayerEvent> {
+        let mut events = vec![PlayerEvent::PlaylistLoaded {
+            songs: self.songs.clone(),
+            song_index: self.song_index,
+        }];
+        if let Some(song) = self.current_song() {
+            events.push(PlayerEvent::TrackChanged {
+                index: self.song_index,
+                song: song.clone(),
+            });
+        }
+        events.push(PlayerEvent::Position {
+            position: self.position,
+            duration: self.duration,
+        });
+        events.push(PlayerEvent::PlaybackChanged(self.playing));
+        events.push(PlayerEvent::LoopingChanged(self.looping));
+        events.push(PlayerEvent::ShuffleChanged(self.shuffle));
+        events.push(PlayerEvent::QueueChanged {
+            up_next: self.up_next.clone(),
+            history: self.history.clone(),
+        });
+        events
+    }
+}
+
+/// Sends the events of the audio thread to every subscriber
+///
+/// subscribers: The channels of every subscriber
+/// state: The state after every event published so far
+#[derive(Default)]
+pub struct EventHub {
+    subscribers: Vec<Sender<PlayerEvent>>,
+    state: PlayerState,
+}
+
+/// An event hub shared between the audio thread and its subscribers
+pub type SharedEventHub = Arc<Mutex<EventHub>>;
+
+impl EventHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a channel that receives every event from now on
+    /// The channel starts with events describing the current state, so subscribers do not
+    /// have to ask the audio thread for it.
+    pub fn subscribe(&mut self) -> Receiver<PlayerEvent> {
+        let (sender, receiver) = mpsc::channel();
+        for event in self.state.snapshot() {
+            let _ = sender.send(event);
+        }
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Sends an event to every subscriber, forgetting subscribers that have gone away
+    pub fn publish(&mut self, event: PlayerEvent) {
+        self.state.apply(&event);
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// The state after every event published so far
+    pub fn state(&self) -> &PlayerState {
+        &self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A song with a name and a length in seconds
+    fn song(name: &str, duration: f32) -> Song {
+        Song {
+            name: name.to_string(),
+            duration,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn apply_updates_the_state() {
+        let mut state = PlayerState::default();
+        state.apply(&PlayerEvent::PlaylistLoaded {
+            songs: vec![song("A", 100.0), song("B", 200.0)],
+            song_index: 0,
+        });
+        assert_eq!(state.songs.len(), 2);
+        assert_eq!(state.current_song().unwrap().name, "A");
+
+        state.apply(&PlayerEvent::Position {
+            position: Duration::from_secs(50),
+            duration: Duration::from_secs(100),
+        });
+        assert_eq!(state.progress(), 0.5);
+
+        state.apply(&PlayerEvent::TrackChanged {
+            index: 1,
+            song: song("B", 200.0),
+        });
+        assert_eq!(state.current_song().unwrap().name, "B");
+        assert_eq!(state.position, Duration::ZERO);
+        assert_eq!(state.duration, Duration::from_secs(200));
+
+        state.apply(&PlayerEvent::PlaybackChanged(true));
+        state.apply(&PlayerEvent::LoopingChanged(true));
+        state.apply(&PlayerEvent::ShuffleChanged(ShuffleMode::Smart));
+        state.apply(&PlayerEvent::QueueChanged {
+            up_next: vec![0],
+            history: vec![0, 1],
+        });
+        state.apply(&PlayerEvent::Error("Failed".to_string()));
+        assert!(state.playing);
+        assert!(state.looping);
+        assert_eq!(state.shuffle, ShuffleMode::Smart);
+        assert_eq!(state.up_next, vec![0]);
+        assert_eq!(state.history, vec![0, 1]);
+    }
+
+    #[test]
+    fn progress_is_clamped() {
+        let mut state = PlayerState::default();
+        assert_eq!(state.progress(), 0.0);
+        state.apply(&PlayerEvent::Position {
+            position: Duration::from_secs(12),
+            duration: Duration::from_secs(10),
+        });
+        assert_eq!(state.progress(), 1.0);
+
+        // Songs with an unknown length do not have a negative duration
+        state.apply(&PlayerEvent::TrackChanged {
+            index: 0,
+            song: song("A", -1.0),
+        });
+        assert_eq!(state.progress(), 0.0);
+    }
+
+    #[test]
+    fn subscribers_start_with_the_current_state() {
+        let mut hub = EventHub::new();
+        hub.publish(PlayerEvent::PlaylistLoaded {
+            songs: vec![song("A", 100.0), song("B", 200.0)],
+            song_index: 0,
+        });
+        hub.publish(PlayerEvent::TrackChanged {
+            index: 1,
+            song: song("B", 200.0),
+        });
+        hub.publish(PlayerEvent::Position {
+            position: Duration::from_secs(20),
+            duration: Duration::from_secs(200),
+        });
+        hub.publish(PlayerEvent::PlaybackChanged(true));
+        hub.publish(PlayerEvent::QueueChanged {
+            up_next: vec![0],
+            history: vec![0],
+        });
+
+        let receiver = hub.subscribe();
+        let mut state = PlayerState::default();
+        for event in receiver.try_iter() {
+            state.apply(&event);
+        }
+        assert_eq!(state.current_song().unwrap().name, "B");
+        assert_eq!(state.position, Duration::from_secs(20));
+        assert_eq!(state.duration, Duration::from_secs(200));
+        assert!(state.playing);
+        assert_eq!(state.up_next, vec![0]);
+        assert_eq!(state.history, vec![0]);
+    }
+
+    #[test]
+    fn subscribers_that_went_away_are_forgotten() {
+        let mut hub = EventHub::new();
+        let kept = hub.subscribe();
+        drop(hub.subscribe());
+        assert_eq!(hub.subscribers.len(), 2);
+
+        let snapshot = kept.try_iter().count();
+        hub.publish(PlayerEvent::LoopingChanged(true));
+        assert_eq!(hub.subscribers.len(), 1);
+        assert!(matches!(
+            kept.try_iter().collect::<Vec<_>>()[..],
+            [PlayerEvent::LoopingChanged(true)]
+        ));
+        assert!(snapshot > 0);
+        assert!(hub.state().looping);
+    }
+}
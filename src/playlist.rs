@@ -0,0 +1,469 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio_player::Song;
+use crate::constants::PLAYLIST_EXTENSIONS;
+use crate::library::MediaLibrary;
+
+/// The M3U directive VLC uses for the position a song starts at
+const M3U_START_TIME: &str = "#EXTVLCOPT:start-time=";
+
+/// The M3U directive for the overrides that only Open Lights understands
+const M3U_OPEN_LIGHTS: &str = "#EXTOPENLIGHTS:";
+
+/// One song of a playlist file
+///
+/// path: The audio file, absolute or relative to the playlist file
+/// start: Where the song starts playing in seconds
+/// volume: The factor the volume of the song is multiplied with
+/// light_file: The light file to play instead of the default light track of the song
+/// info: The M3U `#EXTINF` line of the song, its length and name
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlaylistEntry {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub start: f32,
+    #[serde(default = "full_volume", skip_serializing_if = "is_full_volume")]
+    pub volume: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light_file: Option<PathBuf>,
+    #[serde(skip)]
+    pub info: Option<String>,
+}
+
+fn full_volume() -> f32 {
+    1.0
+}
+
+fn is_full_volume(volume: &f32) -> bool {
+    *volume == 1.0
+}
+
+fn is_zero(value: &f32) -> bool {
+    *value == 0.0
+}
+
+impl PlaylistEntry {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            start: 0.0,
+            volume: full_volume(),
+            light_file: None,
+            info: None,
+        }
+    }
+
+    /// Applies the overrides of the entry to its song
+    fn apply(&self, song: &mut Song) {
+        song.start = self.start.clamp(0.0, song.duration.max(0.0));
+        song.volume = self.volume.max(0.0);
+        if let Some(light_file) = &self.light_file {
+            match song.light_files.iter().position(|path| path == light_file) {
+                Some(light_track) => song.light_track = light_track,
+                None => {
+                    song.light_files.insert(0, light_file.clone());
+                    song.light_track = 0;
+                }
+            }
+        }
+    }
+}
+
+/// A playlist stored as a single file, which lists songs from anywhere in the library in order
+/// M3U, PLS and JSON files are supported. Only JSON and M3U keep the overrides of each entry.
+///
+/// entries: The songs of the playlist in the order they play
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PlaylistFile {
+    pub entries: Vec<PlaylistEntry>,
+}
+
+impl PlaylistFile {
+    /// Reads a playlist file, going by its extension for the format
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        match playlist_extension(path).as_deref() {
+            Some("m3u") | Some("m3u8") => Ok(parse_m3u(&contents)),
+            Some("pls") => Ok(parse_pls(&contents)),
+            Some("json") => serde_json::from_str(&contents).map_err(|err| err.to_string()),
+            _ => Err(format!("{} is not a playlist file", path.display())),
+        }
+    }
+
+    /// Writes the playlist, going by the extension of the path for the format
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let contents = match playlist_extension(path).as_deref() {
+            Some("m3u") | Some("m3u8") => self.to_m3u(),
+            Some("pls") => self.to_pls(),
+            Some("json") => serde_json::to_string_pretty(self).map_err(|err| err.to_string())?,
+            _ => return Err(format!("{} is not a playlist file", path.display())),
+        };
+        fs::write(path, contents).map_err(|err| err.to_string())
+    }
+
+    /// A playlist of songs as they are loaded, keeping their start, volume and light track
+    /// A light track other than the default is kept as a light file override.
+    pub fn from_songs(songs: &[Song]) -> Self {
+        let entries = songs
+            .iter()
+            .map(|song| PlaylistEntry {
+                path: song.path.clone(),
+                start: song.start,
+                volume: song.volume,
+                light_file: song.light_file().filter(|_| song.light_track != 0).cloned(),
+                info: Some(format!(
+                    "{},{} - {}",
+                    song.duration.round() as i64,
+                    song.artist,
+                    song.name
+                )),
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Makes every relative path absolute
+    ///
+    /// directory: The directory of the playlist file, which relative paths start from
+    pub fn resolve(&mut self, directory: &Path) {
+        for entry in &mut self.entries {
+            entry.path = directory.join(&entry.path);
+            if let Some(light_file) = &mut entry.light_file {
+                *light_file = directory.join(&*light_file);
+            }
+        }
+    }
+
+    /// Removes the entry of a song
+    ///
+    /// directory: The directory of the playlist file, which relative paths start from
+    /// path: The resolved path of the song
+    /// occurrence: Which entry of the song to remove, counting from 0, for songs listed twice
+    pub fn remove(&mut self, directory: &Path, path: &Path, occurrence: usize) {
+        let index = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| directory.join(&entry.path) == path)
+            .nth(occurrence)
+            .map(|(index, _)| index);
+        if let Some(index) = index {
+            self.entries.remove(index);
+        }
+    }
+
+    fn to_m3u(&self) -> String {
+        let mut lines = vec!["#EXTM3U".to_string()];
+        for entry in &self.entries {
+            if let Some(info) = &entry.info {
+                lines.push(format!("#EXTINF:{}", info));
+            }
+            if entry.start != 0.0 {
+                lines.push(format!("{}{}", M3U_START_TIME, entry.start));
+            }
+            if entry.volume != 1.0 {
+                lines.push(format!("{}volume={}", M3U_OPEN_LIGHTS, entry.volume));
+            }
+            if let Some(light_file) = &entry.light_file {
+                lines.push(format!(
+                    "{}light-file={}",
+                    M3U_OPEN_LIGHTS,
+                    light_file.display()
+                ));
+            }
+            lines.push(entry.path.display().to_string());
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    fn to_pls(&self) -> String {
+        let mut lines = vec!["[playlist]".to_string()];
+        for (index, entry) in self.entries.iter().enumerate() {
+            let number = index + 1;
+            lines.push(format!("File{}={}", number, entry.path.display()));
+            if let Some((length, title)) = entry.info.as_ref().and_then(|info| info.split_once(','))
+            {
+                lines.push(format!("Title{}={}", number, title));
+                lines.push(format!("Length{}={}", number, length));
+            }
+        }
+        lines.push(format!("NumberOfEntries={}", self.entries.len()));
+        lines.push("Version=2".to_string());
+        lines.push(String::new());
+        lines.join("\n")
+    }
+}
+
+/// Reads an M3U playlist
+/// Start positions use the `#EXTVLCOPT:start-time=` directive of VLC, while the volume and
+/// light file use `#EXTOPENLIGHTS:volume=` and `#EXTOPENLIGHTS:light-file=`. Streams are skipped.
+fn parse_m3u(contents: &str) -> PlaylistFile {
+    let mut entries = Vec::new();
+    let mut next = PlaylistEntry::new(PathBuf::new());
+    for line in contents.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            next.info = Some(info.to_string());
+        } else if let Some(start) = line.strip_prefix(M3U_START_TIME) {
+            next.start = start.trim().parse().unwrap_or(0.0);
+        } else if let Some(option) = line.strip_prefix(M3U_OPEN_LIGHTS) {
+            match option.split_once('=') {
+                Some(("volume", volume)) => next.volume = volume.trim().parse().unwrap_or(1.0),
+                Some(("light-file", light_file)) => {
+                    next.light_file = Some(PathBuf::from(light_file.trim()))
+                }
+                _ => {}
+            }
+        } else if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else {
+            let path = line.strip_prefix("file://").unwrap_or(line);
+            if !path.contains("://") {
+                next.path = PathBuf::from(path);
+                entries.push(next);
+            }
+            next = PlaylistEntry::new(PathBuf::new());
+        }
+    }
+    PlaylistFile { entries }
+}
+
+/// Reads a PLS playlist, ordering the songs by their number
+fn parse_pls(contents: &str) -> PlaylistFile {
+    let mut files = BTreeMap::new();
+    let mut info = BTreeMap::new();
+    for line in contents.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_string();
+        let number = |prefix: &str| key.strip_prefix(prefix)?.parse::<u32>().ok();
+        if let Some(number) = number("file") {
+            files.insert(number, value);
+        } else if let Some(number) = number("title") {
+            info.entry(number).or_insert((None, None)).1 = Some(value);
+        } else if let Some(number) = number("length") {
+            info.entry(number).or_insert((None, None)).0 = Some(value);
+        }
+    }
+
+    let entries = files
+        .into_iter()
+        .filter(|(_, path)| !path.contains("://") || path.starts_with("file://"))
+        .map(|(number, path)| {
+            let path = path.strip_prefix("file://").unwrap_or(&path);
+            let mut entry = PlaylistEntry::new(PathBuf::from(path));
+            if let Some((length, Some(title))) = info.remove(&number) {
+                entry.info = Some(format!(
+                    "{},{}",
+                    length.unwrap_or_else(|| "-1".to_string()),
+                    title
+                ));
+            }
+            entry
+        })
+        .collect();
+    PlaylistFile { entries }
+}
+
+/// The lowercase extension of a playlist file, if it is one
+fn playlist_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase)
+        .filter(|extension| PLAYLIST_EXTENSIONS.contains(&extension.as_str()))
+}
+
+/// Checks if a file is a playlist file, going by its extension
+pub fn is_playlist_file(path: &Path) -> bool {
+    path.is_file() && playlist_extension(path).is_some()
+}
+
+/// Gathers the songs of a playlist, which is either a directory with a folder for every song or
+/// a playlist file
+/// Songs that cannot be read are skipped.
+///
+/// library: The library that the tags of the songs are read through
+/// path: The playlist directory or file
+/// skipped: Where a message is added for every song that could not be read
+pub fn load_playlist(
+    library: &mut MediaLibrary,
+    path: &Path,
+    skipped: &mut Vec<String>,
+) -> Result<Vec<Song>, String> {
+    if path.is_dir() {
+        return Ok(library.scan(path, skipped));
+    }
+    let mut playlist = PlaylistFile::read(path)?;
+    playlist.resolve(path.parent().unwrap_or(Path::new("")));
+
+    let mut songs = Vec::new();
+    for entry in &playlist.entries {
+        match library.song(&entry.path) {
+            Ok(mut song) => {
+                entry.apply(&mut song);
+                songs.push(song);
+            }
+            Err(err) => skipped.push(format!("{}: {}", entry.path.display(), err)),
+        }
+    }
+    Ok(songs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A playlist with one entry using every override and one using none
+    fn playlist() -> PlaylistFile {
+        PlaylistFile {
+            entries: vec![
+                PlaylistEntry {
+                    path: PathBuf::from("Christmas/Song/Song.mp3"),
+                    start: 12.5,
+                    volume: 0.8,
+                    light_file: Some(PathBuf::from("shows/Song.calm.json")),
+                    info: Some("180,Artist - Song".to_string()),
+                },
+                PlaylistEntry::new(PathBuf::from("/music/Other.flac")),
+            ],
+        }
+    }
+
+    #[test]
+    fn m3u_round_trip() {
+        let playlist = playlist();
+        assert_eq!(parse_m3u(&playlist.to_m3u()), playlist);
+    }
+
+    #[test]
+    fn m3u_skips_streams_and_unknown_directives() {
+        let playlist = parse_m3u(
+            "\u{feff}#EXTM3U\n\
+             #EXTINF:-1,Radio\n\
+             http://example.com/stream\n\
+             #EXTOPENLIGHTS:brightness=2\n\
+             \n\
+             file:///music/Song.mp3\n",
+        );
+        assert_eq!(
+            playlist.entries,
+            vec![PlaylistEntry::new(PathBuf::from("/music/Song.mp3"))]
+        );
+    }
+
+    #[test]
+    fn pls_round_trip_keeps_paths_and_titles() {
+        let mut playlist = playlist();
+        let parsed = parse_pls(&playlist.to_pls());
+        // PLS has no place for the overrides
+        playlist.entries[0].start = 0.0;
+        playlist.entries[0].volume = 1.0;
+        playlist.entries[0].light_file = None;
+        assert_eq!(parsed, playlist);
+    }
+
+    #[test]
+    fn pls_is_ordered_by_number_and_skips_streams() {
+        let playlist = parse_pls(
+            "[playlist]\n\
+             File2=/music/Second.mp3\n\
+             Title2=Second\n\
+             file1=/music/First.mp3\n\
+             File3=https://example.com/stream\n\
+             NumberOfEntries=3\n",
+        );
+        let paths: Vec<&Path> = playlist
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_path())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("/music/First.mp3"),
+                Path::new("/music/Second.mp3")
+            ]
+        );
+        assert_eq!(playlist.entries[1].info.as_deref(), Some("-1,Second"));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let folder = std::env::temp_dir().join("open_lights_playlist_json");
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("Mix.JSON");
+
+        let mut playlist = playlist();
+        playlist.write(&path).unwrap();
+        // The M3U info is not stored in JSON
+        playlist.entries[0].info = None;
+        assert_eq!(PlaylistFile::read(&path).unwrap(), playlist);
+        assert!(is_playlist_file(&path));
+        assert!(PlaylistFile::read(&folder.join("Mix.txt")).is_err());
+    }
+
+    #[test]
+    fn resolve_keeps_absolute_paths() {
+        let mut playlist = playlist();
+        playlist.resolve(Path::new("/playlists"));
+        assert_eq!(
+            playlist.entries[0].path,
+            PathBuf::from("/playlists/Christmas/Song/Song.mp3")
+        );
+        assert_eq!(
+            playlist.entries[0].light_file,
+            Some(PathBuf::from("/playlists/shows/Song.calm.json"))
+        );
+        assert_eq!(playlist.entries[1].path, PathBuf::from("/music/Other.flac"));
+    }
+
+    #[test]
+    fn remove_picks_the_occurrence() {
+        let mut playlist = playlist();
+        let mut repeat = PlaylistEntry::new(PathBuf::from("Christmas/Song/Song.mp3"));
+        repeat.volume = 0.5;
+        playlist.entries.push(repeat);
+
+        let directory = Path::new("/playlists");
+        let song = Path::new("/playlists/Christmas/Song/Song.mp3");
+        playlist.remove(directory, song, 1);
+        assert_eq!(playlist.entries.len(), 2);
+        assert_eq!(playlist.entries[0].volume, 0.8);
+
+        playlist.remove(directory, song, 1);
+        assert_eq!(playlist.entries.len(), 2);
+        playlist.remove(directory, song, 0);
+        assert_eq!(playlist.entries.len(), 1);
+    }
+
+    #[test]
+    fn apply_sets_the_overrides_of_a_song() {
+        let mut song = Song {
+            duration: 10.0,
+            light_files: vec![PathBuf::from("Song.json")],
+            ..Default::default()
+        };
+        let mut entry = PlaylistEntry::new(PathBuf::from("Song.mp3"));
+        entry.start = 12.5;
+        entry.volume = -1.0;
+        entry.light_file = Some(PathBuf::from("shows/Song.calm.json"));
+        entry.apply(&mut song);
+        assert_eq!(song.start, 10.0);
+        assert_eq!(song.volume, 0.0);
+        assert_eq!(song.light_files.len(), 2);
+        assert_eq!(song.light_track, 0);
+        assert_eq!(song.light_files[0], PathBuf::from("shows/Song.calm.json"));
+
+        entry.light_file = Some(PathBuf::from("Song.json"));
+        entry.apply(&mut song);
+        assert_eq!(song.light_track, 1);
+    }
+}
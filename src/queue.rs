@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+
+/// The most songs the play history remembers
+const MAX_HISTORY: usize = 100;
+
+/// The songs queued to play next and the songs played before, as indices of the song list
+///
+/// up_next: The songs queued to play before the rest of the play order, in order
+/// history: The songs played before the current one, the latest last
+#[derive(Default)]
+pub struct PlayQueue {
+    up_next: VecDeque<usize>,
+    history: Vec<usize>,
+}
+
+impl PlayQueue {
+    /// The song that plays after the current one, if any are queued
+    pub fn front(&self) -> Option<usize> {
+        self.up_next.front().copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.up_next.is_empty()
+    }
+
+    pub fn up_next(&self) -> Vec<usize> {
+        self.up_next.iter().copied().collect()
+    }
+
+    pub fn history(&self) -> &[usize] {
+        &self.history
+    }
+
+    /// Adds a song to the queue
+    ///
+    /// index: The index of the song in the song list
+    /// next: Whether the song plays right after the current one, instead of after the queue
+    pub fn enqueue(&mut self, index: usize, next: bool) {
+        if next {
+            self.up_next.push_front(index);
+        } else {
+            self.up_next.push_back(index);
+        }
+    }
+
+    /// Takes a song off the queue
+    ///
+    /// position: The position of the song in the queue
+    pub fn dequeue(&mut self, position: usize) -> Result<(), String> {
+        self.up_next
+            .remove(position)
+            .map(|_| ())
+            .ok_or_else(|| format!("There is no song {} in the queue", position + 1))
+    }
+
+    /// Moves a song to another position of the queue
+    ///
+    /// from: The position of the song in the queue
+    /// to: The position the song is moved to
+    pub fn move_in_queue(&mut self, from: usize, to: usize) -> Result<(), String> {
+        if from >= self.up_next.len() || to >= self.up_next.len() {
+            return Err(format!(
+                "There is no song {} in the queue",
+                from.max(to) + 1
+            ));
+        }
+        if let Some(index) = self.up_next.remove(from) {
+            self.up_next.insert(to, index);
+        }
+        Ok(())
+    }
+
+    /// Takes the front of the queue off once it starts playing
+    pub fn pop_front(&mut self) {
+        self.up_next.pop_front();
+    }
+
+    /// Remembers a song that was replaced by another, forgetting the oldest song when full
+    ///
+    /// index: The index of the song in the song list
+    pub fn remember(&mut self, index: usize) {
+        self.history.push(index);
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+    }
+
+    /// Takes the song played before the current one off the history
+    pub fn previous(&mut self) -> Option<usize> {
+        self.history.pop()
+    }
+
+    pub fn clear(&mut self) {
+        self.up_next.clear();
+        self.history.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn play_next_goes_before_the_queue() {
+        let mut queue = PlayQueue::default();
+        queue.enqueue(3, false);
+        queue.enqueue(5, false);
+        queue.enqueue(7, true);
+        assert_eq!(queue.up_next(), vec![7, 3, 5]);
+        assert_eq!(queue.front(), Some(7));
+
+        queue.pop_front();
+        assert_eq!(queue.up_next(), vec![3, 5]);
+    }
+
+    #[test]
+    fn dequeue_and_move() {
+        let mut queue = PlayQueue::default();
+        for index in [1, 2, 3, 4] {
+            queue.enqueue(index, false);
+        }
+        queue.move_in_queue(0, 3).unwrap();
+        assert_eq!(queue.up_next(), vec![2, 3, 4, 1]);
+        queue.move_in_queue(2, 0).unwrap();
+        assert_eq!(queue.up_next(), vec![4, 2, 3, 1]);
+        queue.dequeue(1).unwrap();
+        assert_eq!(queue.up_next(), vec![4, 3, 1]);
+
+        assert_eq!(
+            queue.move_in_queue(0, 3).unwrap_err(),
+            "There is no song 4 in the queue"
+        );
+        assert_eq!(
+            queue.dequeue(5).unwrap_err(),
+            "There is no song 6 in the queue"
+        );
+        assert_eq!(queue.up_next(), vec![4, 3, 1]);
+    }
+
+    #[test]
+    fn history_goes_back_from_the_latest() {
+        let mut queue = PlayQueue::default();
+        queue.remember(0);
+        queue.remember(4);
+        assert_eq!(queue.history(), &[0, 4]);
+        assert_eq!(queue.previous(), Some(4));
+        assert_eq!(queue.previous(), Some(0));
+        assert_eq!(queue.previous(), None);
+    }
+
+    #[test]
+    fn history_forgets_the_oldest_songs() {
+        let mut queue = PlayQueue::default();
+        for index in 0..MAX_HISTORY + 5 {
+            queue.remember(index);
+        }
+        assert_eq!(queue.history().len(), MAX_HISTORY);
+        assert_eq!(queue.history()[0], 5);
+        assert_eq!(queue.previous(), Some(MAX_HISTORY + 4));
+    }
+
+    #[test]
+    fn clear_empties_both() {
+        let mut queue = PlayQueue::default();
+        queue.enqueue(1, false);
+        queue.remember(2);
+        queue.clear();
+        assert!(queue.is_empty());
+        assert!(queue.history().is_empty());
+    }
+}
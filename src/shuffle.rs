@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use crate::audio_player::Song;
+
+/// How the songs of a playlist are ordered for playback
+/// The song list itself is never reordered, so turning shuffle off returns to the original order.
+///
+/// Off: The songs play in the order of the playlist
+/// Random: The songs play in a random order
+/// Smart: The songs play in a random order that avoids songs by the same artist back to back
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ShuffleMode {
+    #[default]
+    Off,
+    Random,
+    Smart,
+}
+
+impl ShuffleMode {
+    /// The mode after this one, for a button that cycles through them
+    pub fn next(self) -> Self {
+        match self {
+            ShuffleMode::Off => ShuffleMode::Random,
+            ShuffleMode::Random => ShuffleMode::Smart,
+            ShuffleMode::Smart => ShuffleMode::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShuffleMode::Off => "Shuffle Off",
+            ShuffleMode::Random => "Shuffle",
+            ShuffleMode::Smart => "Smart Shuffle",
+        }
+    }
+}
+
+/// The order the songs of the song list play in, as indices of the song list
+/// Each round of a shuffled order plays every song once. A new round is shuffled when the last
+/// one ends, starting with the song that ended it, so no song plays twice in a row.
+///
+/// mode: How the songs are ordered
+/// order: The indices of the songs in the order they play
+#[derive(Default)]
+pub struct PlayOrder {
+    mode: ShuffleMode,
+    order: Vec<usize>,
+}
+
+impl PlayOrder {
+    pub fn mode(&self) -> ShuffleMode {
+        self.mode
+    }
+
+    /// Orders a newly loaded song list from its start
+    /// Returns the index of the song to play first.
+    pub fn reset(&mut self, songs: &[Song]) -> usize {
+        self.order = (0..songs.len()).collect();
+        if self.mode != ShuffleMode::Off {
+            self.order = shuffle(self.order.clone(), songs, self.mode, None);
+        }
+        self.order.first().copied().unwrap_or(0)
+    }
+
+    /// Switches to another mode partway through the song list
+    /// A shuffled order starts with the current song, so the songs after it are shuffled.
+    ///
+    /// current: The index of the song playing
+    pub fn set_mode(&mut self, mode: ShuffleMode, songs: &[Song], current: usize) {
+        self.mode = mode;
+        self.order = (0..songs.len()).collect();
+        if mode != ShuffleMode::Off {
+            self.start_round(songs, current);
+        }
+    }
+
+    /// The index of the song after a song
+    /// When the round ends a new one is shuffled, so asking again gives the same song.
+    ///
+    /// current: The index of the song playing
+    pub fn next(&mut self, songs: &[Song], current: usize) -> usize {
+        if songs.is_empty() {
+            return 0;
+        }
+        if self.order.len() != songs.len() {
+            self.set_mode(self.mode, songs, current);
+        }
+        let position = self.order.iter().position(|&index| index == current);
+        match position {
+            Some(position) if position + 1 < self.order.len() => self.order[position + 1],
+            _ if self.mode == ShuffleMode::Off => self.order[0],
+            _ => {
+                self.start_round(songs, current);
+                self.order.get(1).copied().unwrap_or(current)
+            }
+        }
+    }
+
+    /// Shuffles a new round that starts with a song, which counts as already played
+    fn start_round(&mut self, songs: &[Song], current: usize) {
+        let rest = (0..songs.len()).filter(|&index| index != current).collect();
+        self.order = vec![current];
+        self.order
+            .extend(shuffle(rest, songs, self.mode, Some(current)));
+    }
+}
+
+/// Puts songs in a random order
+/// Smart shuffle never puts two songs by the same artist back to back, unless one artist has
+/// too many of the songs to keep them apart.
+///
+/// indices: The indices of the songs to order
+/// previous: The index of the song that plays before the first of these songs
+fn shuffle(
+    mut indices: Vec<usize>,
+    songs: &[Song],
+    mode: ShuffleMode,
+    previous: Option<usize>,
+) -> Vec<usize> {
+    fastrand::shuffle(&mut indices);
+    if mode != ShuffleMode::Smart {
+        return indices;
+    }
+
+    let artist = |index: usize| songs[index].artist.as_str();
+    let mut remaining: HashMap<&str, usize> = HashMap::new();
+    for &index in &indices {
+        *remaining.entry(artist(index)).or_default() += 1;
+    }
+
+    let mut order = Vec::with_capacity(indices.len());
+    let mut last_artist = previous.map(artist);
+    while !indices.is_empty() {
+        // An artist with more than half of the songs left has to come now to keep them apart
+        let crowded = remaining
+            .iter()
+            .find(|(name, count)| Some(**name) != last_artist && **count * 2 > indices.len())
+            .map(|(name, _)| *name);
+        let position = indices
+            .iter()
+            .position(|&index| match crowded {
+                Some(name) => artist(index) == name,
+                None => Some(artist(index)) != last_artist,
+            })
+            .unwrap_or(0);
+
+        let index = indices.remove(position);
+        let name = artist(index);
+        if let Some(count) = remaining.get_mut(name) {
+            *count -= 1;
+        }
+        last_artist = Some(name);
+        order.push(index);
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Songs by the given artists, in order
+    fn songs(artists: &[&str]) -> Vec<Song> {
+        artists
+            .iter()
+            .map(|artist| Song {
+                artist: artist.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// The songs played after a song, asking for the next song a number of times
+    fn play(order: &mut PlayOrder, songs: &[Song], mut current: usize, count: usize) -> Vec<usize> {
+        let mut played = Vec::new();
+        for _ in 0..count {
+            current = order.next(songs, current);
+            played.push(current);
+        }
+        played
+    }
+
+    #[test]
+    fn off_plays_in_order_and_wraps() {
+        let songs = songs(&["A", "B", "C"]);
+        let mut order = PlayOrder::default();
+        assert_eq!(order.reset(&songs), 0);
+        assert_eq!(play(&mut order, &songs, 0, 4), vec![1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn shuffle_plays_every_song_once_per_round_without_repeats() {
+        let songs = songs(&["A", "B", "C", "D", "E"]);
+        let mut order = PlayOrder::default();
+        order.set_mode(ShuffleMode::Random, &songs, 2);
+        for _ in 0..50 {
+            let mut played = vec![2];
+            played.extend(play(&mut order, &songs, 2, 48));
+            // Each round starts with the song that ended the round before
+            for start in (0..=played.len() - songs.len()).step_by(songs.len() - 1) {
+                let mut sorted = played[start..start + songs.len()].to_vec();
+                sorted.sort();
+                assert_eq!(sorted, vec![0, 1, 2, 3, 4]);
+            }
+            assert!(played.windows(2).all(|pair| pair[0] != pair[1]));
+            order.set_mode(ShuffleMode::Random, &songs, 2);
+        }
+    }
+
+    #[test]
+    fn asking_again_at_the_end_of_a_round_gives_the_same_song() {
+        let songs = songs(&["A", "B", "C"]);
+        let mut order = PlayOrder::default();
+        order.set_mode(ShuffleMode::Random, &songs, 0);
+        let last = *play(&mut order, &songs, 0, 2).last().unwrap();
+        let next = order.next(&songs, last);
+        assert_ne!(next, last);
+        assert_eq!(order.next(&songs, last), next);
+    }
+
+    #[test]
+    fn smart_shuffle_keeps_artists_apart() {
+        let songs = songs(&["A", "A", "B", "B", "C", "C"]);
+        let mut order = PlayOrder::default();
+        order.set_mode(ShuffleMode::Smart, &songs, 0);
+        for _ in 0..50 {
+            let played = play(&mut order, &songs, 0, 60);
+            assert!(played
+                .windows(2)
+                .all(|pair| songs[pair[0]].artist != songs[pair[1]].artist));
+            assert_ne!(songs[played[0]].artist, "A");
+            order.set_mode(ShuffleMode::Smart, &songs, 0);
+        }
+    }
+
+    #[test]
+    fn smart_shuffle_with_one_crowded_artist() {
+        let songs = songs(&["A", "B", "A", "A", "A"]);
+        for _ in 0..50 {
+            let order = shuffle(vec![0, 1, 2, 3, 4], &songs, ShuffleMode::Smart, None);
+            let mut sorted = order.clone();
+            sorted.sort();
+            assert_eq!(sorted, vec![0, 1, 2, 3, 4]);
+            // Only one song can keep the songs by A apart
+            let together = order
+                .windows(2)
+                .filter(|pair| songs[pair[0]].artist == songs[pair[1]].artist)
+                .count();
+            assert_eq!(together, 2);
+        }
+    }
+
+    #[test]
+    fn turning_shuffle_off_returns_to_the_playlist_order() {
+        let songs = songs(&["A", "B", "C", "D"]);
+        let mut order = PlayOrder::default();
+        order.set_mode(ShuffleMode::Smart, &songs, 1);
+        assert_eq!(order.mode(), ShuffleMode::Smart);
+        order.set_mode(ShuffleMode::Off, &songs, 1);
+        assert_eq!(play(&mut order, &songs, 1, 3), vec![2, 3, 0]);
+    }
+
+    #[test]
+    fn empty_song_list() {
+        let mut order = PlayOrder::default();
+        order.set_mode(ShuffleMode::Random, &[], 0);
+        assert_eq!(order.reset(&[]), 0);
+        assert_eq!(order.next(&[], 0), 0);
+    }
+
+    #[test]
+    fn modes_cycle() {
+        let mode = ShuffleMode::default();
+        assert_eq!(mode, ShuffleMode::Off);
+        assert_eq!(mode.next().next().next(), ShuffleMode::Off);
+    }
+}
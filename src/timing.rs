@@ -0,0 +1,301 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::constants::TIMING_DIRECTORY;
+
+/// The most reports kept in memory, older reports are discarded first
+const MAX_REPORTS: usize = 10;
+
+/// The upper bounds of the lateness histogram buckets in milliseconds, with their labels
+/// The last bucket holds everything later than the previous bound.
+pub const HISTOGRAM_BUCKETS: [(f64, &str); 8] = [
+    (0.0, "early"),
+    (1.0, "0-1 ms"),
+    (2.0, "1-2 ms"),
+    (5.0, "2-5 ms"),
+    (10.0, "5-10 ms"),
+    (20.0, "10-20 ms"),
+    (50.0, "20-50 ms"),
+    (f64::INFINITY, "50+ ms"),
+];
+
+/// When an event was meant to fire compared to when it reached the output
+///
+/// channels: The channels the event was sent to
+/// intended: The position in the audio the event was due at in milliseconds, including latency
+/// actual: The position in the audio the output was flushed at in milliseconds
+#[derive(Serialize, Clone, Debug)]
+pub struct EventTiming {
+    pub channels: Vec<i32>,
+    pub intended: i32,
+    pub actual: f64,
+}
+
+impl EventTiming {
+    /// How late the event was in milliseconds, negative if it was early
+    pub fn lateness(&self) -> f64 {
+        self.actual - self.intended as f64
+    }
+}
+
+/// The timing of every event of one playback of a song
+///
+/// song: The name of the song
+/// started: When playback started, in seconds since the Unix epoch
+/// events: The timing of every event that reached the output
+/// dropped: The amount of events that were overtaken by a later event before reaching the output
+#[derive(Serialize, Clone, Debug)]
+pub struct TimingReport {
+    pub song: String,
+    pub started: u64,
+    pub events: Vec<EventTiming>,
+    pub dropped: usize,
+}
+
+/// The statistics of a timing report
+///
+/// events: The amount of events that reached the output
+/// dropped: The amount of events that never reached the output
+/// mean_lateness: The average lateness in milliseconds
+/// max_lateness: The largest lateness in milliseconds
+/// histogram: The amount of events in each of the `HISTOGRAM_BUCKETS`
+#[derive(Serialize, Clone, Debug)]
+pub struct TimingSummary {
+    pub events: usize,
+    pub dropped: usize,
+    pub mean_lateness: f64,
+    pub max_lateness: f64,
+    pub histogram: Vec<usize>,
+}
+
+impl TimingReport {
+    fn new(song: String) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Self {
+            song,
+            started,
+            events: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Calculates the statistics of the report
+    pub fn summary(&self) -> TimingSummary {
+        let mut histogram = vec![0; HISTOGRAM_BUCKETS.len()];
+        let mut total = 0.0;
+        let mut max_lateness: f64 = 0.0;
+        for event in &self.events {
+            let lateness = event.lateness();
+            total += lateness;
+            max_lateness = max_lateness.max(lateness);
+            let bucket = HISTOGRAM_BUCKETS
+                .iter()
+                .position(|(bound, _)| lateness < *bound)
+                .unwrap_or(HISTOGRAM_BUCKETS.len() - 1);
+            histogram[bucket] += 1;
+        }
+
+        TimingSummary {
+            events: self.events.len(),
+            dropped: self.dropped,
+            mean_lateness: if self.events.is_empty() {
+                0.0
+            } else {
+                total / self.events.len() as f64
+            },
+            max_lateness,
+            histogram,
+        }
+    }
+
+    /// The report as CSV, with one row per event
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("channels,intended_ms,actual_ms,lateness_ms\n");
+        for event in &self.events {
+            let channels: Vec<String> = event.channels.iter().map(i32::to_string).collect();
+            csv.push_str(&format!(
+                "{},{},{:.3},{:.3}\n",
+                channels.join(" "),
+                event.intended,
+                event.actual,
+                event.lateness()
+            ));
+        }
+        csv
+    }
+
+    /// The report and its summary as JSON
+    pub fn to_json(&self) -> Result<String, String> {
+        let value = serde_json::json!({
+            "summary": self.summary(),
+            "report": self,
+        });
+        serde_json::to_string_pretty(&value).map_err(|err| err.to_string())
+    }
+
+    /// Writes the report to the timing directory
+    /// Returns the path of the written file.
+    ///
+    /// json: Whether to write JSON instead of CSV
+    pub fn export(&self, json: bool) -> Result<PathBuf, String> {
+        let directory = Path::new(&*TIMING_DIRECTORY);
+        fs::create_dir_all(directory).map_err(|err| err.to_string())?;
+
+        let name: String = self
+            .song
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let (extension, contents) = if json {
+            ("json", self.to_json()?)
+        } else {
+            ("csv", self.to_csv())
+        };
+        let path = directory.join(format!("{}_{}.{}", name, self.started, extension));
+        fs::write(&path, contents).map_err(|err| err.to_string())?;
+        Ok(path)
+    }
+}
+
+/// Records the timing of the light thread for every song played
+///
+/// reports: The reports of the most recent songs, the last being the song playing now
+#[derive(Default)]
+pub struct TimingRecorder {
+    reports: Vec<TimingReport>,
+}
+
+/// A timing recorder shared between the light thread and the GUI
+pub type SharedTimingRecorder = Arc<Mutex<TimingRecorder>>;
+
+impl TimingRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a new report, called whenever a light thread starts
+    ///
+    /// song: The name of the song
+    pub fn start(&mut self, song: String) {
+        if self.reports.len() >= MAX_REPORTS {
+            self.reports.remove(0);
+        }
+        self.reports.push(TimingReport::new(song));
+    }
+
+    /// Records an event that reached the output
+    ///
+    /// channels: The channels the event was sent to
+    /// intended: The position in the audio the event was due at in milliseconds
+    /// actual: The position in the audio the output was flushed at
+    pub fn record(&mut self, channels: &[i32], intended: i32, actual: Duration) {
+        if let Some(report) = self.reports.last_mut() {
+            report.events.push(EventTiming {
+                channels: channels.to_vec(),
+                intended,
+                actual: actual.as_secs_f64() * 1000.0,
+            });
+        }
+    }
+
+    /// Records events that never reached the output
+    ///
+    /// count: The amount of dropped events
+    pub fn record_dropped(&mut self, count: usize) {
+        if let Some(report) = self.reports.last_mut() {
+            report.dropped += count;
+        }
+    }
+
+    /// The report of the song playing now, or of the last song played
+    pub fn latest(&self) -> Option<&TimingReport> {
+        self.reports.last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A recorder with one report of events due at 1000 ms with the given lateness in milliseconds
+    fn recorder_with_lateness(lateness: &[f64]) -> TimingRecorder {
+        let mut recorder = TimingRecorder::new();
+        recorder.start("Song".to_string());
+        for late in lateness {
+            recorder.record(
+                &[0, 1],
+                1000,
+                Duration::from_secs_f64((1000.0 + late) / 1000.0),
+            );
+        }
+        recorder
+    }
+
+    #[test]
+    fn summary_of_an_empty_report() {
+        let recorder = recorder_with_lateness(&[]);
+        let summary = recorder.latest().unwrap().summary();
+        assert_eq!(summary.events, 0);
+        assert_eq!(summary.mean_lateness, 0.0);
+        assert_eq!(summary.max_lateness, 0.0);
+        assert_eq!(summary.histogram, vec![0; HISTOGRAM_BUCKETS.len()]);
+    }
+
+    #[test]
+    fn summary_statistics() {
+        let mut recorder = recorder_with_lateness(&[-2.0, 0.5, 1.5, 3.0, 7.0, 15.0, 30.0, 120.0]);
+        recorder.record_dropped(2);
+        recorder.record_dropped(1);
+        let summary = recorder.latest().unwrap().summary();
+        assert_eq!(summary.events, 8);
+        assert_eq!(summary.dropped, 3);
+        assert!((summary.mean_lateness - 175.0 / 8.0).abs() < 1e-6);
+        assert!((summary.max_lateness - 120.0).abs() < 1e-6);
+        assert_eq!(summary.histogram, vec![1; HISTOGRAM_BUCKETS.len()]);
+    }
+
+    #[test]
+    fn events_on_time_are_not_early() {
+        let recorder = recorder_with_lateness(&[0.0]);
+        let summary = recorder.latest().unwrap().summary();
+        assert_eq!(summary.histogram, vec![0, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn events_before_a_report_are_ignored() {
+        let mut recorder = TimingRecorder::new();
+        recorder.record(&[0], 0, Duration::ZERO);
+        recorder.record_dropped(1);
+        assert!(recorder.latest().is_none());
+    }
+
+    #[test]
+    fn oldest_reports_are_discarded() {
+        let mut recorder = TimingRecorder::new();
+        for song in 0..MAX_REPORTS + 2 {
+            recorder.start(song.to_string());
+        }
+        assert_eq!(recorder.reports.len(), MAX_REPORTS);
+        assert_eq!(recorder.reports[0].song, "2");
+        assert_eq!(
+            recorder.latest().unwrap().song,
+            (MAX_REPORTS + 1).to_string()
+        );
+    }
+
+    #[test]
+    fn csv_has_a_row_per_event() {
+        let recorder = recorder_with_lateness(&[2.5]);
+        assert_eq!(
+            recorder.latest().unwrap().to_csv(),
+            "channels,intended_ms,actual_ms,lateness_ms\n0 1,1000,1002.500,2.500\n"
+        );
+    }
+}
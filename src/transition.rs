@@ -0,0 +1,432 @@
+use std::f32::consts::FRAC_PI_2;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::source::{SeekError, UniformSourceIterator};
+use rodio::Source;
+
+use crate::audio_clock::{CountingSource, SharedAudioClock};
+
+/// The channel count every track is converted to, so any two tracks can be mixed
+const OUTPUT_CHANNELS: u16 = 2;
+
+/// The sample rate every track is converted to, so any two tracks can be mixed
+const OUTPUT_SAMPLE_RATE: u32 = 44100;
+
+/// How many samples are played between two checks for a queued track
+/// This must be a multiple of `OUTPUT_CHANNELS` so crossfades start on a whole frame.
+const CHECK_INTERVAL: u64 = 1024;
+
+type TrackSource = UniformSourceIterator<CountingSource<Box<dyn Source<Item = i16> + Send>>, f32>;
+
+/// A song prepared for playback
+///
+/// source: The audio of the song, converted to the output format
+/// duration: The length of the song
+/// remaining: The amount of samples left until the end of the song, going by its length
+/// crossfade: How long the song fades in over the end of the song before it
+/// gain: The factor every sample is multiplied with to bring the song to the target loudness
+pub struct Track {
+    source: TrackSource,
+    duration: Duration,
+    remaining: u64,
+    crossfade: Duration,
+    gain: f32,
+}
+
+impl Track {
+    /// Prepares the audio of a song for playback
+    ///
+    /// source: The decoded audio
+    /// clock: The clock that counts the samples of this song, which the light thread follows
+    /// duration: The length of the song
+    /// crossfade: How long the song fades in over the end of the song before it
+    /// gain: The factor every sample is multiplied with, 1.0 to play the song as it is
+    pub fn new(
+        source: Box<dyn Source<Item = i16> + Send>,
+        clock: SharedAudioClock,
+        duration: Duration,
+        crossfade: Duration,
+        gain: f32,
+    ) -> Self {
+        let source = UniformSourceIterator::new(
+            CountingSource::new(source, clock),
+            OUTPUT_CHANNELS,
+            OUTPUT_SAMPLE_RATE,
+        );
+        Self {
+            source,
+            duration,
+            remaining: samples_in(duration),
+            crossfade,
+            gain,
+        }
+    }
+
+    /// Jumps to a position in the song
+    pub fn seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(position)?;
+        self.remaining = samples_in(self.duration.saturating_sub(position));
+        Ok(())
+    }
+
+    fn next_sample(&mut self) -> Option<f32> {
+        let sample = self.source.next()?;
+        self.remaining = self.remaining.saturating_sub(1);
+        Some(sample * self.gain)
+    }
+}
+
+/// The amount of output samples in a duration, across all channels
+fn samples_in(duration: Duration) -> u64 {
+    let frames = (duration.as_secs_f64() * OUTPUT_SAMPLE_RATE as f64) as u64;
+    frames * OUTPUT_CHANNELS as u64
+}
+
+/// The track to play after the current one, shared between the audio thread and the
+/// `TransitionSource`
+///
+/// next: The track waiting to start
+/// handed_over: Set when the next track becomes the current track
+#[derive(Default)]
+pub struct TrackQueue {
+    next: Mutex<Option<Track>>,
+    handed_over: AtomicBool,
+}
+
+impl TrackQueue {
+    /// Queues the track to play after the current one, replacing any queued track
+    pub fn queue(&self, track: Track) {
+        *self.next.lock().unwrap() = Some(track);
+    }
+
+    /// Removes the queued track if it has not started yet
+    /// Returns whether a track was removed.
+    pub fn cancel(&self) -> bool {
+        self.next.lock().unwrap().take().is_some()
+    }
+
+    /// Checks if the next track became the current track since the last check
+    pub fn take_handover(&self) -> bool {
+        self.handed_over.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// A track fading in over the end of the current track
+///
+/// track: The incoming track
+/// length: The amount of samples the crossfade lasts
+/// mixed: The amount of samples mixed so far
+struct Incoming {
+    track: Track,
+    length: u64,
+    mixed: u64,
+}
+
+impl Incoming {
+    /// Whether the crossfade is halfway through, when the incoming track takes over
+    fn handed_over(&self) -> bool {
+        self.mixed >= (self.length / 2).max(1)
+    }
+}
+
+/// Plays tracks one after another, crossfading between them or joining them without a gap
+/// The next track is handed over halfway through a crossfade, or on the first sample after the
+/// current track ends when there is no crossfade. The light show follows the handover, so each
+/// show runs while its song is the louder one.
+///
+/// current: The track playing
+/// incoming: The track fading in while a crossfade runs
+/// queue: Where the next track comes from
+pub struct TransitionSource {
+    current: Track,
+    incoming: Option<Incoming>,
+    queue: Arc<TrackQueue>,
+}
+
+impl TransitionSource {
+    pub fn new(track: Track, queue: Arc<TrackQueue>) -> Self {
+        Self {
+            current: track,
+            incoming: None,
+            queue,
+        }
+    }
+
+    /// Starts fading in the queued track once the current track is within the crossfade of its end
+    fn start_crossfade(&mut self) {
+        // The audio output must never wait for the audio thread
+        let Ok(mut next) = self.queue.next.try_lock() else {
+            return;
+        };
+        let Some(crossfade) = next.as_ref().map(|track| samples_in(track.crossfade)) else {
+            return;
+        };
+        // A track queued late fades in over what is left of the current track
+        let length = crossfade.min(self.current.remaining);
+        if crossfade == 0 || self.current.remaining > crossfade || length == 0 {
+            return;
+        }
+        if let Some(track) = next.take() {
+            self.incoming = Some(Incoming {
+                track,
+                length,
+                mixed: 0,
+            });
+        }
+    }
+}
+
+impl Iterator for TransitionSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.incoming.is_none() && self.current.remaining % CHECK_INTERVAL == 0 {
+            self.start_crossfade();
+        }
+
+        let Some(incoming) = &mut self.incoming else {
+            if let Some(sample) = self.current.next_sample() {
+                return Some(sample);
+            }
+            // Without a crossfade the next track starts right after the current track ends
+            let track = self.queue.next.lock().unwrap().take()?;
+            self.current = track;
+            self.queue.handed_over.store(true, Ordering::Relaxed);
+            return self.current.next_sample();
+        };
+
+        // An equal power crossfade keeps the loudness steady halfway through
+        let progress = incoming.mixed as f32 / incoming.length as f32 * FRAC_PI_2;
+        let outgoing = self.current.next_sample().unwrap_or(0.0) * progress.cos();
+        let incoming_sample = incoming.track.next_sample().unwrap_or(0.0) * progress.sin();
+        let was_handed_over = incoming.handed_over();
+        incoming.mixed += 1;
+        if !was_handed_over && incoming.handed_over() {
+            self.queue.handed_over.store(true, Ordering::Relaxed);
+        }
+        if incoming.mixed >= incoming.length {
+            if let Some(incoming) = self.incoming.take() {
+                self.current = incoming.track;
+            }
+        }
+        Some(outgoing + incoming_sample)
+    }
+}
+
+impl Source for TransitionSource {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        OUTPUT_CHANNELS
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        OUTPUT_SAMPLE_RATE
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Seeks in the current track, ending any crossfade
+    /// A track that was already handed over is the one being seeked, otherwise the incoming
+    /// track is dropped and has to be queued again.
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        if let Some(incoming) = self.incoming.take() {
+            if incoming.handed_over() {
+                self.current = incoming.track;
+            }
+        }
+        self.current.seek(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_clock::AudioClock;
+    use rodio::buffer::SamplesBuffer;
+    use std::f32::consts::FRAC_PI_4;
+
+    /// Half of full scale, which is exact as a float
+    const HALF: i16 = 16384;
+
+    /// The sample the crossfade set up by `crossfade` starts at
+    const CROSSFADE_START: usize = 3072 * 2;
+
+    /// The amount of samples the crossfade set up by `crossfade` lasts
+    const CROSSFADE_LENGTH: usize = 1024 * 2;
+
+    /// The length of an amount of frames at the output sample rate, rounded up so converting it
+    /// back to samples gives every frame
+    fn frames(count: u64) -> Duration {
+        Duration::from_nanos((count * 1_000_000_000).div_ceil(OUTPUT_SAMPLE_RATE as u64))
+    }
+
+    /// A track in the output format
+    ///
+    /// samples: The samples of both channels, interleaved
+    /// crossfade: How many frames the track fades in over the track before it
+    fn track(samples: Vec<i16>, crossfade: u64) -> Track {
+        let length = samples.len() as u64 / OUTPUT_CHANNELS as u64;
+        let source = SamplesBuffer::new(OUTPUT_CHANNELS, OUTPUT_SAMPLE_RATE, samples);
+        Track::new(
+            Box::new(source),
+            Arc::new(AudioClock::new()),
+            frames(length),
+            frames(crossfade),
+            1.0,
+        )
+    }
+
+    fn constant(frames: usize, value: i16) -> Vec<i16> {
+        vec![value; frames * OUTPUT_CHANNELS as usize]
+    }
+
+    /// Plays a track of 4096 frames followed by one that fades in over its last 1024 frames
+    /// Returns the source and the queue, with the second track queued.
+    fn crossfade(outgoing: i16, incoming: i16) -> (TransitionSource, Arc<TrackQueue>) {
+        let queue = Arc::new(TrackQueue::default());
+        let source = TransitionSource::new(track(constant(4096, outgoing), 0), Arc::clone(&queue));
+        queue.queue(track(constant(4096, incoming), 1024));
+        (source, queue)
+    }
+
+    #[test]
+    fn crossfade_has_equal_power() {
+        let (outgoing, _queue) = crossfade(HALF, 0);
+        let fade_out: Vec<f32> = outgoing.collect();
+        let (incoming, _queue) = crossfade(0, HALF);
+        let fade_in: Vec<f32> = incoming.collect();
+        assert_eq!(fade_out.len(), 7168 * 2);
+        assert_eq!(fade_in.len(), fade_out.len());
+
+        assert!(fade_out[..CROSSFADE_START]
+            .iter()
+            .all(|sample| *sample == 0.5));
+        assert!(fade_in[..CROSSFADE_START]
+            .iter()
+            .all(|sample| *sample == 0.0));
+
+        let start = CROSSFADE_START;
+        let middle = start + CROSSFADE_LENGTH / 2;
+        let end = start + CROSSFADE_LENGTH - 1;
+        assert_eq!(fade_out[start], 0.5);
+        assert_eq!(fade_in[start], 0.0);
+        assert!((fade_out[middle] - 0.5 * FRAC_PI_4.cos()).abs() < 1e-6);
+        assert!((fade_out[middle] - fade_in[middle]).abs() < 1e-6);
+        assert!(fade_out[end] < 0.001);
+        assert!(fade_in[end] > 0.499);
+
+        // The power of both tracks together stays the same throughout the crossfade
+        for index in start..start + CROSSFADE_LENGTH {
+            let power = fade_out[index].powi(2) + fade_in[index].powi(2);
+            assert!((power - 0.25).abs() < 1e-5, "{} at {}", power, index);
+        }
+        assert!(fade_out[start + CROSSFADE_LENGTH..]
+            .iter()
+            .all(|sample| *sample == 0.0));
+        assert!(fade_in[start + CROSSFADE_LENGTH..]
+            .iter()
+            .all(|sample| *sample == 0.5));
+    }
+
+    #[test]
+    fn handover_happens_once_halfway_through_the_crossfade() {
+        let (mut source, queue) = crossfade(HALF, HALF);
+        let mut handovers = Vec::new();
+        let mut index = 0;
+        while source.next().is_some() {
+            if queue.take_handover() {
+                handovers.push(index);
+            }
+            index += 1;
+        }
+        assert_eq!(handovers, vec![CROSSFADE_START + CROSSFADE_LENGTH / 2 - 1]);
+    }
+
+    #[test]
+    fn gapless_join_keeps_every_sample() {
+        let queue = Arc::new(TrackQueue::default());
+        let first: Vec<i16> = (1..=1000).collect();
+        let second: Vec<i16> = (1001..=2000).collect();
+        let source = TransitionSource::new(track(first, 0), Arc::clone(&queue));
+        queue.queue(track(second, 0));
+
+        let mut samples = Vec::new();
+        let mut handover = None;
+        for sample in source {
+            samples.push((sample * 32768.0).round() as i16);
+            if queue.take_handover() {
+                handover = Some(samples.len() - 1);
+            }
+        }
+        assert_eq!(samples, (1..=2000).collect::<Vec<i16>>());
+        assert_eq!(handover, Some(1000));
+    }
+
+    #[test]
+    fn cancel_before_the_crossfade_starts() {
+        let (mut source, queue) = crossfade(HALF, 0);
+        for _ in 0..100 {
+            source.next();
+        }
+        assert!(queue.cancel());
+        assert!(!queue.cancel());
+
+        let rest: Vec<f32> = source.by_ref().collect();
+        assert_eq!(rest.len(), 4096 * 2 - 100);
+        assert!(rest.iter().all(|sample| *sample == 0.5));
+        assert!(!queue.take_handover());
+    }
+
+    #[test]
+    fn cancel_after_the_crossfade_starts() {
+        let (mut source, queue) = crossfade(HALF, HALF);
+        for _ in 0..CROSSFADE_START + 1 {
+            source.next();
+        }
+        // The incoming track is already playing, so it cannot be taken back
+        assert!(!queue.cancel());
+        assert_eq!(source.count(), 7168 * 2 - CROSSFADE_START - 1);
+        assert!(queue.take_handover());
+    }
+
+    #[test]
+    fn seeking_before_the_handover_drops_the_incoming_track() {
+        let (mut source, queue) = crossfade(HALF, HALF / 2);
+        for _ in 0..CROSSFADE_START + 10 {
+            source.next();
+        }
+        source.try_seek(Duration::ZERO).unwrap();
+
+        let rest: Vec<f32> = source.by_ref().collect();
+        assert_eq!(rest.len(), 4096 * 2);
+        assert!(rest.iter().all(|sample| *sample == 0.5));
+        assert!(!queue.take_handover());
+        assert!(!queue.cancel());
+    }
+
+    #[test]
+    fn seeking_after_the_handover_seeks_the_incoming_track() {
+        let (mut source, queue) = crossfade(HALF, HALF / 2);
+        for _ in 0..CROSSFADE_START + CROSSFADE_LENGTH / 2 + 10 {
+            source.next();
+        }
+        assert!(queue.take_handover());
+        source.try_seek(frames(100)).unwrap();
+
+        let rest: Vec<f32> = source.by_ref().collect();
+        assert_eq!(rest.len(), (4096 - 100) * 2);
+        assert!(rest.iter().all(|sample| *sample == 0.25));
+        assert!(!queue.take_handover());
+    }
+}
@@ -0,0 +1,614 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::fixtures::{Color, FixtureConfig};
+use crate::library::{cached_song_hash, SharedLibrary};
+use crate::lights::{
+    parse_event, song_hash, EventValue, LightEvent, LightFile, LIGHT_FILE_VERSION,
+};
+
+/// How serious a problem in a light file is
+///
+/// Error: The show will not play as written
+/// Warning: The show plays, but probably not as intended
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a light file
+///
+/// severity: How serious the problem is
+/// location: Where in the light file the problem is
+/// message: A description of the problem
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.location, self.message)
+    }
+}
+
+/// What a light file is validated against
+///
+/// channel_count: The amount of channels the light output drives
+/// song_duration: The length of the song in milliseconds, if known
+/// song: The audio file of the song, which the song hash of the light file is checked against
+/// library: The library the song hash is cached in, or None to hash the song every time
+/// fixtures: The fixtures tracks may address
+pub struct ValidationContext<'a> {
+    pub channel_count: usize,
+    pub song_duration: Option<u32>,
+    pub song: Option<&'a Path>,
+    pub library: Option<&'a SharedLibrary>,
+    pub fixtures: &'a FixtureConfig,
+}
+
+/// A track as written in the light file, before any sorting
+///
+/// location: Where the track is in the light file
+/// channels: The channels the track is sent to
+/// fixture: The fixture the track is sent to
+/// events: The events of the track in file order, with their locations
+struct RawTrack {
+    location: String,
+    channels: Vec<i32>,
+    fixture: Option<String>,
+    events: Vec<(String, LightEvent)>,
+}
+
+/// A JSON object that keeps its entries in file order, including duplicate keys
+struct OrderedMap<V>(Vec<(String, V)>);
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for OrderedMap<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OrderedMapVisitor<V>(PhantomData<V>);
+
+        impl<'de, V: Deserialize<'de>> Visitor<'de> for OrderedMapVisitor<V> {
+            type Value = OrderedMap<V>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("an object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(OrderedMap(entries))
+            }
+        }
+
+        deserializer.deserialize_map(OrderedMapVisitor(PhantomData))
+    }
+}
+
+/// Checks a light file and reports every problem found
+///
+/// path: Path to the light file
+/// context: What the light file is validated against
+pub fn validate_light_file(path: &Path, context: &ValidationContext<'_>) -> Vec<Diagnostic> {
+    match fs::read_to_string(path) {
+        Ok(contents) => validate_light_json(&contents, context),
+        Err(err) => vec![error("file", format!("Failed to read the file: {}", err))],
+    }
+}
+
+/// Checks the contents of a light file and reports every problem found
+///
+/// contents: The JSON text of the light file
+/// context: What the light file is validated against
+pub fn validate_light_json(contents: &str, context: &ValidationContext<'_>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let value: serde_json::Value = match serde_json::from_str(contents) {
+        Ok(value) => value,
+        Err(err) => return vec![json_error(err)],
+    };
+
+    let (tracks, song_duration) = match value.get("format_version") {
+        None => (read_v1(contents, &mut diagnostics), context.song_duration),
+        Some(version) if version.as_u64() == Some(LIGHT_FILE_VERSION as u64) => {
+            let light_file: LightFile = match serde_json::from_str(contents) {
+                Ok(light_file) => light_file,
+                Err(err) => return vec![json_error(err)],
+            };
+            let song_duration = context.song_duration.or(light_file.metadata.duration);
+            if let (Some(expected), Some(song)) = (&light_file.metadata.song_hash, context.song) {
+                check_song_hash(expected, song, context.library, &mut diagnostics);
+            }
+            (read_v2(light_file), song_duration)
+        }
+        Some(version) => {
+            return vec![error(
+                "format_version",
+                format!("Unsupported light file version {}", version),
+            )]
+        }
+    };
+
+    check_tracks(&tracks, context, song_duration, &mut diagnostics);
+    diagnostics
+}
+
+/// Checks that a light file was made for the audio file it is played with
+///
+/// expected: The song hash stored in the light file
+/// song: The audio file of the song
+/// library: The library the song hash is cached in
+fn check_song_hash(
+    expected: &str,
+    song: &Path,
+    library: Option<&SharedLibrary>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let hash = match library {
+        Some(library) => cached_song_hash(library, song),
+        None => song_hash(song).map_err(|err| err.to_string()),
+    };
+    match hash {
+        Ok(hash) if hash == expected => {}
+        Ok(hash) => diagnostics.push(warning(
+            "metadata.song_hash",
+            format!(
+                "The light file was made for a different version of the song ({} instead of {})",
+                expected, hash
+            ),
+        )),
+        Err(err) => diagnostics.push(warning(
+            "metadata.song_hash",
+            format!("Failed to hash {}: {}", song.display(), err),
+        )),
+    }
+}
+
+/// Reads the tracks of a v1 light file, keeping the order and duplicates of its timestamps
+fn read_v1(contents: &str, diagnostics: &mut Vec<Diagnostic>) -> Vec<RawTrack> {
+    let data: OrderedMap<OrderedMap<serde_json::Value>> = match serde_json::from_str(contents) {
+        Ok(data) => data,
+        Err(err) => {
+            diagnostics.push(json_error(err));
+            return Vec::new();
+        }
+    };
+
+    let mut tracks = Vec::new();
+    for (key, entries) in data.0 {
+        let location = format!("track \"{}\"", key);
+        let mut track = RawTrack {
+            location: location.clone(),
+            channels: Vec::new(),
+            fixture: None,
+            events: Vec::new(),
+        };
+
+        if let Some(name) = key.strip_prefix('@') {
+            track.fixture = Some(name.trim().to_string());
+        } else {
+            for part in key.split(',') {
+                match part.trim().parse::<i32>() {
+                    Ok(channel) => track.channels.push(channel),
+                    Err(_) => diagnostics.push(error(
+                        &location,
+                        format!("Unknown channel \"{}\"", part.trim()),
+                    )),
+                }
+            }
+        }
+
+        for (timestamp_str, value) in entries.0 {
+            let event_location = format!("{}, timestamp \"{}\"", location, timestamp_str);
+            let Ok(time) = timestamp_str.trim().parse::<i32>() else {
+                diagnostics.push(error(
+                    &event_location,
+                    "The timestamp is not a whole number of milliseconds".to_string(),
+                ));
+                continue;
+            };
+            match serde_json::from_value::<EventValue>(value) {
+                Ok(value) => track
+                    .events
+                    .push((event_location, parse_event(time, value))),
+                Err(_) => diagnostics.push(error(
+                    &event_location,
+                    "The value must be an intensity from 0 to 255, a colour or an event object"
+                        .to_string(),
+                )),
+            }
+        }
+        tracks.push(track);
+    }
+    tracks
+}
+
+/// Reads the tracks of a v2 light file
+fn read_v2(light_file: LightFile) -> Vec<RawTrack> {
+    light_file
+        .tracks
+        .into_iter()
+        .enumerate()
+        .map(|(index, track)| {
+            let location = format!("tracks[{}]", index);
+            RawTrack {
+                events: track
+                    .events
+                    .into_iter()
+                    .enumerate()
+                    .map(|(event_index, event)| {
+                        (
+                            format!("{}.events[{}] ({} ms)", location, event_index, event.time),
+                            event,
+                        )
+                    })
+                    .collect(),
+                location,
+                channels: track.channels,
+                fixture: track.fixture,
+            }
+        })
+        .collect()
+}
+
+/// Runs every check that does not depend on the file version
+fn check_tracks(
+    tracks: &[RawTrack],
+    context: &ValidationContext<'_>,
+    song_duration: Option<u32>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    // The intensity every channel is set to at every timestamp, across all tracks
+    let mut channel_states: HashMap<(i32, i32), (Option<u8>, &str)> = HashMap::new();
+
+    for track in tracks {
+        match &track.fixture {
+            Some(name) => {
+                if context.fixtures.get(name).is_none() {
+                    diagnostics.push(error(
+                        &track.location,
+                        format!("Unknown fixture \"{}\"", name),
+                    ));
+                }
+            }
+            None => {
+                if track.channels.is_empty() {
+                    diagnostics.push(warning(
+                        &track.location,
+                        "The track is not sent to any channel".to_string(),
+                    ));
+                }
+                for channel in &track.channels {
+                    if *channel < 0 || *channel as usize >= context.channel_count {
+                        diagnostics.push(error(
+                            &track.location,
+                            format!(
+                                "Channel {} is outside of the {} available outputs (0-{})",
+                                channel,
+                                context.channel_count,
+                                context.channel_count.saturating_sub(1)
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        let mut previous: Option<i32> = None;
+        let mut seen: HashMap<i32, &LightEvent> = HashMap::new();
+        for (location, event) in &track.events {
+            if event.time < 0 {
+                diagnostics.push(error(location, "The timestamp is negative".to_string()));
+            }
+            if let Some(duration) = song_duration {
+                if event.time > duration as i32 {
+                    diagnostics.push(warning(
+                        location,
+                        format!("The event is after the end of the song ({} ms)", duration),
+                    ));
+                }
+            }
+            if let Some(previous) = previous {
+                if event.time < previous {
+                    diagnostics.push(warning(
+                        location,
+                        format!(
+                            "The event comes before the previous event at {} ms",
+                            previous
+                        ),
+                    ));
+                }
+            }
+            previous = Some(event.time);
+
+            if let Some(color) = &event.color {
+                if Color::parse(color).is_none() {
+                    diagnostics.push(error(location, format!("Invalid colour \"{}\"", color)));
+                }
+            }
+            if event.intensity.is_none()
+                && event.color.is_none()
+                && event.pan.is_none()
+                && event.tilt.is_none()
+            {
+                diagnostics.push(warning(
+                    location,
+                    "The event does not change anything".to_string(),
+                ));
+            }
+
+            match seen.get(&event.time) {
+                Some(earlier) if !same_state(earlier, event) => diagnostics.push(error(
+                    location,
+                    format!("Another event at {} ms sets a different state", event.time),
+                )),
+                Some(_) => diagnostics.push(warning(
+                    location,
+                    format!("The event repeats another event at {} ms", event.time),
+                )),
+                None => {
+                    seen.insert(event.time, event);
+                }
+            }
+
+            if track.fixture.is_none() {
+                for channel in &track.channels {
+                    match channel_states.get(&(*channel, event.time)) {
+                        Some((intensity, other))
+                            if *intensity != event.intensity && *other != track.location =>
+                        {
+                            diagnostics.push(error(
+                                location,
+                                format!(
+                                    "Channel {} is set to a different state at the same time by {}",
+                                    channel, other
+                                ),
+                            ))
+                        }
+                        Some(_) => {}
+                        None => {
+                            channel_states
+                                .insert((*channel, event.time), (event.intensity, &track.location));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether two events set the same state
+fn same_state(first: &LightEvent, second: &LightEvent) -> bool {
+    first.intensity == second.intensity
+        && first.color == second.color
+        && first.pan == second.pan
+        && first.tilt == second.tilt
+        && first.fade == second.fade
+}
+
+fn error(location: &str, message: String) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Error,
+        location: location.to_string(),
+        message,
+    }
+}
+
+fn warning(location: &str, message: String) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Warning,
+        location: location.to_string(),
+        message,
+    }
+}
+
+/// Converts a JSON error into a diagnostic with its line and column
+fn json_error(err: serde_json::Error) -> Diagnostic {
+    error(
+        &format!("line {}, column {}", err.line(), err.column()),
+        format!("Invalid JSON: {}", err),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Component, ComponentKind, Fixture};
+
+    fn fixtures() -> FixtureConfig {
+        FixtureConfig {
+            fixtures: vec![Fixture {
+                name: "flood".to_string(),
+                components: vec![Component {
+                    kind: ComponentKind::Red,
+                    channel: 10,
+                }],
+            }],
+        }
+    }
+
+    /// Validates a light file against 16 channels and a song of 10 seconds
+    fn validate(contents: &str) -> Vec<Diagnostic> {
+        let fixtures = fixtures();
+        let context = ValidationContext {
+            channel_count: 16,
+            song_duration: Some(10000),
+            song: None,
+            library: None,
+            fixtures: &fixtures,
+        };
+        validate_light_json(contents, &context)
+    }
+
+    /// Whether a diagnostic of a severity mentions a text
+    fn has(diagnostics: &[Diagnostic], severity: Severity, text: &str) -> bool {
+        diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == severity && diagnostic.message.contains(text))
+    }
+
+    #[test]
+    fn valid_files_have_no_diagnostics() {
+        assert!(
+            validate(r##"{ "0,1": { "0": 255, "500": 0 }, "@flood": { "0": "#ff0000" } }"##)
+                .is_empty()
+        );
+        assert!(validate(
+            r##"{
+                "format_version": 2,
+                "tracks": [
+                    { "channels": [2], "events": [{ "time": 0, "intensity": 10, "fade": 200 }] },
+                    { "fixture": "flood", "events": [{ "time": 100, "color": "hsv(30, 100, 100)" }] }
+                ]
+            }"##
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn invalid_json_reports_its_position() {
+        let diagnostics = validate("{\n  \"0\": {\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert!(diagnostics[0].location.starts_with("line "));
+    }
+
+    #[test]
+    fn unsupported_versions_are_errors() {
+        let diagnostics = validate(r#"{ "format_version": 3, "tracks": [] }"#);
+        assert!(has(
+            &diagnostics,
+            Severity::Error,
+            "Unsupported light file version 3"
+        ));
+    }
+
+    #[test]
+    fn channels_and_fixtures_are_checked() {
+        let diagnostics =
+            validate(r#"{ "0,16,x": { "0": 255 }, "@spot": { "0": 255 }, "": { "0": 255 } }"#);
+        assert!(has(&diagnostics, Severity::Error, "Channel 16 is outside"));
+        assert!(has(&diagnostics, Severity::Error, "Unknown channel \"x\""));
+        assert!(has(
+            &diagnostics,
+            Severity::Error,
+            "Unknown fixture \"spot\""
+        ));
+    }
+
+    #[test]
+    fn timestamps_are_checked() {
+        let diagnostics = validate(r#"{ "0": { "-5": 255, "abc": 0, "20000": 0 } }"#);
+        assert!(has(&diagnostics, Severity::Error, "negative"));
+        assert!(has(&diagnostics, Severity::Error, "not a whole number"));
+        assert!(has(
+            &diagnostics,
+            Severity::Warning,
+            "after the end of the song"
+        ));
+    }
+
+    #[test]
+    fn duplicate_and_conflicting_events() {
+        // v1 objects keep duplicate keys, which serde_json would otherwise drop
+        let diagnostics = validate(r#"{ "0": { "100": 255, "100": 255, "200": 255, "200": 0 } }"#);
+        assert!(has(
+            &diagnostics,
+            Severity::Warning,
+            "repeats another event"
+        ));
+        assert!(has(&diagnostics, Severity::Error, "sets a different state"));
+
+        let diagnostics = validate(r#"{ "0": { "100": 255 }, "0,1": { "100": 0 } }"#);
+        assert!(has(
+            &diagnostics,
+            Severity::Error,
+            "Channel 0 is set to a different state"
+        ));
+    }
+
+    #[test]
+    fn v2_checks() {
+        let diagnostics = validate(
+            r##"{
+                "format_version": 2,
+                "metadata": { "duration": 1000 },
+                "tracks": [
+                    { "channels": [], "events": [{ "time": 500 }] },
+                    { "channels": [0], "events": [
+                        { "time": 800, "intensity": 255 },
+                        { "time": 400, "color": "#12345" }
+                    ] }
+                ]
+            }"##,
+        );
+        assert!(has(
+            &diagnostics,
+            Severity::Warning,
+            "not sent to any channel"
+        ));
+        assert!(has(
+            &diagnostics,
+            Severity::Warning,
+            "does not change anything"
+        ));
+        assert!(has(
+            &diagnostics,
+            Severity::Warning,
+            "before the previous event"
+        ));
+        assert!(has(&diagnostics, Severity::Error, "Invalid colour"));
+        // The song duration of the context wins over the metadata
+        assert!(!has(&diagnostics, Severity::Warning, "after the end"));
+    }
+
+    #[test]
+    fn song_hash_mismatch_is_a_warning() {
+        let song = std::env::temp_dir().join("open_lights_song_hash.mp3");
+        fs::write(&song, b"audio").unwrap();
+        let fixtures = fixtures();
+        let context = ValidationContext {
+            channel_count: 16,
+            song_duration: None,
+            song: Some(&song),
+            library: None,
+            fixtures: &fixtures,
+        };
+        let light_file = |hash: &str| {
+            format!(
+                r#"{{ "format_version": 2, "metadata": {{ "song_hash": "{}" }}, "tracks": [] }}"#,
+                hash
+            )
+        };
+
+        let hash = song_hash(&song).unwrap();
+        assert!(validate_light_json(&light_file(&hash), &context).is_empty());
+        let diagnostics = validate_light_json(&light_file("fnv1a64:0000000000000000"), &context);
+        assert!(has(
+            &diagnostics,
+            Severity::Warning,
+            "different version of the song"
+        ));
+    }
+
+    #[test]
+    fn diagnostics_display_their_severity_and_location() {
+        let diagnostic = warning("tracks[0]", "Something".to_string());
+        assert_eq!(diagnostic.to_string(), "warning: tracks[0]: Something");
+    }
+}